@@ -0,0 +1,56 @@
+//! Flow id rate of the crate's primed-hasher kernel vs a fresh BLAKE3
+//! hasher per nonce.
+//!
+//! The primed variant is `calculate_flow_ids_batch`, which hashes `x` once
+//! and clones the hasher for every nonce; it runs the same kernel as
+//! `find_valid_nonce` and the other nonce searches. The fresh variant is the
+//! baseline without priming: `calculate_flow_id` truncated with
+//! `truncate_flow_id`. Both compute `H(x||r)|_B` over the same nonces and
+//! must agree. Throughput is reported in hashes, so the speedup is the ratio
+//! of the two rates.
+//!
+//! Usage: cargo bench --bench grind
+
+use collidervm_toy::core::{
+    calculate_flow_id, calculate_flow_ids_batch, truncate_flow_id,
+};
+use criterion::{
+    BenchmarkId, Criterion, Throughput, criterion_group, criterion_main,
+};
+use std::hint::black_box;
+
+/// Input value `x`
+const INPUT: u32 = 114;
+/// Hash prefix length `B`
+const B_BITS: usize = 16;
+/// Nonces hashed per iteration, about one grind at `B - L = 12`
+const NONCES: u64 = 1 << 12;
+
+fn flow_ids_fresh(input: u32, nonces: &[u64], b_bits: usize) -> Vec<u32> {
+    nonces
+        .iter()
+        .map(|&nonce| truncate_flow_id(calculate_flow_id(input, nonce), b_bits))
+        .collect()
+}
+
+fn bench_grind(c: &mut Criterion) {
+    let nonces = (0..NONCES).collect::<Vec<_>>();
+    assert_eq!(
+        flow_ids_fresh(INPUT, &nonces, B_BITS),
+        calculate_flow_ids_batch(INPUT, &nonces, B_BITS)
+    );
+
+    let mut group = c.benchmark_group("grind");
+    group.throughput(Throughput::Elements(NONCES));
+    let params = format!("B={B_BITS}");
+    group.bench_function(BenchmarkId::new("fresh_hasher", &params), |b| {
+        b.iter(|| flow_ids_fresh(black_box(INPUT), &nonces, B_BITS))
+    });
+    group.bench_function(BenchmarkId::new("primed_hasher", &params), |b| {
+        b.iter(|| calculate_flow_ids_batch(black_box(INPUT), &nonces, B_BITS))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_grind);
+criterion_main!(benches);
@@ -20,8 +20,8 @@
 //!       `collidervm_toy::core::find_valid_nonce`).
 //!     * chooses the corresponding flow `d` and builds the **locking script**
 //!       for `F1` (and `F2`) using the existing helpers.
-//!     * constructs and signs **tx_f1** (spends the funding UTXO → P2WSH locked
-//!       by the `F1` program).
+//!     * constructs and signs **tx_f1** (spends the funding UTXO → Taproot
+//!       output whose single leaf is the `F1` program).
 //! 4.  **Online phase** – it then builds and signs **f2_tx**, spending the F1
 //!     output with the witness `[sig, flow_id, x, script]`, paying the remaining
 //!     funds to an Operator address.
@@ -34,34 +34,59 @@
 
 #![allow(clippy::too_many_arguments)]
 
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use bitcoin::Network;
-use bitcoin::secp256k1::{PublicKey, Secp256k1, XOnlyPublicKey};
-use bitcoin::taproot::TaprootBuilder;
-use bitcoin::{Address, OutPoint};
+use bitcoin::secp256k1::{Keypair, PublicKey, Secp256k1, XOnlyPublicKey};
+use bitcoin::{Address, Amount, OutPoint, Transaction, Txid};
 use bitcoincore_rpc::{Auth, Client, RpcApi};
-use clap::Parser;
-use collidervm_toy::core::{find_valid_nonce, flow_id_to_prefix_bytes};
-use collidervm_toy::musig2::simulate_musig2;
+use clap::{Parser, ValueEnum};
+use collidervm_toy::core::{
+    BBits, BenchMode, F1_THRESHOLD, F2_THRESHOLD, LBits, ScriptPredicate,
+    benchmark_hash_rate, calculate_flow_id, find_valid_nonce_with_style,
+    flow_id_to_prefix_bytes, input_from_label, predicate_holds,
+    truncate_flow_id, verify_blake3_snippet_compat,
+};
+use collidervm_toy::funding::{
+    EsploraFundingSource, FundingSource, RpcExistingUtxo, RpcFundingSource,
+};
+use collidervm_toy::musig2::{
+    aggregate_pubkeys, generate_keys_n, generate_keys_n_from_seed,
+    simulate_musig2, simulate_musig2_seeded,
+};
 use collidervm_toy::output::{
     DemoOutput, DemoParameters, KeyInfo, KeyPair, TransactionInfo, TxInfo,
     write_demo_output_to_file,
 };
 use collidervm_toy::transactions::{
-    create_f1_tx, create_f2_tx, create_spending_tx, finalize_f1_tx,
-    finalize_lock_tx,
+    DemoChain, anchor_payload, create_f1_tx, create_f2_tx, create_spending_tx,
+    finalize_f1_tx, finalize_lock_tx, funding_taproot_spend_info,
+    nums_internal_key, signer_xonly_key, unsigned_leaf_psbt,
+    validate_chain_links, validate_flow_locally,
 };
-use collidervm_toy::utils::inner_from;
 use collidervm_toy::utils::{
-    wait_for_confirmation, wrap_network, write_transaction_to_file,
+    ProgressStyleConfig, pubkey_inner_from, seckey_inner_from,
+    wait_for_confirmation, wrap_network, write_psbt_to_file,
+    write_transaction_to_file,
 };
+use musig2::BinaryEncoding;
+use qrcode::QrCode;
+use qrcode::render::unicode;
+use serde::{Deserialize, Serialize};
 
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Minimal amount we ask the user to deposit (200 000 sat ≈ 0.002 BTC)
 const REQUIRED_AMOUNT_SAT: u64 = 150_000;
 /// Hard‑coded ColliderVM parameters (match the toy simulation)
 const L_PARAM: usize = 4;
-const B_PARAM: usize = 16; // multiple of 8 ≤ 32
+const B_PARAM: usize = 16; // multiple of 4 ≤ 32
+/// The steps of the demo flow and the predicate each one checks
+const DEMO_PREDICATES: [(&str, ScriptPredicate); 2] =
+    [("F1", ScriptPredicate::F1), ("F2", ScriptPredicate::F2)];
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -70,13 +95,34 @@ struct Args {
     #[arg(short, long, default_value_t = 114)]
     x: u32,
 
+    /// Derive x deterministically from a label (e.g. "alice") instead of --x
+    #[arg(long, conflicts_with = "x")]
+    label: Option<String>,
+
+    /// Take x and the network from a token printed by a previous run
+    #[arg(long, conflicts_with_all = ["x", "label", "network"])]
+    from_token: Option<String>,
+
     /// Dry run mode doesn't interact with Bitcoin network
     #[arg(long)]
     dry_run: bool,
 
     /// Fee‑rate in sat/vB (default = 1 sat/vB, plenty for Signet)
-    #[arg(long, default_value_t = 1)]
-    fee_rate: u64,
+    #[arg(long)]
+    fee_rate: Option<u64>,
+
+    /// Ask the node for a fee-rate estimate instead of using --fee-rate
+    #[arg(long)]
+    estimate_fee: bool,
+
+    /// Signal BIP125 replaceability on every input, so a stuck transaction
+    /// can be fee-bumped
+    #[arg(long)]
+    rbf: bool,
+
+    /// Required to run the demo on mainnet with real funds
+    #[arg(long)]
+    i_understand_mainnet: bool,
 
     /// Write JSON output to a file instead of stdout
     #[arg(long, default_value = "target/demo")]
@@ -86,6 +132,29 @@ struct Args {
     #[arg(long, default_value = "demo.json")]
     output_file: String,
 
+    /// Skip writing the transaction and JSON files, only print the txids
+    #[arg(long)]
+    no_files: bool,
+
+    /// Also write a `replay.sh` reproducing this run to the output dir
+    /// (needs --seed)
+    #[arg(long)]
+    emit_replay: bool,
+
+    /// Also write the unsigned F1 and F2 transactions as `f1.psbt` and
+    /// `f2.psbt` to the output dir, for external tools to inspect or sign
+    #[arg(long)]
+    psbt: bool,
+
+    /// Use this nonce instead of grinding for one (must select a flow in D)
+    #[arg(long)]
+    nonce: Option<u64>,
+
+    /// Measure the hash rate for this many seconds before grinding, so the
+    /// nonce search ETA is meaningful from the start
+    #[arg(long, value_name = "SECS", conflicts_with = "nonce")]
+    calibrate: Option<u64>,
+
     /// receiver of the spending tx
     #[arg(
         long,
@@ -102,20 +171,108 @@ struct Args {
     rpc_url: String,
 
     /// RPC user
-    #[arg(long, default_value = "user")]
+    #[arg(long, env = "COLLIDERVM_RPC_USER", default_value = "user")]
     rpc_user: String,
 
     /// RPC password
-    #[arg(long, default_value = "PaSsWoRd")]
+    #[arg(long, env = "COLLIDERVM_RPC_PASSWORD", default_value = "PaSsWoRd")]
     rpc_password: String,
 
     /// bitcoin wallet name
     #[arg(long, default_value = "alice")]
     wallet_name: String,
+
+    /// Where the funding UTXO comes from
+    #[arg(long, value_enum, default_value_t = FundingBackend::Rpc)]
+    funding_backend: FundingBackend,
+
+    /// Esplora API base URL for --funding-backend esplora
+    #[arg(long, default_value = "https://mempool.space/signet/api")]
+    esplora_url: String,
+
+    /// Spend this already funded tx instead of funding the address from
+    /// the wallet (needs --funding-vout and --seed)
+    #[arg(long)]
+    funding_txid: Option<Txid>,
+
+    /// Output of --funding-txid paying the funding address
+    #[arg(long)]
+    funding_vout: Option<u32>,
+
+    /// Use the BIP341 NUMS point as the funding output's internal key,
+    /// disabling its key-path spend
+    #[arg(long)]
+    nums_internal_key: bool,
+
+    /// Confirmations to wait for on each broadcast transaction
+    #[arg(long, default_value_t = 1)]
+    confirmations: u32,
+
+    /// Confirmations to wait for on the funding tx (defaults to
+    /// --confirmations)
+    #[arg(long)]
+    funding_confirmations: Option<u32>,
+
+    /// Block until the funding tx has this many confirmations before
+    /// building F1, for nodes rejecting spends of unconfirmed outputs
+    #[arg(long)]
+    wait_funding_conf: Option<u32>,
+
+    /// Also render the funding address as a QR code in the terminal
+    #[arg(long)]
+    qr: bool,
+
+    /// Add an OP_RETURN output committing to the flow id and x to the
+    /// spending tx
+    #[arg(long)]
+    anchor: bool,
+
+    /// Derive the signer keys and MuSig2 nonces from this seed. Together
+    /// with --dry-run, everything but the funding outpoint is reproducible.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Sign with a single plain Schnorr key instead of the 3-of-3 MuSig2
+    /// aggregate
+    #[arg(long)]
+    single_key: bool,
+
+    /// Extra `key=value` entry for the metadata of the JSON output, may be
+    /// repeated
+    #[arg(long = "meta", value_parser = parse_meta_entry)]
+    meta: Vec<(String, String)>,
 }
 
+/// How the demo obtains its funding UTXO
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum FundingBackend {
+    /// Send the funds from the node's wallet
+    Rpc,
+    /// Wait for the user to fund the address, and find the UTXO through
+    /// --esplora-url. Needs --seed, so that a re-run after funding derives
+    /// the same address.
+    Esplora,
+}
+
+/// Fee-rate used when neither --fee-rate nor --estimate-fee is given
+const DEFAULT_FEE_RATE: u64 = 1;
+/// Confirmation target (in blocks) for --estimate-fee
+const FEE_ESTIMATE_TARGET_BLOCKS: u16 = 6;
+
 fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    if let Some(token) = args.from_token.take() {
+        let shared = decode_params(&token)?;
+        args.x = shared.x;
+        args.network = shared.network;
+    }
+    if let Err(err) = validate_args(&args) {
+        anyhow::bail!("invalid arguments: {err}");
+    }
+    println!(
+        "Share this configuration with --from-token {}",
+        encode_params(&args)
+    );
 
     let rpc_client = Client::new(
         &format!("{}/wallet/{}", args.rpc_url, args.wallet_name),
@@ -136,10 +293,8 @@ fn main() -> anyhow::Result<()> {
     };
 
     let secp: Secp256k1<bitcoin::secp256k1::All> = Secp256k1::new();
-    let sk_signers = collidervm_toy::musig2::generate_keys::<3>();
-    let pk_signers = sk_signers.iter().map(|key| key.1).collect::<Vec<_>>();
-    let agg_ctx = musig2::KeyAggContext::new(pk_signers)?;
-    let pk_signer: musig2::secp256k1::PublicKey = agg_ctx.aggregated_pubkey();
+    let sk_signers = signer_keys(args.seed, args.single_key);
+    let pk_signer = signer_pubkey(&sk_signers)?;
 
     if args.dry_run {
         println!(
@@ -147,22 +302,215 @@ fn main() -> anyhow::Result<()> {
         );
     }
 
-    let funding_outpoint = get_funding_outpoint(
-        &rpc_client,
+    let fee_rate = match args.fee_rate {
+        Some(fee_rate) => fee_rate,
+        None if args.estimate_fee => estimate_fee_rate(&rpc_client)?,
+        None => DEFAULT_FEE_RATE,
+    };
+
+    let internal_key = args.nums_internal_key.then(nums_internal_key);
+
+    let funding_source: Box<dyn FundingSource> =
+        match (args.funding_backend, args.funding_txid, args.funding_vout) {
+            (FundingBackend::Rpc, Some(txid), Some(vout)) => {
+                Box::new(RpcExistingUtxo {
+                    client: &rpc_client,
+                    outpoint: OutPoint { txid, vout },
+                })
+            }
+            (FundingBackend::Rpc, ..) => Box::new(RpcFundingSource {
+                client: &rpc_client,
+            }),
+            (FundingBackend::Esplora, ..) => {
+                Box::new(EsploraFundingSource::new(&args.esplora_url))
+            }
+        };
+    let (funding_outpoint, funding_value) = get_funding_outpoint(
+        funding_source.as_ref(),
         &secp,
         network,
-        &inner_from(pk_signer),
+        &pk_signer,
         REQUIRED_AMOUNT_SAT,
-    );
+        internal_key,
+        args.qr,
+    )?;
+    wait_for_funding_before_build(
+        &funding_outpoint.txid,
+        args.wait_funding_conf,
+        |txid, confirmations| {
+            wait_for_confirmation(&rpc_client, txid, confirmations, timeout)
+        },
+    )?;
 
-    // In a production‑ready tool we would RPC‑query the node to retrieve the
-    // exact amount & pkScript of the funding UTXO.  To keep the demo
-    // self‑contained we *assume* the UTXO pays `REQUIRED_AMOUNT_SAT` to the
-    // Signer's P2WPKH address.  The instructions ensured the user sends that.
-    let funding_value_sat = REQUIRED_AMOUNT_SAT;
+    // The locking scripts rely on the layout of bitvm's BLAKE3 output
+    verify_blake3_snippet_compat().map_err(anyhow::Error::msg)?;
+    let chain = build_demo_chain(
+        &args,
+        &secp,
+        network,
+        &sk_signers,
+        &pk_signer,
+        funding_outpoint,
+        funding_value,
+        fee_rate,
+        internal_key,
+    )?;
+    // The timestamp is left out of seeded runs, which must be reproducible
+    let timestamp = args.seed.is_none().then(SystemTime::now);
+    let metadata = demo_metadata(&args, network, timestamp);
+    write_demo_files(&args, &chain, &sk_signers, network, metadata)?;
+
+    if args.emit_replay && !args.no_files {
+        let path = Path::new(&args.output_dir).join("replay.sh");
+        write_replay_script(&chain, &args, &path)?;
+        println!("Replay script written to {path:?}");
+    }
+
+    validate_chain_links(&chain)?;
 
-    let (nonce, flow_id) = find_valid_nonce(args.x, B_PARAM, L_PARAM)
-        .expect("nonce search should succeed quickly");
+    if !args.dry_run {
+        validate_flow_locally(&chain).map_err(|err| {
+            anyhow::anyhow!(
+                "not broadcasting, the node would reject the chain: {err}"
+            )
+        })?;
+        let targets = ConfirmationTargets::from_args(&args);
+        broadcast_chain(
+            &chain.funding_outpoint.txid,
+            [&chain.f1_tx, &chain.f2_tx, &chain.spending_tx],
+            &targets,
+            |tx| Ok(rpc_client.send_raw_transaction(tx)?),
+            |txid, confirmations| {
+                wait_for_confirmation(&rpc_client, txid, confirmations, timeout)
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Number of MuSig2 signers, unless --single-key is set
+const NUM_SIGNERS: usize = 3;
+
+/// The signer keys, derived from `seed` when one is given: one key with
+/// `single_key`, [`NUM_SIGNERS`] MuSig2 keys otherwise.
+fn signer_keys(
+    seed: Option<u64>,
+    single_key: bool,
+) -> Vec<(musig2::secp256k1::SecretKey, musig2::secp256k1::PublicKey)> {
+    let count = if single_key { 1 } else { NUM_SIGNERS };
+    match seed {
+        Some(seed) => generate_keys_n_from_seed(count, seed),
+        None => generate_keys_n(count),
+    }
+}
+
+/// The key the locking scripts check signatures against: the key itself
+/// for a single signer, the MuSig2 aggregate otherwise.
+fn signer_pubkey(
+    sk_signers: &[(
+        musig2::secp256k1::SecretKey,
+        musig2::secp256k1::PublicKey,
+    )],
+) -> anyhow::Result<PublicKey> {
+    if let [(_, pk)] = sk_signers {
+        return Ok(pubkey_inner_from(*pk));
+    }
+    let pubkeys = sk_signers.iter().map(|key| key.1).collect::<Vec<_>>();
+    let (_, agg_pubkey) = aggregate_pubkeys(&pubkeys)?;
+    Ok(pubkey_inner_from(agg_pubkey))
+}
+
+/// Signs `message` with all the signers, deriving the MuSig2 nonces from
+/// `seed` when one is given.
+///
+/// A single signer signs with plain BIP340 Schnorr, bypassing MuSig2.
+fn sign(
+    sk_signers: &[(
+        musig2::secp256k1::SecretKey,
+        musig2::secp256k1::PublicKey,
+    )],
+    message: &secp256k1::Message,
+    seed: Option<u64>,
+) -> anyhow::Result<musig2::LiftedSignature> {
+    if let [(sk, _)] = sk_signers {
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_secret_key(&secp, &seckey_inner_from(*sk));
+        // BIP340 nonces are derived from the key and message, which keeps
+        // --seed runs reproducible
+        let signature = secp.sign_schnorr_no_aux_rand(message, &keypair);
+        return Ok(musig2::LiftedSignature::from_bytes(
+            &signature.serialize(),
+        )?);
+    }
+    match seed {
+        Some(seed) => simulate_musig2_seeded(sk_signers, message, seed),
+        None => simulate_musig2(sk_signers, message),
+    }
+}
+
+/// Grinds the nonce for the demo input and builds the signed f1, f2 and
+/// spending transactions on top of `funding_outpoint`.
+///
+/// Nothing here talks to the node: with --seed, the same funding outpoint
+/// always gives the same chain.
+fn build_demo_chain(
+    args: &Args,
+    secp: &Secp256k1<secp256k1::All>,
+    network: Network,
+    sk_signers: &[(
+        musig2::secp256k1::SecretKey,
+        musig2::secp256k1::PublicKey,
+    )],
+    pk_signer: &PublicKey,
+    funding_outpoint: OutPoint,
+    funding_value: Amount,
+    fee_rate: u64,
+    internal_key: Option<XOnlyPublicKey>,
+) -> anyhow::Result<DemoChain> {
+    let funding_value_sat = funding_value.to_sat();
+
+    let x = match &args.label {
+        Some(label) => {
+            let x = input_from_label(label);
+            println!("Derived x = {x} from label \"{label}\"");
+            x
+        }
+        None => args.x,
+    };
+    // Cheap off-chain check, before grinding and building scripts that
+    // would only fail once executed
+    for (step, predicate) in DEMO_PREDICATES {
+        if !predicate_holds(&predicate, x) {
+            anyhow::bail!("x = {x} does not satisfy {step} ({predicate:?})");
+        }
+    }
+
+    let (nonce, flow_id) = match args.nonce {
+        Some(nonce) => {
+            let flow_id =
+                truncate_flow_id(calculate_flow_id(x, nonce), B_PARAM);
+            if flow_id >= 1 << L_PARAM {
+                anyhow::bail!(
+                    "nonce {nonce} maps x = {x} to flow {flow_id}, outside D (L={L_PARAM})"
+                );
+            }
+            (nonce, flow_id)
+        }
+        None => {
+            let hash_rate = args.calibrate.map(|secs| {
+                benchmark_hash_rate(secs, BenchMode::FullFlowCheck) as f64
+            });
+            find_valid_nonce_with_style(
+                x,
+                BBits(B_PARAM),
+                LBits(L_PARAM),
+                ProgressStyleConfig::default(),
+                hash_rate,
+            )
+            .expect("nonce search should succeed quickly")
+        }
+    };
 
     println!(
         "Found nonce r = {nonce} selecting flow d = {flow_id} (B={B_PARAM} bits, L={L_PARAM})"
@@ -179,183 +527,1148 @@ fn main() -> anyhow::Result<()> {
         message,
     ) = create_f1_tx(
         B_PARAM,
-        &secp,
-        &inner_from(pk_signer),
+        secp,
+        pk_signer,
         &network,
         &funding_outpoint,
         &funding_value_sat,
         &flow_id_prefix,
-        &args.fee_rate,
+        F1_THRESHOLD,
+        &fee_rate,
+        internal_key,
+        args.rbf,
+    )?;
+    let final_signature = sign(sk_signers, &message, args.seed)?;
+    let funding_prevout = bitcoin::TxOut {
+        value: funding_value,
+        script_pubkey: create_funding_taproot_address(
+            pk_signer,
+            secp,
+            network,
+            internal_key,
+        )
+        .script_pubkey(),
+    };
+    maybe_write_psbt(
+        args,
+        &f1_tx,
+        &funding_prevout,
+        &funding_script,
+        &funding_spend_info,
+        "f1",
     )?;
-    let final_signature = simulate_musig2(&sk_signers, &message)?;
     finalize_f1_tx(
         &mut f1_tx,
         final_signature,
         &funding_spend_info,
         &funding_script,
-    );
+        &funding_prevout,
+    )?;
 
     let f1_output_value = f1_tx.output[0].value.to_sat();
     let (mut f2_tx, f2_lock, f2_spend_info, message) = create_f2_tx(
         B_PARAM,
-        &secp,
-        &inner_from(pk_signer),
+        secp,
+        pk_signer,
         &network,
         &f1_tx,
         &f1_output_value,
         &f1_lock,
         &flow_id_prefix,
-        &args.fee_rate,
+        F2_THRESHOLD,
+        &fee_rate,
+        args.rbf,
     )?;
-    let final_signature = simulate_musig2(&sk_signers, &message)?;
+    maybe_write_psbt(
+        args,
+        &f2_tx,
+        &f1_tx.output[0],
+        &f1_lock,
+        &f1_spend_info,
+        "f2",
+    )?;
+    let final_signature = sign(sk_signers, &message, args.seed)?;
     finalize_lock_tx(
         &mut f2_tx,
         final_signature,
         &f1_spend_info,
         &f1_lock,
-        &args.x,
+        &x,
         &nonce,
+        &f1_tx.output[0],
     )?;
 
     let receiver_addr =
         Address::from_str(&args.receiver)?.require_network(network)?;
 
     let f2_output_value = f2_tx.output[0].value.to_sat();
+    let anchor = args.anchor.then(|| anchor_payload(flow_id, x));
     let (mut spending_tx, message) = create_spending_tx(
         &f2_tx,
         &f2_output_value,
         &receiver_addr,
         &f2_lock,
-        &args.fee_rate,
+        &fee_rate,
+        anchor.as_deref(),
+        None,
+        args.rbf,
     )?;
-    let final_signature = simulate_musig2(&sk_signers, &message)?;
+    let final_signature = sign(sk_signers, &message, args.seed)?;
     finalize_lock_tx(
         &mut spending_tx,
         final_signature,
         &f2_spend_info,
         &f2_lock,
-        &args.x,
+        &x,
         &nonce,
+        &f2_tx.output[0],
     )?;
 
-    let f1_tx_path = write_transaction_to_file(&f1_tx, &args.output_dir, "f1")?;
-    let f2_tx_path = write_transaction_to_file(&f2_tx, &args.output_dir, "f2")?;
+    Ok(DemoChain {
+        funding_outpoint,
+        funding_value_sat,
+        x,
+        nonce,
+        flow_id,
+        f1_tx,
+        f2_tx,
+        spending_tx,
+    })
+}
+
+/// Writes the three transactions and the `demo.json` summary with
+/// `metadata`, honouring --no-files.
+fn write_demo_files(
+    args: &Args,
+    chain: &DemoChain,
+    sk_signers: &[(
+        musig2::secp256k1::SecretKey,
+        musig2::secp256k1::PublicKey,
+    )],
+    network: Network,
+    metadata: BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let f1_tx_path = maybe_write_transaction(args, &chain.f1_tx, "f1")?;
+    let f2_tx_path = maybe_write_transaction(args, &chain.f2_tx, "f2")?;
     let spending_tx_path =
-        write_transaction_to_file(&spending_tx, &args.output_dir, "spending")?;
+        maybe_write_transaction(args, &chain.spending_tx, "spending")?;
 
     let signers = sk_signers
         .iter()
         .map(|key| KeyPair {
-            wif: bitcoin::PrivateKey::new(inner_from(key.0), network).to_wif(),
+            wif: bitcoin::PrivateKey::new(seckey_inner_from(key.0), network)
+                .to_wif(),
         })
         .collect::<Vec<_>>();
     let demo_output = DemoOutput {
+        schema_version: DemoOutput::CURRENT_VERSION,
         keys: KeyInfo { signers },
         transactions: Some(TransactionInfo {
             f1: TxInfo {
-                txid: f1_tx.compute_txid().to_string(),
+                txid: chain.f1_tx.compute_txid().to_string(),
                 file_path: f1_tx_path,
             },
             f2: TxInfo {
-                txid: f2_tx.compute_txid().to_string(),
+                txid: chain.f2_tx.compute_txid().to_string(),
                 file_path: f2_tx_path,
             },
             spending: TxInfo {
-                txid: spending_tx.compute_txid().to_string(),
+                txid: chain.spending_tx.compute_txid().to_string(),
                 file_path: spending_tx_path,
             },
-            nonce,
-            flow_id,
+            nonce: chain.nonce,
+            flow_id: chain.flow_id,
         }),
-        input_x: args.x,
+        input_x: chain.x,
         parameters: DemoParameters {
             required_amount_sat: REQUIRED_AMOUNT_SAT,
             l_param: L_PARAM,
             b_param: B_PARAM,
         },
+        metadata,
     };
 
-    write_demo_output_to_file(
-        &demo_output,
-        &args.output_dir,
-        &args.output_file,
+    maybe_write_demo_output(args, &demo_output)
+}
+
+/// Context recorded with the demo output: the resolved network, the crate
+/// version and, when given, the UTC time of the run. `--meta` entries are
+/// added last and may override these.
+fn demo_metadata(
+    args: &Args,
+    network: Network,
+    timestamp: Option<SystemTime>,
+) -> BTreeMap<String, String> {
+    let mut metadata = BTreeMap::from([
+        ("network".to_string(), network.to_string()),
+        (
+            "crate_version".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+    ]);
+    if let Some(timestamp) = timestamp {
+        let unix_secs = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        metadata.insert("timestamp_utc".to_string(), format_utc(unix_secs));
+    }
+    metadata.extend(args.meta.iter().cloned());
+    metadata
+}
+
+/// The parameters a `--from-token` token carries.
+///
+/// `B`, `L` and the predicates are fixed in this binary, they travel with
+/// the token so that one made by a differently built demo is rejected.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct SharedParams {
+    x: u32,
+    b: usize,
+    l: usize,
+    predicates: Vec<ScriptPredicate>,
+    network: String,
+}
+
+/// Encodes the parameters of a run as a base64url token for --from-token.
+/// A --label is resolved to its x.
+fn encode_params(args: &Args) -> String {
+    let params = SharedParams {
+        x: args.label.as_deref().map_or(args.x, input_from_label),
+        b: B_PARAM,
+        l: L_PARAM,
+        predicates: DEMO_PREDICATES.map(|(_, predicate)| predicate).to_vec(),
+        network: args.network.clone(),
+    };
+    let json = serde_json::to_vec(&params).expect("serializable parameters");
+    URL_SAFE_NO_PAD.encode(json)
+}
+
+/// Decodes a token from [`encode_params`] into the default arguments with
+/// its x and network.
+fn decode_params(token: &str) -> anyhow::Result<Args> {
+    let json = URL_SAFE_NO_PAD
+        .decode(token.trim())
+        .map_err(|e| anyhow::anyhow!("invalid token: {e}"))?;
+    let params: SharedParams = serde_json::from_slice(&json)
+        .map_err(|e| anyhow::anyhow!("invalid token: {e}"))?;
+    let predicates = DEMO_PREDICATES.map(|(_, predicate)| predicate);
+    if params.b != B_PARAM
+        || params.l != L_PARAM
+        || params.predicates != predicates
+    {
+        anyhow::bail!(
+            "token is for B={} L={} {:?}, this demo runs B={B_PARAM} L={L_PARAM} {predicates:?}",
+            params.b,
+            params.l,
+            params.predicates
+        );
+    }
+    Ok(Args {
+        x: params.x,
+        network: params.network,
+        ..Args::parse_from(["demo"])
+    })
+}
+
+/// Parses a `--meta key=value` entry.
+fn parse_meta_entry(entry: &str) -> Result<(String, String), String> {
+    match entry.split_once('=') {
+        Some((key, value)) if !key.is_empty() => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected key=value, got '{entry}'")),
+    }
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 UTC timestamp.
+fn format_utc(unix_secs: u64) -> String {
+    let (days, secs) = ((unix_secs / 86_400) as i64, unix_secs % 86_400);
+    // Civil date from a day count (H. Hinnant's days_from_civil inverse)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+/// Checks the interdependencies between arguments that clap can't express,
+/// before any work is done.
+fn validate_args(args: &Args) -> Result<(), String> {
+    if !["regtest", "signet", "testnet", "mainnet"]
+        .contains(&args.network.as_str())
+    {
+        return Err(format!(
+            "unknown network '{}', expected regtest, signet, testnet or mainnet",
+            args.network
+        ));
+    }
+    if args.estimate_fee && args.fee_rate.is_some() {
+        return Err("--estimate-fee conflicts with --fee-rate".to_string());
+    }
+    if args.fee_rate == Some(0) {
+        return Err("--fee-rate must be at least 1 sat/vB".to_string());
+    }
+    match (args.network.as_str(), args.i_understand_mainnet) {
+        ("mainnet", false) => {
+            return Err(
+                "running on mainnet spends real funds, pass --i-understand-mainnet"
+                    .to_string(),
+            );
+        }
+        (network, true) if network != "mainnet" => {
+            return Err(format!(
+                "--i-understand-mainnet only applies to mainnet, not {network}"
+            ));
+        }
+        _ => {}
+    }
+    if args.confirmations == 0
+        || args.funding_confirmations == Some(0)
+        || args.wait_funding_conf == Some(0)
+    {
+        return Err("confirmation targets must be at least 1".to_string());
+    }
+    if args.funding_backend == FundingBackend::Esplora && args.seed.is_none() {
+        return Err(
+            "--funding-backend esplora needs --seed, the funding address changes with the keys"
+                .to_string(),
+        );
+    }
+    match (args.funding_txid, args.funding_vout) {
+        (Some(_), None) => {
+            return Err("--funding-txid needs --funding-vout".to_string());
+        }
+        (None, Some(_)) => {
+            return Err("--funding-vout needs --funding-txid".to_string());
+        }
+        (Some(_), Some(_)) if args.funding_backend != FundingBackend::Rpc => {
+            return Err(
+                "--funding-txid is looked up in the node, it conflicts with --funding-backend esplora"
+                    .to_string(),
+            );
+        }
+        (Some(_), Some(_)) if args.seed.is_none() => {
+            return Err(
+                "--funding-txid needs --seed, the funding address changes with the keys"
+                    .to_string(),
+            );
+        }
+        _ => {}
+    }
+    if args.emit_replay && args.seed.is_none() {
+        return Err(
+            "--emit-replay needs --seed, a re-run would use other keys"
+                .to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// Queries the node's `estimatesmartfee` and converts it to sat/vB.
+fn estimate_fee_rate(rpc_client: &Client) -> anyhow::Result<u64> {
+    let estimate =
+        rpc_client.estimate_smart_fee(FEE_ESTIMATE_TARGET_BLOCKS, None)?;
+    let Some(fee_rate) = estimate.fee_rate else {
+        anyhow::bail!(
+            "node could not estimate a fee-rate ({:?}), use --fee-rate instead",
+            estimate.errors
+        );
+    };
+    // BTC/kvB => sat/vB, rounded up
+    Ok(fee_rate.to_sat().div_ceil(1000).max(1))
+}
+
+/// Writes `tx` to `<output_dir>/<name>.tx`, or only prints its txid when
+/// `--no-files` is set.
+fn maybe_write_transaction(
+    args: &Args,
+    tx: &Transaction,
+    name: &str,
+) -> anyhow::Result<Option<String>> {
+    if args.no_files {
+        println!("{name}: {}", tx.compute_txid());
+        return Ok(None);
+    }
+    Ok(Some(write_transaction_to_file(tx, &args.output_dir, name)?))
+}
+
+/// Writes the unsigned PSBT of `tx` spending `leaf` of `spend_info` to
+/// `<name>.psbt`, when --psbt is set and --no-files is not.
+fn maybe_write_psbt(
+    args: &Args,
+    tx: &Transaction,
+    prevout: &bitcoin::TxOut,
+    leaf: &bitcoin::ScriptBuf,
+    spend_info: &bitcoin::taproot::TaprootSpendInfo,
+    name: &str,
+) -> anyhow::Result<()> {
+    if !args.psbt || args.no_files {
+        return Ok(());
+    }
+    let psbt = unsigned_leaf_psbt(
+        tx,
+        std::slice::from_ref(prevout),
+        leaf,
+        spend_info,
     )?;
+    let path = write_psbt_to_file(&psbt, &args.output_dir, name)?;
+    println!("Unsigned {name} PSBT written to {path}");
+    Ok(())
+}
 
-    if !args.dry_run {
-        println!("▶️  Waiting for founding tx: {}", funding_outpoint.txid);
-        wait_for_confirmation(&rpc_client, &funding_outpoint.txid, 1, timeout)
-            .unwrap();
+/// Writes the demo summary JSON, unless `--no-files` is set.
+fn maybe_write_demo_output(
+    args: &Args,
+    demo_output: &DemoOutput,
+) -> anyhow::Result<()> {
+    if args.no_files {
+        return Ok(());
+    }
+    write_demo_output_to_file(demo_output, &args.output_dir, &args.output_file)
+}
 
-        println!("▶️  Pushed f1: {}", f1_tx.compute_txid());
-        let f1_txid = rpc_client.send_raw_transaction(&f1_tx)?;
-        wait_for_confirmation(&rpc_client, &f1_txid, 1, timeout)?;
+/// Writes a shell script with the commands reproducing `chain`: the demo
+/// invocation (reusing the found nonce) and the raw transactions to
+/// rebroadcast.
+fn write_replay_script(
+    chain: &DemoChain,
+    args: &Args,
+    path: &Path,
+) -> anyhow::Result<()> {
+    // The credentials come from the environment, never from the script
+    let bitcoin_cli = format!(
+        "bitcoin-cli -chain={} -rpcuser=\"$COLLIDERVM_RPC_USER\" -rpcpassword=\"$COLLIDERVM_RPC_PASSWORD\" -rpcwallet={}",
+        match args.network.as_str() {
+            "mainnet" => "main",
+            "testnet" => "test",
+            network => network,
+        },
+        args.wallet_name
+    );
+    let seed = args
+        .seed
+        .ok_or_else(|| anyhow::anyhow!("--emit-replay needs --seed"))?;
 
-        println!("▶️  Pushed f2: {}", f2_tx.compute_txid());
-        let f2_txid = rpc_client.send_raw_transaction(&f2_tx)?;
-        wait_for_confirmation(&rpc_client, &f2_txid, 1, timeout)?;
+    let mut script = String::from(
+        "#!/bin/sh\n# Replay of a ColliderVM demo run\n\
+         # Usage: COLLIDERVM_RPC_USER=.. COLLIDERVM_RPC_PASSWORD=.. replay.sh rerun|broadcast\n\
+         set -e\n\n",
+    );
+    script += &format!(
+        "# Funding outpoint: {}:{} ({} sat)\n\n",
+        chain.funding_outpoint.txid,
+        chain.funding_outpoint.vout,
+        chain.funding_value_sat
+    );
+    script += "case \"$1\" in\nrerun)\n";
+    script += &format!(
+        "    # Re-run the demo with the same seed and input, skipping the nonce search (flow d = {})\n",
+        chain.flow_id
+    );
+    script += &format!(
+        "    cargo run --release --bin demo -- --seed {seed}{} --x {} --nonce {} --fee-rate {} --network {} --rpc-url {} --wallet-name {} --receiver {}\n    ;;\n",
+        if args.single_key { " --single-key" } else { "" },
+        chain.x,
+        chain.nonce,
+        args.fee_rate.unwrap_or(DEFAULT_FEE_RATE),
+        args.network,
+        args.rpc_url,
+        args.wallet_name,
+        args.receiver
+    );
+    script +=
+        "broadcast)\n    # Rebroadcast the exact transactions of this run\n";
+    script += &format!(
+        "    {bitcoin_cli} getrawtransaction {}\n",
+        chain.funding_outpoint.txid
+    );
+    for (name, tx) in [
+        ("f1", &chain.f1_tx),
+        ("f2", &chain.f2_tx),
+        ("spending", &chain.spending_tx),
+    ] {
+        script += &format!(
+            "    # {name}: {}\n    {bitcoin_cli} sendrawtransaction {}\n",
+            tx.compute_txid(),
+            bitcoin::consensus::encode::serialize_hex(tx)
+        );
+    }
+    script += "    ;;\n*)\n    echo \"usage: $0 rerun|broadcast\" >&2\n    exit 2\n    ;;\nesac\n";
 
-        println!("▶️  Pushed spending tx: {}", spending_tx.compute_txid());
-        let spending_tx_txid = rpc_client.send_raw_transaction(&spending_tx)?;
-        wait_for_confirmation(&rpc_client, &spending_tx_txid, 1, timeout)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
     }
+    std::fs::write(path, script)?;
+    Ok(())
+}
 
+/// Number of confirmations to wait for before moving on to the next tx
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ConfirmationTargets {
+    funding: u32,
+    chain: u32,
+}
+
+impl ConfirmationTargets {
+    fn from_args(args: &Args) -> Self {
+        Self {
+            funding: args.funding_confirmations.unwrap_or(args.confirmations),
+            chain: args.confirmations,
+        }
+    }
+}
+
+/// With --wait-funding-conf, waits for the funding tx to reach its target
+/// before F1 is built against it.
+fn wait_for_funding_before_build(
+    funding_txid: &Txid,
+    wait_funding_conf: Option<u32>,
+    wait: impl FnOnce(&Txid, u32) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let Some(confirmations) = wait_funding_conf else {
+        return Ok(());
+    };
+    println!(
+        "▶️  Waiting for {confirmations} funding confirmation(s) before building f1: {funding_txid}"
+    );
+    wait(funding_txid, confirmations)
+}
+
+/// Waits for the funding tx, then pushes f1, f2 and the spending tx in
+/// order, each one waiting for its confirmation target.
+fn broadcast_chain(
+    funding_txid: &Txid,
+    txs: [&Transaction; 3],
+    targets: &ConfirmationTargets,
+    mut send: impl FnMut(&Transaction) -> anyhow::Result<Txid>,
+    mut wait: impl FnMut(&Txid, u32) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    println!("▶️  Waiting for founding tx: {funding_txid}");
+    wait(funding_txid, targets.funding)?;
+
+    for (name, tx) in ["f1", "f2", "spending tx"].into_iter().zip(txs) {
+        println!("▶️  Pushed {name}: {}", tx.compute_txid());
+        let txid = send(tx)?;
+        wait(&txid, targets.chain)?;
+    }
     Ok(())
 }
 
 /// create a funding taproot address, and demo the spending tx with musig2
+///
+/// `internal_key` defaults to the signer key when `None`.
 fn create_funding_taproot_address(
     pubkey: &PublicKey,
     secp: &Secp256k1<secp256k1::All>,
     network: Network,
+    internal_key: Option<XOnlyPublicKey>,
 ) -> Address {
-    let xonly_pk = XOnlyPublicKey::from(*pubkey);
-    let leaf_script =
-        collidervm_toy::transactions::get_funding_script(&xonly_pk);
-
-    let spend_info = TaprootBuilder::new()
-        .add_leaf(0, leaf_script.clone())
-        .unwrap()
-        .finalize(secp, xonly_pk)
-        .unwrap();
+    let xonly_pk = signer_xonly_key(pubkey);
+    let spend_info = funding_taproot_spend_info(secp, &xonly_pk, internal_key);
 
     // The scriptPubKey for this Taproot output and the address (for funding):
     Address::p2tr_tweaked(spend_info.output_key(), network)
 }
 
+/// Renders `address` as a BIP21 URI QR code made of Unicode half blocks,
+/// scannable by mobile wallets straight from the terminal.
+fn funding_address_qr(address: &Address) -> anyhow::Result<String> {
+    let code = QrCode::new(address.to_qr_uri().as_bytes())?;
+    Ok(code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build())
+}
+
+/// Prints the funding address and asks `source` for a UTXO paying it at
+/// least `required_amount_sat`.
 fn get_funding_outpoint(
-    rpc_client: &Client,
+    source: &dyn FundingSource,
     secp: &Secp256k1<secp256k1::All>,
     network: Network,
     signer_pubkey: &PublicKey,
     required_amount_sat: u64,
-) -> OutPoint {
-    let funding_address =
-        create_funding_taproot_address(signer_pubkey, secp, network);
-    let txid = rpc_client
-        .send_to_address(
-            &funding_address,
-            bitcoin::Amount::from_sat(required_amount_sat),
-            None,
-            None,
-            None,
-            None,
+    internal_key: Option<XOnlyPublicKey>,
+    qr: bool,
+) -> anyhow::Result<(OutPoint, Amount)> {
+    let funding_address = create_funding_taproot_address(
+        signer_pubkey,
+        secp,
+        network,
+        internal_key,
+    );
+    println!("▶️  Funding address: {funding_address}");
+    if qr {
+        match funding_address_qr(&funding_address) {
+            Ok(code) => println!("{code}"),
+            Err(err) => eprintln!("⚠️  Could not render QR code: {err}"),
+        }
+    }
+    let (outpoint, value) = source
+        .find_utxo(&funding_address, Amount::from_sat(required_amount_sat))?;
+    println!("▶️  Funding UTXO: {outpoint} ({} sat)", value.to_sat());
+
+    Ok((outpoint, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collidervm_toy::output::{load_demo_output, replay_demo_output};
+
+    #[test]
+    fn test_validate_args() {
+        let parse = |extra: &[&str]| {
+            Args::parse_from(
+                std::iter::once("demo").chain(extra.iter().copied()),
+            )
+        };
+
+        assert_eq!(validate_args(&parse(&[])), Ok(()));
+        assert_eq!(validate_args(&parse(&["--estimate-fee"])), Ok(()));
+        assert_eq!(
+            validate_args(&parse(&[
+                "--funding-backend",
+                "esplora",
+                "--seed",
+                "1"
+            ])),
+            Ok(())
+        );
+        assert_eq!(
+            validate_args(&parse(&[
+                "--network",
+                "mainnet",
+                "--i-understand-mainnet"
+            ])),
+            Ok(())
+        );
+        let funding_txid =
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        assert_eq!(
+            validate_args(&parse(&[
+                "--funding-txid",
+                funding_txid,
+                "--funding-vout",
+                "1",
+                "--seed",
+                "1"
+            ])),
+            Ok(())
+        );
+
+        for bad in [
+            &["--estimate-fee", "--fee-rate", "2"][..],
+            &["--fee-rate", "0"],
+            &["--network", "mainnet"],
+            &["--network", "signet", "--i-understand-mainnet"],
+            &["--i-understand-mainnet"],
+            &["--network", "liquid"],
+            &["--confirmations", "0"],
+            &["--funding-confirmations", "0"],
+            &["--wait-funding-conf", "0"],
+            &["--funding-backend", "esplora"],
+            &["--funding-txid", funding_txid, "--seed", "1"],
+            &["--funding-vout", "1", "--seed", "1"],
+            &["--funding-txid", funding_txid, "--funding-vout", "1"],
+            &[
+                "--funding-txid",
+                funding_txid,
+                "--funding-vout",
+                "1",
+                "--seed",
+                "1",
+                "--funding-backend",
+                "esplora",
+            ],
+        ] {
+            assert!(validate_args(&parse(bad)).is_err(), "accepted {bad:?}");
+        }
+    }
+
+    #[test]
+    fn test_funding_address_qr() {
+        let address =
+            Address::from_str("bcrt1qz3fps2lxvrp5rqj8ucsqrzjx2c3md9gawqr3l6")
+                .unwrap()
+                .assume_checked();
+
+        let code = funding_address_qr(&address).unwrap();
+        assert!(!code.is_empty());
+        // One text row per two module rows, all of equal width
+        let widths =
+            code.lines().map(|l| l.chars().count()).collect::<Vec<_>>();
+        assert!(widths.len() > 10);
+        assert!(widths.iter().all(|w| *w == widths[0]));
+    }
+
+    fn empty_tx() -> Transaction {
+        Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        }
+    }
+
+    #[test]
+    fn test_replay_script() {
+        let funding_outpoint = OutPoint {
+            txid: Txid::from_str(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            )
+            .unwrap(),
+            vout: 1,
+        };
+        let chain = DemoChain {
+            funding_outpoint,
+            funding_value_sat: REQUIRED_AMOUNT_SAT,
+            x: 114,
+            nonce: 3543,
+            flow_id: 13,
+            f1_tx: empty_tx(),
+            f2_tx: empty_tx(),
+            spending_tx: empty_tx(),
+        };
+        let args = Args::parse_from(["demo", "--emit-replay", "--seed", "7"]);
+
+        let path = std::env::temp_dir().join(format!(
+            "collidervm_replay_{}/replay.sh",
+            std::process::id()
+        ));
+        write_replay_script(&chain, &args, &path).unwrap();
+        let script = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        assert!(script.starts_with("#!/bin/sh"));
+        assert!(script.contains(&funding_outpoint.txid.to_string()));
+        assert!(script.contains("--nonce 3543"));
+        assert!(script.contains("--x 114"));
+        assert!(script.contains("--seed 7"));
+        assert_eq!(script.matches("sendrawtransaction").count(), 3);
+        // Re-running and rebroadcasting are alternatives, not one sequence
+        let rerun = script.find("rerun)").unwrap();
+        let broadcast = script.find("broadcast)").unwrap();
+        assert!(script[rerun..broadcast].contains("cargo run"));
+        assert!(!script[rerun..broadcast].contains("sendrawtransaction"));
+        assert!(!script.contains(&args.rpc_password));
+
+        let args = Args::parse_from(["demo", "--emit-replay"]);
+        assert!(validate_args(&args).unwrap_err().contains("--seed"));
+    }
+
+    #[test]
+    fn test_no_files_writes_nothing() {
+        let output_dir = std::env::temp_dir()
+            .join(format!("collidervm_no_files_{}", std::process::id()));
+        let output_dir = output_dir.to_str().unwrap();
+        let args = Args::parse_from([
+            "demo",
+            "--no-files",
+            "--output-dir",
+            output_dir,
+        ]);
+
+        let tx = empty_tx();
+        assert_eq!(maybe_write_transaction(&args, &tx, "f1").unwrap(), None);
+
+        let demo_output = DemoOutput {
+            schema_version: DemoOutput::CURRENT_VERSION,
+            keys: KeyInfo { signers: vec![] },
+            transactions: None,
+            input_x: 114,
+            parameters: DemoParameters {
+                required_amount_sat: REQUIRED_AMOUNT_SAT,
+                l_param: L_PARAM,
+                b_param: B_PARAM,
+            },
+            metadata: BTreeMap::new(),
+        };
+        maybe_write_demo_output(&args, &demo_output).unwrap();
+
+        assert!(!std::path::Path::new(output_dir).exists());
+    }
+
+    #[test]
+    fn test_confirmation_targets_passed_to_waiter() {
+        let args = Args::parse_from([
+            "demo",
+            "--confirmations",
+            "2",
+            "--funding-confirmations",
+            "6",
+        ]);
+        let targets = ConfirmationTargets::from_args(&args);
+        assert_eq!(
+            targets,
+            ConfirmationTargets {
+                funding: 6,
+                chain: 2
+            }
+        );
+
+        let tx = empty_tx();
+        let funding_txid = tx.compute_txid();
+
+        let mut waited = Vec::new();
+        broadcast_chain(
+            &funding_txid,
+            [&tx, &tx, &tx],
+            &targets,
+            |tx| Ok(tx.compute_txid()),
+            |_, confirmations| {
+                waited.push(confirmations);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(waited, vec![6, 2, 2, 2]);
+
+        let args = Args::parse_from(["demo"]);
+        assert_eq!(
+            ConfirmationTargets::from_args(&args),
+            ConfirmationTargets {
+                funding: 1,
+                chain: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_wait_funding_conf_blocks_before_build() {
+        let funding_txid = empty_tx().compute_txid();
+
+        let mut waited = Vec::new();
+        let args = Args::parse_from(["demo", "--wait-funding-conf", "3"]);
+        wait_for_funding_before_build(
+            &funding_txid,
+            args.wait_funding_conf,
+            |txid, confirmations| {
+                waited.push((*txid, confirmations));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(waited, vec![(funding_txid, 3)]);
+
+        let args = Args::parse_from(["demo"]);
+        wait_for_funding_before_build(
+            &funding_txid,
+            args.wait_funding_conf,
+            |_, _| panic!("waited without --wait-funding-conf"),
+        )
+        .unwrap();
+
+        let err =
+            wait_for_funding_before_build(&funding_txid, Some(1), |_, _| {
+                anyhow::bail!("node unreachable")
+            })
+            .unwrap_err();
+        assert_eq!(err.to_string(), "node unreachable");
+    }
+
+    #[test]
+    fn test_params_token_round_trip() {
+        let args = Args::parse_from(["demo", "-x", "150", "-n", "signet"]);
+        let token = encode_params(&args);
+        assert!(
+            token
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        );
+        let decoded = decode_params(&token).unwrap();
+        assert_eq!((decoded.x, decoded.network.as_str()), (150, "signet"));
+        assert_eq!(encode_params(&decoded), token);
+
+        // A label travels as the x it derives
+        let args = Args::parse_from(["demo", "--label", "alice"]);
+        let decoded = decode_params(&encode_params(&args)).unwrap();
+        assert_eq!(decoded.x, input_from_label("alice"));
+
+        let foreign = SharedParams {
+            x: 150,
+            b: 8,
+            l: L_PARAM,
+            predicates: vec![ScriptPredicate::F1],
+            network: "regtest".to_string(),
+        };
+        let token =
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&foreign).unwrap());
+        assert!(decode_params(&token).is_err());
+        assert!(decode_params("not a token!").is_err());
+    }
+
+    #[test]
+    fn test_seeded_dry_runs_are_identical() {
+        let output_dir = std::env::temp_dir()
+            .join(format!("collidervm_seed_{}", std::process::id()));
+        let funding_outpoint = OutPoint {
+            txid: Txid::from_str(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            )
+            .unwrap(),
+            vout: 0,
+        };
+        let secp = Secp256k1::new();
+
+        let run = |seed: &str| {
+            let args = Args::parse_from([
+                "demo",
+                "--dry-run",
+                "--seed",
+                seed,
+                "--output-dir",
+                output_dir.to_str().unwrap(),
+            ]);
+            let network = wrap_network(&args.network);
+            let sk_signers = signer_keys(args.seed, args.single_key);
+            let pk_signer = signer_pubkey(&sk_signers).unwrap();
+
+            let chain = build_demo_chain(
+                &args,
+                &secp,
+                network,
+                &sk_signers,
+                &pk_signer,
+                funding_outpoint,
+                Amount::from_sat(REQUIRED_AMOUNT_SAT),
+                DEFAULT_FEE_RATE,
+                None,
+            )
+            .unwrap();
+            let metadata = demo_metadata(&args, network, None);
+            write_demo_files(&args, &chain, &sk_signers, network, metadata)
+                .unwrap();
+            std::fs::read(output_dir.join(&args.output_file)).unwrap()
+        };
+
+        let first = run("42");
+        let second = run("42");
+        let other = run("43");
+        std::fs::remove_dir_all(&output_dir).unwrap();
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn test_dry_run_output_replays() {
+        let output_dir = std::env::temp_dir()
+            .join(format!("collidervm_replay_out_{}", std::process::id()));
+        let args = Args::parse_from([
+            "demo",
+            "--dry-run",
+            "--seed",
+            "42",
+            "--output-dir",
+            output_dir.to_str().unwrap(),
+        ]);
+        let network = wrap_network(&args.network);
+        let sk_signers = signer_keys(args.seed, args.single_key);
+        let pk_signer = signer_pubkey(&sk_signers).unwrap();
+        let funding_outpoint = OutPoint {
+            txid: empty_tx().compute_txid(),
+            vout: 0,
+        };
+        let chain = build_demo_chain(
+            &args,
+            &Secp256k1::new(),
+            network,
+            &sk_signers,
+            &pk_signer,
+            funding_outpoint,
+            Amount::from_sat(REQUIRED_AMOUNT_SAT),
+            DEFAULT_FEE_RATE,
             None,
+        )
+        .unwrap();
+        write_demo_files(&args, &chain, &sk_signers, network, BTreeMap::new())
+            .unwrap();
+        let path = output_dir.join(&args.output_file);
+
+        let checks = replay_demo_output(&path, None).unwrap();
+        assert_eq!(checks.len(), 6);
+        for (step, result) in &checks {
+            assert_eq!(result, &Ok(()), "{step}");
+        }
+
+        // Unusable parameters fail the replay instead of panicking
+        let mut output = load_demo_output(&path).unwrap();
+        output.parameters.b_param = 30;
+        write_demo_output_to_file(
+            &output,
+            output_dir.to_str().unwrap(),
+            &args.output_file,
+        )
+        .unwrap();
+        let checks = replay_demo_output(&path, None).unwrap();
+        std::fs::remove_dir_all(&output_dir).unwrap();
+        assert!(matches!(checks.as_slice(), [("parameters", Err(_))]));
+    }
+
+    #[test]
+    fn test_psbt_writes_unsigned_f1_f2() {
+        let output_dir = std::env::temp_dir()
+            .join(format!("collidervm_demo_psbt_{}", std::process::id()));
+        let args = Args::parse_from([
+            "demo",
+            "--dry-run",
+            "--psbt",
+            "--seed",
+            "3",
+            "--output-dir",
+            output_dir.to_str().unwrap(),
+        ]);
+        let network = wrap_network(&args.network);
+        let sk_signers = signer_keys(args.seed, args.single_key);
+        let pk_signer = signer_pubkey(&sk_signers).unwrap();
+        let funding_outpoint = OutPoint {
+            txid: empty_tx().compute_txid(),
+            vout: 0,
+        };
+        let chain = build_demo_chain(
+            &args,
+            &Secp256k1::new(),
+            network,
+            &sk_signers,
+            &pk_signer,
+            funding_outpoint,
+            Amount::from_sat(REQUIRED_AMOUNT_SAT),
+            DEFAULT_FEE_RATE,
             None,
         )
-        .map_err(|err| panic!("Error: {err}"))
         .unwrap();
 
-    let confirmed_funding_tx =
-        rpc_client.get_raw_transaction(&txid, None).unwrap();
+        for (name, tx) in [("f1", &chain.f1_tx), ("f2", &chain.f2_tx)] {
+            let bytes =
+                std::fs::read(output_dir.join(format!("{name}.psbt"))).unwrap();
+            let psbt = bitcoin::Psbt::deserialize(&bytes).unwrap();
+            // Same transaction, without the witness the demo signed in
+            assert_eq!(psbt.unsigned_tx.compute_txid(), tx.compute_txid());
+            assert!(psbt.unsigned_tx.input[0].witness.is_empty());
+            assert_eq!(psbt.inputs[0].tap_scripts.len(), 1);
+            assert!(psbt.inputs[0].witness_utxo.is_some());
+        }
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
 
-    println!("▶️  Pushed founding tx: {txid}");
+    #[test]
+    fn test_demo_metadata() {
+        let args = Args::parse_from([
+            "demo",
+            "--meta",
+            "operator=alice",
+            "--meta",
+            "note=a=b",
+        ]);
+        let network = wrap_network(&args.network);
+        let timestamp =
+            UNIX_EPOCH + std::time::Duration::from_secs(951_782_400);
+        let metadata = demo_metadata(&args, network, Some(timestamp));
 
-    let tx_out_sp_0 = &confirmed_funding_tx.output[0].script_pubkey;
-    let vout = if *tx_out_sp_0 == funding_address.script_pubkey() {
-        0
-    } else {
-        1
-    };
+        assert_eq!(metadata["network"], "regtest");
+        assert_eq!(metadata["crate_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(metadata["timestamp_utc"], "2000-02-29T00:00:00Z");
+        assert_eq!(metadata["operator"], "alice");
+        assert_eq!(metadata["note"], "a=b");
+
+        assert!(
+            !demo_metadata(&args, network, None).contains_key("timestamp_utc")
+        );
+        assert_eq!(format_utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_utc(1_700_000_000), "2023-11-14T22:13:20Z");
+        assert!(parse_meta_entry("novalue").is_err());
+        assert!(parse_meta_entry("=value").is_err());
+    }
 
-    OutPoint { txid, vout }
+    #[test]
+    fn test_build_rejects_x_failing_a_predicate() {
+        let args = Args::parse_from(["demo", "--x", "50"]);
+        let network = wrap_network(&args.network);
+        let sk_signers = signer_keys(Some(1), false);
+        let pk_signer = signer_pubkey(&sk_signers).unwrap();
+        let funding_outpoint = OutPoint {
+            txid: empty_tx().compute_txid(),
+            vout: 0,
+        };
+
+        let err = build_demo_chain(
+            &args,
+            &Secp256k1::new(),
+            network,
+            &sk_signers,
+            &pk_signer,
+            funding_outpoint,
+            Amount::from_sat(REQUIRED_AMOUNT_SAT),
+            DEFAULT_FEE_RATE,
+            None,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("does not satisfy F1"), "{err}");
+    }
+
+    #[test]
+    fn test_single_key_chain_verifies() {
+        let args = Args::parse_from(["demo", "--single-key", "--seed", "5"]);
+        let network = wrap_network(&args.network);
+        let secp = Secp256k1::new();
+        let sk_signers = signer_keys(args.seed, args.single_key);
+        assert_eq!(sk_signers.len(), 1);
+        let pk_signer = signer_pubkey(&sk_signers).unwrap();
+        assert_eq!(pk_signer, pubkey_inner_from(sk_signers[0].1));
+
+        let funding_outpoint = OutPoint {
+            txid: empty_tx().compute_txid(),
+            vout: 0,
+        };
+        let chain = build_demo_chain(
+            &args,
+            &secp,
+            network,
+            &sk_signers,
+            &pk_signer,
+            funding_outpoint,
+            Amount::from_sat(REQUIRED_AMOUNT_SAT),
+            DEFAULT_FEE_RATE,
+            None,
+        )
+        .unwrap();
+        validate_chain_links(&chain).unwrap();
+
+        // Every script-path signature checks against the single key
+        let funding_prevout = bitcoin::TxOut {
+            value: bitcoin::Amount::from_sat(chain.funding_value_sat),
+            script_pubkey: create_funding_taproot_address(
+                &pk_signer, &secp, network, None,
+            )
+            .script_pubkey(),
+        };
+        let xonly = XOnlyPublicKey::from(pk_signer);
+        for (tx, prevout) in [
+            (&chain.f1_tx, funding_prevout),
+            (&chain.f2_tx, chain.f1_tx.output[0].clone()),
+            (&chain.spending_tx, chain.f2_tx.output[0].clone()),
+        ] {
+            let witness = &tx.input[0].witness;
+            let leaf = witness.tapscript().unwrap().to_owned();
+            let message = taproot_leaf_sighash(tx, &[prevout], &leaf).unwrap();
+            let signature = bitcoin::secp256k1::schnorr::Signature::from_slice(
+                &witness[witness.len() - 3],
+            )
+            .unwrap();
+            secp.verify_schnorr(&signature, &message, &xonly).unwrap();
+        }
+    }
 }
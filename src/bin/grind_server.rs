@@ -0,0 +1,182 @@
+//! Minimal HTTP/JSON front end for the ColliderVM nonce grinder.
+//!
+//! Exposes a single endpoint:
+//!
+//! ```text
+//! POST /grind  {"input": 114, "b": 16, "l": 4}
+//!   => 200     {"nonce": 3543, "flow_id": 13, "attempts": 3544}
+//! ```
+//!
+//! Requests are handled one at a time. Bodies larger than
+//! [`MAX_BODY_BYTES`] and parameters asking for more than
+//! `2^MAX_WORK_BITS` expected hashes are rejected.
+//!
+//! Usage: cargo run --release --features server --bin grind_server -- --port 8080
+
+use clap::Parser;
+use collidervm_toy::core::{BBits, LBits, find_valid_nonce};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Largest request body accepted, in bytes
+const MAX_BODY_BYTES: u64 = 1024;
+/// Largest `B - L` accepted, bounding the expected work per request
+const MAX_WORK_BITS: usize = 24;
+
+#[derive(Parser, Debug)]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+}
+
+#[derive(Deserialize, Debug)]
+struct GrindRequest {
+    /// Input value `x`
+    input: u32,
+    /// `B`: hash prefix length in bits
+    b: usize,
+    /// `L`: the flow set `D` has `2^L` flows
+    l: usize,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct GrindResponse {
+    nonce: u64,
+    flow_id: u32,
+    /// Hashes computed, the search scans nonces from 0
+    attempts: u64,
+}
+
+/// Parses a `/grind` request body and runs the nonce search.
+///
+/// Errors are returned as human-readable messages, to be sent back as a
+/// `400 Bad Request`.
+fn handle_grind(body: &[u8]) -> Result<GrindResponse, String> {
+    let request: GrindRequest = serde_json::from_slice(body)
+        .map_err(|e| format!("invalid request body: {e}"))?;
+
+    if request.b == 0 || request.b > 32 || request.b % 4 != 0 {
+        return Err(format!(
+            "b must be a multiple of 4 in [4, 32], got {}",
+            request.b
+        ));
+    }
+    if request.l > request.b {
+        return Err(format!(
+            "l must not exceed b, got l={} b={}",
+            request.l, request.b
+        ));
+    }
+    if request.b - request.l > MAX_WORK_BITS {
+        return Err(format!(
+            "b - l must be at most {MAX_WORK_BITS}, got {}",
+            request.b - request.l
+        ));
+    }
+
+    let (nonce, flow_id) =
+        find_valid_nonce(request.input, BBits(request.b), LBits(request.l))
+            .map_err(|e| e.to_string())?;
+    Ok(GrindResponse {
+        nonce,
+        flow_id,
+        attempts: nonce + 1,
+    })
+}
+
+fn json_response(
+    status: u16,
+    body: String,
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    let content_type =
+        Header::from_bytes("Content-Type", "application/json").unwrap();
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+fn respond(mut request: Request) -> std::io::Result<()> {
+    if request.url() != "/grind" {
+        return request.respond(json_response(404, error_body("not found")));
+    }
+    if *request.method() != Method::Post {
+        return request
+            .respond(json_response(405, error_body("use POST /grind")));
+    }
+
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_BYTES + 1)
+        .read_to_end(&mut body)?;
+    if body.len() as u64 > MAX_BODY_BYTES {
+        return request.respond(json_response(
+            413,
+            error_body(&format!("body exceeds {MAX_BODY_BYTES} bytes")),
+        ));
+    }
+
+    let response = match handle_grind(&body) {
+        Ok(grind) => json_response(
+            200,
+            serde_json::to_string(&grind).expect("serializable response"),
+        ),
+        Err(err) => json_response(400, error_body(&err)),
+    };
+    request.respond(response)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let addr = format!("{}:{}", args.host, args.port);
+    let server = Server::http(&addr).map_err(|e| anyhow::anyhow!("{e}"))?;
+    println!("[+] Listening on http://{addr}/grind");
+
+    for request in server.incoming_requests() {
+        if let Err(e) = respond(request) {
+            eprintln!("[!] Error handling request: {e}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collidervm_toy::core::{calculate_flow_id, truncate_flow_id};
+
+    #[test]
+    fn test_handle_grind() {
+        let response =
+            handle_grind(br#"{"input": 114, "b": 16, "l": 4}"#).unwrap();
+        assert!(response.flow_id < 1 << 4);
+        assert_eq!(
+            truncate_flow_id(calculate_flow_id(114, response.nonce), 16),
+            response.flow_id
+        );
+        assert_eq!(response.attempts, response.nonce + 1);
+
+        let json = serde_json::to_value(&response).unwrap();
+        for key in ["nonce", "flow_id", "attempts"] {
+            assert!(json.get(key).is_some(), "missing {key}");
+        }
+
+        for bad in [
+            &b"not json"[..],
+            br#"{"input": 114, "b": 16}"#,
+            br#"{"input": 114, "b": 40, "l": 4}"#,
+            br#"{"input": 114, "b": 8, "l": 16}"#,
+            br#"{"input": 114, "b": 32, "l": 0}"#,
+            br#"{"input": 114, "b": 10, "l": 4}"#,
+        ] {
+            assert!(handle_grind(bad).is_err(), "accepted {bad:?}");
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Offline checks on the outputs of the ColliderVM demo.
+//!
+//! `--diff a.json b.json` compares two `demo.json` files written by the demo
+//! and lists the fields that changed (input, nonce, flow id, txids and
+//! parameters), e.g. before and after a code change.
+//!
+//! `--replay demo.json` re-checks a run without bitcoind: it rebuilds the F1
+//! and F2 locking scripts from the recorded input, nonce, flow id,
+//! parameters and signer keys, and executes the witness of each spend
+//! against them, signature included (see `output::replay_demo_output`).
+//!
+//! Usage: cargo run --bin verify -- --diff target/demo/demo.json other/demo.json
+//!        cargo run --bin verify -- --replay target/demo/demo.json
+
+use clap::{ArgGroup, Parser};
+use collidervm_toy::output::{
+    FieldDiff, diff_demo_outputs, load_demo_output, replay_demo_output,
+};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+#[command(group(ArgGroup::new("mode").required(true).args(["diff", "replay"])))]
+struct Args {
+    /// Compare two demo.json files and print the fields that differ
+    #[arg(long, num_args = 2, value_names = ["A", "B"])]
+    diff: Vec<PathBuf>,
+
+    /// Check the transactions of a demo.json against the scripts its
+    /// recorded parameters lock them with
+    #[arg(long, value_name = "DEMO_JSON")]
+    replay: Option<PathBuf>,
+
+    /// Directory holding f1.tx, f2.tx and spending.tx, instead of the paths
+    /// recorded in the demo.json
+    #[arg(long, requires = "replay")]
+    tx_dir: Option<PathBuf>,
+}
+
+fn format_value(value: &Option<String>) -> &str {
+    value.as_deref().unwrap_or("<missing>")
+}
+
+fn format_diff(diff: &FieldDiff) -> String {
+    format!(
+        "{}: {} -> {}",
+        diff.field,
+        format_value(&diff.left),
+        format_value(&diff.right)
+    )
+}
+
+/// Checks every step of the run in `path`, printing one line per step, and
+/// returns whether all of them passed.
+fn replay(path: &Path, tx_dir: Option<&Path>) -> anyhow::Result<bool> {
+    let mut passed = true;
+    for (step, result) in replay_demo_output(path, tx_dir)? {
+        match result {
+            Ok(()) => println!("✅ {step}"),
+            Err(reason) => {
+                println!("❌ {step}: {reason}");
+                passed = false;
+            }
+        }
+    }
+    Ok(passed)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    if let Some(path) = &args.replay {
+        if !replay(path, args.tx_dir.as_deref())? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let [a, b] = [&args.diff[0], &args.diff[1]];
+    let diffs = diff_demo_outputs(&load_demo_output(a)?, &load_demo_output(b)?);
+
+    if diffs.is_empty() {
+        println!("✅ {} and {} match", a.display(), b.display());
+        return Ok(());
+    }
+    println!("{} field(s) differ:", diffs.len());
+    for diff in &diffs {
+        println!("  {}", format_diff(diff));
+    }
+    std::process::exit(1);
+}
@@ -1,13 +1,21 @@
-use crate::utils::{NonceSearchProgress, encode_scriptnum};
+use crate::utils::{
+    ConsoleProgress, NonceSearchProgress, ProgressStyleConfig, encode_scriptnum,
+};
+use bitcoin::consensus::encode::{deserialize_hex, serialize_hex};
 use bitcoin::{
-    Amount, PublicKey, XOnlyPublicKey,
-    blockdata::script::{Builder, ScriptBuf},
-    opcodes::{self, OP_TRUE},
+    Amount, PublicKey, TapLeafHash, Transaction, TxOut, Witness,
+    XOnlyPublicKey, absolute,
+    blockdata::script::{Builder, PushBytesBuf, ScriptBuf},
+    hashes::Hash,
+    opcodes::{self, OP_TRUE, Opcode},
+    taproot::TAPROOT_CONTROL_BASE_SIZE,
+    transaction::Version,
 };
 use bitcoin_hashes::{HashEngine, sha256};
 pub use bitcoin_script::builder::StructuredScript as Script;
 pub use bitcoin_script::script;
 use bitcoin_script_stack::optimizer;
+use bitcoin_scriptexec::{Exec, ExecCtx, Options, TxTemplate};
 use bitvm::{
     execute_script_buf,
     hash::blake3::{
@@ -16,7 +24,11 @@ use bitvm::{
 };
 use blake3::Hasher;
 use indicatif::{ProgressBar, ProgressStyle};
-use secp256k1::Message;
+use secp256k1::{Message, schnorr};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
 /// F1 threshold: x must be > 100
@@ -24,419 +36,4242 @@ pub const F1_THRESHOLD: u32 = 100;
 /// F2 threshold: x must be < 200
 pub const F2_THRESHOLD: u32 = 200;
 
-/// Create a minimal sighash for demonstration
-pub fn create_toy_sighash_message(
-    locking_script: &ScriptBuf,
-    value: Amount,
-) -> Message {
-    let mut engine = sha256::HashEngine::default();
-    engine.input(&locking_script.to_bytes());
-    engine.input(&value.to_sat().to_le_bytes());
-    let digest = sha256::Hash::from_engine(engine);
-    Message::from_digest(digest.to_byte_array())
+/// Number of steps (sub-functions) in each flow: F1 and F2
+pub const NUM_STEPS: usize = 2;
+
+/// Size in bytes of a BIP340 Schnorr signature
+pub const SCHNORR_SIG_SIZE: usize = 64;
+
+/// ColliderVM protocol parameters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ColliderVmConfig {
+    /// Number of signers
+    pub n: usize,
+    /// Number of operators
+    pub m: usize,
+    /// `L`: the flow set `D` has `2^L` flows
+    pub l: usize,
+    /// `B`: hash prefix length in bits
+    pub b: usize,
+    /// Size of the signer subsets that presign each flow
+    pub k: usize,
 }
 
-/// Calculate H(x||nonce)|_B => flow_id
-pub fn calculate_flow_id(input: u32, nonce: u64) -> u32 {
-    let mut hasher = Hasher::new();
-    hasher.update(&input.to_le_bytes());
-    hasher.update(&nonce.to_le_bytes());
-    let hash = hasher.finalize();
+impl ColliderVmConfig {
+    /// Builds a config, rejecting parameters that [`Self::validate`] would.
+    pub fn new(
+        n: usize,
+        m: usize,
+        l: usize,
+        b: usize,
+        k: usize,
+    ) -> Result<Self, CoreError> {
+        let config = Self { n, m, l, b, k };
+        config.validate()?;
+        Ok(config)
+    }
 
-    let mut fourb = [0u8; 4];
-    fourb.copy_from_slice(&hash.as_bytes()[0..4]);
+    /// Checks that there is at least one signer and operator, that the
+    /// signer subsets fit in `n`, and that `L` and `B` describe a flow space
+    /// `D` whose `2^L` flows can be enumerated with `u32` flow ids.
+    pub fn validate(&self) -> Result<(), CoreError> {
+        if self.n == 0 {
+            return Err(CoreError::NoSigners);
+        }
+        if self.m == 0 {
+            return Err(CoreError::NoOperators);
+        }
+        if self.k == 0 || self.k > self.n {
+            return Err(CoreError::SignerSubsetOutOfRange {
+                k: self.k,
+                n: self.n,
+            });
+        }
+        if self.b > 32 {
+            return Err(CoreError::HashPrefixOutOfRange {
+                prefix: self.b,
+                max: 32,
+            });
+        }
+        check_flow_params(self.b, self.l)?;
+        if self.l >= 32 {
+            return Err(CoreError::FlowSpaceOutOfRange { l_bits: self.l });
+        }
+        Ok(())
+    }
+}
 
-    u32::from_le_bytes(fourb)
+/// Error from the flow parameters or the off-chain nonce search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoreError {
+    /// `B` is longer than the 32-bit flow ids
+    HashPrefixOutOfRange { prefix: usize, max: usize },
+    /// `B` does not end on a digest nibble
+    HashPrefixMisaligned { b_bits: usize },
+    /// A config's `2^L` flows cannot be enumerated as `u32` flow ids
+    FlowSpaceOutOfRange { l_bits: usize },
+    /// `D` would not fit in the `B`-bit hash prefix space
+    FlowSpaceExceedsPrefix { l_bits: usize, b_bits: usize },
+    /// The nonce counter wrapped around
+    NonceOverflow,
+    /// No valid nonce within 100x the expected attempts
+    NonceSearchExhausted {
+        input: u64,
+        attempts: u64,
+        expected: u64,
+    },
+    /// A [`find_valid_nonce_from`] slice ended without a valid nonce; the
+    /// search resumes from `last_nonce`, the first one not tried
+    SearchIncomplete { last_nonce: u64 },
+    /// The progress bar style could not be built
+    ProgressStyle(String),
+    /// No input satisfies every step predicate of a flow
+    InfeasiblePredicates(Vec<ScriptPredicate>),
+    /// The config has no signers
+    NoSigners,
+    /// The config has no operators
+    NoOperators,
+    /// `k` is not in `[1, n]`
+    SignerSubsetOutOfRange { k: usize, n: usize },
+    /// An `OP_CAT` script was requested without opting in to it
+    OpCatNotAllowed,
+    /// A multisig threshold is not in `[1, signers]`
+    ThresholdOutOfRange { threshold: usize, signers: usize },
+    /// The presigning storage estimate does not fit in a `usize`
+    StorageOverflow,
+    /// A limb length the step scripts cannot rebuild x from
+    LimbLenUnsupported { limb_len: u8 },
 }
 
-/// Finds a valid nonce `r` for a given input `x` such that `H(x, r)|_B` falls within the set `D`. (Off-chain logic)
-///
-/// This simulates the work performed by an Operator during the online phase.
-/// The expected number of hash attempts is `2^(B-L)`.
-///
-/// # Arguments
-/// * `input` - The input value `x`.
-/// * `b_bits` - The hash prefix length `B`.
-/// * `l_bits` - The parameter `L` defining the size of set `D`.
-///
-/// # Returns
-/// * `Ok((u64, u32))` - A tuple containing the found nonce `r` and the corresponding flow ID `d`.
-/// * `Err(String)` - An error if a nonce cannot be found (e.g., due to overflow or excessive attempts).
-pub fn find_valid_nonce(
-    input: u32,
-    b_bits: usize,
-    l_bits: usize,
-) -> Result<(u64, u32), String> {
-    let mut nonce: u64 = 0;
+impl std::fmt::Display for CoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoreError::HashPrefixOutOfRange { prefix, max } => {
+                write!(f, "B must be at most {max}, got {prefix}")
+            }
+            CoreError::HashPrefixMisaligned { b_bits } => {
+                write!(f, "B must be a multiple of 4, got {b_bits}")
+            }
+            CoreError::FlowSpaceOutOfRange { l_bits } => {
+                write!(f, "L must be less than 32, got {l_bits}")
+            }
+            CoreError::FlowSpaceExceedsPrefix { l_bits, b_bits } => {
+                write!(f, "L must not exceed B, got L={l_bits} B={b_bits}")
+            }
+            CoreError::NonceOverflow => {
+                write!(f, "Nonce overflowed u64::MAX while searching")
+            }
+            CoreError::NonceSearchExhausted {
+                input,
+                attempts,
+                expected,
+            } => write!(
+                f,
+                "Could not find a valid nonce for x = {input} after {attempts} attempts (expected ~{expected})"
+            ),
+            CoreError::SearchIncomplete { last_nonce } => write!(
+                f,
+                "No valid nonce below {last_nonce}, resume the search from there"
+            ),
+            CoreError::ProgressStyle(err) => write!(f, "{err}"),
+            CoreError::InfeasiblePredicates(predicates) => {
+                write!(f, "no input satisfies all of {predicates:?}")
+            }
+            CoreError::NoSigners => write!(f, "n must be at least 1"),
+            CoreError::NoOperators => write!(f, "m must be at least 1"),
+            CoreError::SignerSubsetOutOfRange { k, n } => {
+                write!(f, "k must be in [1, n], got k={k} n={n}")
+            }
+            CoreError::OpCatNotAllowed => write!(
+                f,
+                "SHA-256 scripts need OP_CAT, which is anyone-can-spend where it is not active"
+            ),
+            CoreError::ThresholdOutOfRange { threshold, signers } => write!(
+                f,
+                "threshold must be in [1, {signers}], got {threshold}"
+            ),
+            CoreError::StorageOverflow => {
+                write!(f, "presigning storage does not fit in usize")
+            }
+            CoreError::LimbLenUnsupported { limb_len } => {
+                write!(f, "limb_len must be 4, 8 or 16, got {limb_len}")
+            }
+        }
+    }
+}
 
-    // Calculate expected number of attempts (2^(B-L)) for progress reporting
-    let expected_attempts: u64 = 1u64
-        .checked_shl((b_bits.saturating_sub(l_bits)) as u32) // Calculate 2^(B-L)
-        .unwrap_or(u64::MAX);
+impl std::error::Error for CoreError {}
 
-    println!(
-        "Finding valid nonce (L={}, B={})... (Expected work: ~2^{} = {} hashes)",
-        l_bits,
-        b_bits,
-        b_bits.saturating_sub(l_bits),
-        expected_attempts
-    );
+/// A nibble-aligned `B` of at most 32 bits is what the locking scripts can
+/// compare, and `L <= B` keeps `D` inside the `B`-bit hash prefix space.
+/// `L = B = 32` is a valid search, where every prefix is in `D`.
+fn check_flow_params(b_bits: usize, l_bits: usize) -> Result<(), CoreError> {
+    if b_bits % 4 != 0 {
+        return Err(CoreError::HashPrefixMisaligned { b_bits });
+    }
+    if b_bits > 32 {
+        return Err(CoreError::HashPrefixOutOfRange {
+            prefix: b_bits,
+            max: 32,
+        });
+    }
+    if l_bits > b_bits {
+        return Err(CoreError::FlowSpaceExceedsPrefix { l_bits, b_bits });
+    }
+    Ok(())
+}
 
-    let mut progress = NonceSearchProgress::new(expected_attempts);
+/// Whether `flow_id` lies in `D = [0, 2^L)`.
+///
+/// The bound is computed in `u64`: as a `u32` it would wrap to 0 for
+/// `L = 32` and reject every flow, when all of them are in `D`.
+pub fn flow_id_in_range(flow_id: u32, l_bits: usize) -> bool {
+    l_bits >= 32 || u64::from(flow_id) < 1u64 << l_bits
+}
 
-    let max_flow_id = (1u64 << l_bits) as u32;
-    let mask_b = if b_bits >= 32 {
-        u32::MAX
-    } else {
-        (1u32 << b_bits) - 1
-    };
+/// `B`: hash prefix length in bits, a multiple of 4 up to 32.
+///
+/// Kept apart from [`LBits`] so the two cannot be swapped in a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BBits(pub usize);
 
-    loop {
-        // Always get the prefix and hash
-        let hash = calculate_flow_id(input, nonce);
-        let prefix_b = hash & mask_b;
-        if prefix_b < max_flow_id {
-            // Found a nonce `r` such that H(x, r)|_B = d ∈ D
-            progress.success(prefix_b, nonce);
-            return Ok((nonce, prefix_b));
-        } else {
-            // Hash prefix was outside the valid range [0, 2^L - 1], try next nonce
-            progress.update(nonce);
+impl BBits {
+    pub fn new(b_bits: usize) -> Result<Self, CoreError> {
+        if b_bits > 32 {
+            return Err(CoreError::HashPrefixOutOfRange {
+                prefix: b_bits,
+                max: 32,
+            });
+        }
+        if b_bits % 4 != 0 {
+            return Err(CoreError::HashPrefixMisaligned { b_bits });
+        }
+        Ok(Self(b_bits))
+    }
+}
+
+/// `L`: the flow set `D` has `2^L` flows. Bounded by its [`BBits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LBits(pub usize);
+
+impl LBits {
+    pub fn new(l_bits: usize, b_bits: BBits) -> Result<Self, CoreError> {
+        check_flow_params(b_bits.0, l_bits)?;
+        Ok(Self(l_bits))
+    }
+}
 
-            // Increment nonce, checking for overflow
-            nonce = nonce.checked_add(1).ok_or_else(|| {
-                "Nonce overflowed u64::MAX while searching".to_string()
-            })?;
+/// A presigned transaction template for one step of a flow, together with
+/// the signatures the signers produced over it during the offline phase.
+///
+/// Serializes with every field hex-encoded, so the signers can hand it to
+/// an operator.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "PresignedStepHex", try_from = "PresignedStepHex")]
+pub struct PresignedStep {
+    /// Transaction template the signers committed to
+    pub tx_template: Transaction,
+    /// Outputs spent by `tx_template`, needed to recompute the sighash
+    pub prevouts: Vec<TxOut>,
+    /// Tapscript leaf spent by input 0 of `tx_template`
+    pub spent_leaf: ScriptBuf,
+    /// Sighash message the signatures commit to
+    pub sighash_message: Message,
+    /// Signatures keyed by the serialized x-only pubkey of their signer
+    pub signatures: HashMap<Vec<u8>, schnorr::Signature>,
+    /// Limb length of the message `spent_leaf` takes in its witness
+    pub limb_len: LimbLen,
+}
 
-            // Safety break after excessive attempts (e.g., 100x expected work)
-            // This prevents infinite loops in case of configuration errors.
-            if nonce > expected_attempts.saturating_mul(100) {
-                progress.failure();
-                return Err(format!(
-                    "Could not find a valid nonce after {nonce} attempts (expected ~{expected_attempts})",
-                ));
+impl PresignedStep {
+    /// Worst-case weight of the step once broadcast: `tx_template` with the
+    /// script-path witness of input 0 filled in (message limbs of
+    /// `limb_len` bits at their largest, signature, `spent_leaf` and a
+    /// single-leaf control block).
+    pub fn weight(&self) -> usize {
+        let mut tx = self.tx_template.clone();
+        if let Some(input) = tx.input.first_mut() {
+            let mut witness = Witness::new();
+            let limb = self.limb_len.max_limb_push();
+            for _ in message_to_witness_limbs_with_limb(0, 0, self.limb_len) {
+                witness.push(&limb);
             }
+            witness.push([0u8; SCHNORR_SIG_SIZE]);
+            witness.push(self.spent_leaf.as_bytes());
+            witness.push([0u8; TAPROOT_CONTROL_BASE_SIZE]);
+            input.witness = witness;
         }
+        tx.weight().to_wu() as usize
     }
-}
 
-/// Convert flow_id => little-endian prefix of length B/8
-pub fn flow_id_to_prefix_bytes(flow_id: u32, b_bits: usize) -> Vec<u8> {
-    assert!(b_bits <= 32);
-    assert_eq!(b_bits % 8, 0, "b_bits must be multiple of 8");
-    let prefix_len = b_bits / 8;
-    let le4 = flow_id.to_le_bytes();
-    let flow_id_prefix_bytes = le4[..prefix_len].to_vec();
-    // Transform to nibbles
-    // For example: [0x12, 0x34] => [0x1, 0x2, 0x3, 0x4]
-    // Or: [0x0d, 0x00] => [0x0, 0xd, 0x0, 0x0]
-    let mut nibbles = Vec::with_capacity(flow_id_prefix_bytes.len() * 2);
-    for &byte in &flow_id_prefix_bytes {
-        // Extract high nibble (first 4 bits)
-        nibbles.push((byte >> 4) & 0x0F);
-        // Extract low nibble (last 4 bits)
-        nibbles.push(byte & 0x0F);
+    /// The signers of `required`, in order, with no signature in
+    /// `signatures` yet.
+    pub fn missing_signers(&self, required: &[PublicKey]) -> Vec<PublicKey> {
+        required
+            .iter()
+            .filter(|pk| {
+                let key = XOnlyPublicKey::from(pk.inner).serialize().to_vec();
+                !self.signatures.contains_key(&key)
+            })
+            .copied()
+            .collect()
     }
-    nibbles
 }
 
-/// Helper: combine scripts (by just concatenating the raw bytes).
-pub fn combine_scripts(fragments: &[ScriptBuf]) -> ScriptBuf {
-    let mut combined = Vec::new();
-    for frag in fragments {
-        combined.extend(frag.to_bytes());
+/// Serialized form of a [`PresignedStep`]: the transactions consensus
+/// encoded, and the leaf, message, signer keys and signatures raw, all as
+/// hex. Signatures are sorted by signer key, so the output is stable. The
+/// limb length is a plain number, [`LIMB_LEN`] when absent.
+#[derive(Serialize, Deserialize)]
+struct PresignedStepHex {
+    tx_template: String,
+    prevouts: Vec<String>,
+    spent_leaf: String,
+    sighash_message: String,
+    signatures: BTreeMap<String, String>,
+    #[serde(default = "default_limb_len")]
+    limb_len: u8,
+}
+
+fn default_limb_len() -> u8 {
+    LIMB_LEN.bits()
+}
+
+impl From<PresignedStep> for PresignedStepHex {
+    fn from(step: PresignedStep) -> Self {
+        Self {
+            tx_template: serialize_hex(&step.tx_template),
+            prevouts: step.prevouts.iter().map(serialize_hex).collect(),
+            spent_leaf: hex::encode(step.spent_leaf.as_bytes()),
+            sighash_message: hex::encode(step.sighash_message.as_ref()),
+            signatures: step
+                .signatures
+                .iter()
+                .map(|(key, sig)| {
+                    (hex::encode(key), hex::encode(sig.serialize()))
+                })
+                .collect(),
+            limb_len: step.limb_len.bits(),
+        }
     }
-    ScriptBuf::from_bytes(combined)
 }
 
-/// A small helper script that pushes `prefix_data` and does OP_EQUALVERIFY
-/// This is used to check if the top of the stack matches the prefix
-/// For example, if the content of the stack is:
-/// [0x00, 0x0d, 0x00, 0x00]
-/// Then the script needs to check equality of each byte.
-/// We need to take care of the fact that the prefix is now in nibbles.
-/// Also the ordering of elements on the stack.
-/// We need to push the prefix in reverse order to the stack.
-pub fn build_prefix_equalverify(prefix_data: &[u8]) -> ScriptBuf {
-    let mut b = Builder::new();
+impl TryFrom<PresignedStepHex> for PresignedStep {
+    type Error = String;
 
-    // Check each nibble individually, pushing in reverse order to match stack evaluation
-    for &nibble in prefix_data.iter().rev() {
-        // For the nibble value, use push_int for accurate stack comparison
-        b = b.push_int(nibble as i64);
-        b = b.push_opcode(opcodes::all::OP_EQUALVERIFY);
+    fn try_from(step: PresignedStepHex) -> Result<Self, Self::Error> {
+        let decode = |field: &str, value: &str| {
+            hex::decode(value).map_err(|e| format!("{field}: {e}"))
+        };
+        Ok(Self {
+            tx_template: deserialize_hex(&step.tx_template)
+                .map_err(|e| format!("tx_template: {e}"))?,
+            prevouts: step
+                .prevouts
+                .iter()
+                .map(|prevout| deserialize_hex(prevout))
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("prevouts: {e}"))?,
+            spent_leaf: ScriptBuf::from_bytes(decode(
+                "spent_leaf",
+                &step.spent_leaf,
+            )?),
+            sighash_message: Message::from_digest_slice(&decode(
+                "sighash_message",
+                &step.sighash_message,
+            )?)
+            .map_err(|e| format!("sighash_message: {e}"))?,
+            signatures: step
+                .signatures
+                .iter()
+                .map(|(key, sig)| -> Result<_, String> {
+                    let sig = schnorr::Signature::from_slice(&decode(
+                        "signatures",
+                        sig,
+                    )?)
+                    .map_err(|e| format!("signatures: {e}"))?;
+                    Ok((decode("signatures", key)?, sig))
+                })
+                .collect::<Result<_, _>>()?,
+            limb_len: LimbLen::new(step.limb_len)
+                .map_err(|e| format!("limb_len: {e}"))?,
+        })
     }
+}
 
-    b.into_script()
+/// Total weight of every step of every flow in `flows`, i.e. the chain load
+/// if the whole flow set were broadcast. See [`PresignedStep::weight`].
+pub fn total_flow_set_weight(flows: &[PresignedFlow]) -> usize {
+    flows
+        .iter()
+        .flat_map(|flow| &flow.steps)
+        .map(PresignedStep::weight)
+        .sum()
 }
 
-pub fn build_drop(items: usize) -> ScriptBuf {
-    let mut b = Builder::new();
-    for _ in 0..items {
-        b = b.push_opcode(opcodes::all::OP_DROP);
-    }
-    b.into_script()
+/// One flow of `D` funded and presigned during the offline phase.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PresignedFlow {
+    /// Flow id `d`, in `[0, 2^L)`
+    pub flow_id: u32,
+    /// Presigned steps of the flow, F1 first
+    pub steps: Vec<PresignedStep>,
 }
 
-// Reconstructs a 32-bit value `x` from limbs of `limb_len` size.
-// Only supports power-of-2 limb lengths for Bitcoin Script efficiency.
-fn build_script_reconstruct_x(limb_len: u8) -> ScriptBuf {
-    // Validate that limb_len_bits is a power of 2
-    assert!(
-        limb_len > 0 && (limb_len & (limb_len - 1)) == 0,
-        "limb_len_bits must be a power of 2"
-    );
+/// Create a minimal sighash for demonstration
+pub fn create_toy_sighash_message(
+    locking_script: &ScriptBuf,
+    value: Amount,
+) -> Message {
+    let mut engine = sha256::HashEngine::default();
+    engine.input(&locking_script.to_bytes());
+    engine.input(&value.to_sat().to_le_bytes());
+    let digest = sha256::Hash::from_engine(engine);
+    Message::from_digest(digest.to_byte_array())
+}
 
-    let limbs_needed = 32u8.div_ceil(limb_len);
+/// One fixed-width field of the hashed message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageField {
+    /// Human-readable field name, used in error messages
+    pub name: String,
+    /// Width of the field in bytes
+    pub width: usize,
+}
 
-    let mut b = Builder::new().push_int(0); // acc = 0
+/// Ordered layout of the message hashed into the flow id.
+///
+/// The off-chain hash and the on-chain BLAKE3 compute script must agree on
+/// the message length, so both derive it from the same schema. The default
+/// schema is the toy's 12-byte `x || nonce`; extra witness data is appended
+/// with [`MessageSchema::with_field`]. `x` always comes first, as the
+/// locking scripts reconstruct it from the bottom witness limbs; it is 4
+/// bytes wide, or 8 in [`MessageSchema::u64_input`]. The step scripts only
+/// accept a 4-byte x, their predicates compare it as one script number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageSchema {
+    fields: Vec<MessageField>,
+}
 
-    for i in 0..limbs_needed {
-        for _ in 0..limb_len {
-            b = b
-                .push_opcode(opcodes::all::OP_DUP)
-                .push_opcode(opcodes::all::OP_ADD);
+impl Default for MessageSchema {
+    fn default() -> Self {
+        Self {
+            fields: vec![
+                MessageField {
+                    name: "x".to_string(),
+                    width: 4,
+                },
+                MessageField {
+                    name: "nonce".to_string(),
+                    width: 8,
+                },
+            ],
         }
+    }
+}
 
-        // Pick the i-th limb from bottom of stack and add to accumulator
-        b = b
-            .push_opcode(opcodes::all::OP_DEPTH)
-            .push_opcode(opcodes::all::OP_1SUB) // depth - 1 (bottom index)
-            .push_int(i as i64) // limb index
-            .push_opcode(opcodes::all::OP_SUB) // (depth-1) - i
-            .push_opcode(opcodes::all::OP_PICK) // copy limb to top
-            .push_opcode(opcodes::all::OP_ADD); // acc += limb
+impl MessageSchema {
+    /// The 16-byte `x || nonce` layout for a 64-bit `x`.
+    ///
+    /// Only for flow ids: the step builders reject it, as their predicates
+    /// would see the low word of x alone.
+    pub fn u64_input() -> Self {
+        Self {
+            fields: vec![
+                MessageField {
+                    name: "x".to_string(),
+                    width: 8,
+                },
+                MessageField {
+                    name: "nonce".to_string(),
+                    width: 8,
+                },
+            ],
+        }
     }
 
-    b.into_script()
-}
+    /// Append a field of `width` bytes after the existing ones.
+    pub fn with_field(mut self, name: &str, width: usize) -> Self {
+        self.fields.push(MessageField {
+            name: name.to_string(),
+            width,
+        });
+        self
+    }
 
-// limb length for blake3 in bits,
-// blake3 accepts any limb length [4, 32) but due to the way how build_script_reconstruct_x
-// it must be a power of 2 between 1 and 16
-// Valid values: 4, 8, 16
-const LIMB_LEN: u8 = 4;
+    /// Fields in message order
+    pub fn fields(&self) -> &[MessageField] {
+        &self.fields
+    }
 
-/// Build an F1 script with onchain BLAKE3, checking x>F1_THRESHOLD and the top (b_bits/8) bytes match flow_id_prefix.
-pub fn build_script_f1_blake3_locked(
-    signer_pubkey: &PublicKey,
-    flow_id_prefix: &[u8],
-    _b_bits: usize,
-) -> ScriptBuf {
-    let prefix_len = flow_id_prefix.len();
-    let total_msg_len = 12; // x_4b + r_4b0 + r_4b1
+    /// Total message length in bytes
+    pub fn total_msg_len(&self) -> usize {
+        self.fields.iter().map(|f| f.width).sum()
+    }
 
-    // 1) Script to check signature
-    let verify_signature_script = {
-        let mut b = Builder::new();
-        b = b.push_x_only_key(&XOnlyPublicKey::from(signer_pubkey.inner));
-        b.push_opcode(opcodes::all::OP_CHECKSIGVERIFY).into_script()
-    };
+    /// Concatenate `values` in schema order.
+    ///
+    /// Panics if the number of values or any of their widths does not match
+    /// the schema.
+    pub fn encode(&self, values: &[&[u8]]) -> Vec<u8> {
+        assert_eq!(
+            values.len(),
+            self.fields.len(),
+            "schema has {} fields, got {} values",
+            self.fields.len(),
+            values.len()
+        );
+        let mut message = Vec::with_capacity(self.total_msg_len());
+        for (field, value) in self.fields.iter().zip(values) {
+            assert_eq!(
+                value.len(),
+                field.width,
+                "field `{}` is {} bytes, got {}",
+                field.name,
+                field.width,
+                value.len()
+            );
+            message.extend_from_slice(value);
+        }
+        message
+    }
+}
 
-    // 2) Reconstruct x from first 8 nibbles
-    let reconstruct_x_script = build_script_reconstruct_x(LIMB_LEN);
+/// Calculate H(x||nonce)|_B => flow_id
+pub fn calculate_flow_id(input: u32, nonce: u64) -> u32 {
+    calculate_flow_id_with_schema(
+        &MessageSchema::default(),
+        &[&input.to_le_bytes(), &nonce.to_le_bytes()],
+    )
+}
 
-    // 3) Check x_num > 100
-    let x_greater_check_script = Builder::new()
-        .push_int(F1_THRESHOLD as i64)
-        .push_opcode(opcodes::all::OP_GREATERTHAN)
-        .push_opcode(opcodes::all::OP_VERIFY)
-        .into_script();
+/// Calculate H(x||nonce)|_B => flow_id for a 64-bit `x`, see
+/// [`MessageSchema::u64_input`].
+pub fn calculate_flow_id_u64(input: u64, nonce: u64) -> u32 {
+    calculate_flow_id_with_schema(
+        &MessageSchema::u64_input(),
+        &[&input.to_le_bytes(), &nonce.to_le_bytes()],
+    )
+}
 
-    // 4) BLAKE3 compute snippet - OPTIMIZED
-    let compute_compiled =
-        blake3_compute_script_with_limb(total_msg_len, LIMB_LEN).compile();
-    let compute_optimized = optimizer::optimize(compute_compiled);
-    let compute_blake3_script =
-        ScriptBuf::from_bytes(compute_optimized.to_bytes());
+/// Calculate the flow id over a message laid out by `schema`.
+pub fn calculate_flow_id_with_schema(
+    schema: &MessageSchema,
+    values: &[&[u8]],
+) -> u32 {
+    let hash = blake3::hash(&schema.encode(values));
+    truncate_digest(hash.as_bytes(), 0)
+}
 
-    // 5) drop limbs we don't need for prefix check
-    // Needed nibbles: prefix_len (because now represented as nibbles) or B / 4
-    let needed_nibbles = prefix_len;
-    let blake3_script_hash_len_nibbles = 64;
+/// A 32-byte hash over an encoded message, as used for flow ids.
+///
+/// The digests must match the hash of the locking scripts: BLAKE3 for the
+/// default scripts, SHA-256 for [`HashChoice::Sha256`] ones.
+pub trait FlowHasher {
+    fn digest(&self, message: &[u8]) -> [u8; 32];
+}
 
-    let drop_script =
-        build_drop(blake3_script_hash_len_nibbles - needed_nibbles);
+/// The default [`FlowHasher`], an incremental [`blake3::Hasher`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blake3FlowHasher;
 
-    // 6) compare prefix => OP_EQUALVERIFY
-    let prefix_cmp_script = build_prefix_equalverify(flow_id_prefix);
+impl FlowHasher for Blake3FlowHasher {
+    fn digest(&self, message: &[u8]) -> [u8; 32] {
+        let mut hasher = Hasher::new();
+        hasher.update(message);
+        *hasher.finalize().as_bytes()
+    }
+}
 
-    // 7) push OP_TRUE
-    let success_script = Builder::new().push_opcode(OP_TRUE).into_script();
+/// [`FlowHasher`] for [`HashChoice::Sha256`] scripts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256FlowHasher;
 
-    // Combine the locking script parts
-    combine_scripts(&[
-        verify_signature_script,
-        reconstruct_x_script,
-        x_greater_check_script,
-        compute_blake3_script,
-        drop_script,
-        prefix_cmp_script,
-        success_script,
-    ])
+impl FlowHasher for Sha256FlowHasher {
+    fn digest(&self, message: &[u8]) -> [u8; 32] {
+        sha256::Hash::hash(message).to_byte_array()
+    }
 }
 
-/// Build an F2 script with onchain BLAKE3, checking x<F2_THRESHOLD and prefix
-pub fn build_script_f2_blake3_locked(
-    signer_pubkey: &PublicKey,
-    flow_id_prefix: &[u8],
-    _b_bits: usize,
-) -> ScriptBuf {
-    let prefix_len = flow_id_prefix.len();
-    let total_msg_len = 12;
+/// The hash the step scripts compute over `x || nonce` to check the flow id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashChoice {
+    /// BLAKE3, emulated in script over the message limbs
+    #[default]
+    Blake3,
+    /// Native `OP_SHA256` over the message bytes, a much smaller script.
+    ///
+    /// Script cannot split the digest, so the witness provides its suffix
+    /// and the script rebuilds it with `OP_CAT` (BIP-347). Where `OP_CAT`
+    /// is not active, it is an `OP_SUCCESS` opcode and the leaf can be
+    /// spent by anyone, so the builders only emit it with `allow_op_cat`.
+    /// Only a `B` multiple of 8 is supported.
+    Sha256,
+}
 
-    // 1) Script to check signature
-    let verify_signature_script = {
-        let mut b = Builder::new();
-        b = b.push_x_only_key(&XOnlyPublicKey::from(signer_pubkey.inner));
-        b.push_opcode(opcodes::all::OP_CHECKSIGVERIFY).into_script()
-    };
+/// Calculate H(x||nonce)|_B => flow_id with the hash of `hash` scripts.
+pub fn calculate_flow_id_with_hash(
+    hash: HashChoice,
+    input: u32,
+    nonce: u64,
+) -> u32 {
+    match hash {
+        HashChoice::Blake3 => {
+            calculate_flow_id_with_hasher(&Blake3FlowHasher, input, nonce)
+        }
+        HashChoice::Sha256 => {
+            calculate_flow_id_with_hasher(&Sha256FlowHasher, input, nonce)
+        }
+    }
+}
 
-    // 2) Reconstruct x from first 8 nibbles
-    let reconstruct_x_script = build_script_reconstruct_x(LIMB_LEN);
+/// Calculate H(x||nonce)|_B => flow_id with the given hasher.
+pub fn calculate_flow_id_with_hasher<H: FlowHasher>(
+    hasher: &H,
+    input: u32,
+    nonce: u64,
+) -> u32 {
+    let message = MessageSchema::default()
+        .encode(&[&input.to_le_bytes(), &nonce.to_le_bytes()]);
+    truncate_digest(&hasher.digest(&message), 0)
+}
 
-    // 3) Check x_num < 200
-    let x_less_check_script = Builder::new()
-        .push_int(F2_THRESHOLD as i64)
-        .push_opcode(opcodes::all::OP_LESSTHAN)
-        .push_opcode(opcodes::all::OP_VERIFY)
-        .into_script();
+/// Calculate the flow id from the 4-byte digest window starting at `offset`
+/// instead of the first four bytes.
+///
+/// This only changes the off-chain path: the locking scripts still drop all
+/// but the leading digest nibbles, so a flow id computed with a non-zero
+/// `offset` only verifies on-chain if paired with a matching drop/compare
+/// script. `offset` 0 is the same as [`calculate_flow_id`].
+pub fn calculate_flow_id_offset(input: u32, nonce: u64, offset: usize) -> u32 {
+    let message = MessageSchema::default()
+        .encode(&[&input.to_le_bytes(), &nonce.to_le_bytes()]);
+    truncate_digest(blake3::hash(&message).as_bytes(), offset)
+}
 
-    // 4) BLAKE3 compute snippet - OPTIMIZED
-    let compute_blake3_script = {
-        let compiled =
-            blake3_compute_script_with_limb(total_msg_len, LIMB_LEN).compile();
-        // Important: Optimize the compute script
-        let optimized = optimizer::optimize(compiled);
-        ScriptBuf::from_bytes(optimized.to_bytes())
-    };
+/// Little-endian `u32` read from `digest[offset..offset + 4]`.
+fn truncate_digest(digest: &[u8; 32], offset: usize) -> u32 {
+    assert!(offset <= 28, "offset must be in [0, 28], got {offset}");
+    let mut fourb = [0u8; 4];
+    fourb.copy_from_slice(&digest[offset..offset + 4]);
 
-    // 5) drop limbs we don't need for prefix check
-    // Needed nibbles: prefix_len (because now represented as nibbles) or B / 4
-    let needed_nibbles = prefix_len;
-    let blake3_script_hash_len_nibbles = 64;
+    u32::from_le_bytes(fourb)
+}
 
-    let drop_script =
-        build_drop(blake3_script_hash_len_nibbles - needed_nibbles);
+/// Mask selecting the low `b_bits` bits of a flow id, i.e. the range of
+/// flow ids for a `B`-bit prefix.
+///
+/// For a byte-aligned `B` this is also `|_B` on a digest word; in general
+/// use [`truncate_flow_id`].
+pub fn flow_id_mask(b_bits: usize) -> u32 {
+    if b_bits >= 32 {
+        u32::MAX
+    } else {
+        (1u32 << b_bits) - 1
+    }
+}
 
-    // 6) compare prefix => OP_EQUALVERIFY
-    let prefix_cmp_script = build_prefix_equalverify(flow_id_prefix);
+/// `hash|_B`: keeps the first `B/4` nibbles of the digest, in the order the
+/// locking scripts compare them, packed into a `B`-bit flow id.
+///
+/// `hash` is the little-endian word of the first four digest bytes, so for
+/// a byte-aligned `B` this is just the low `B` bits. When `B` ends halfway
+/// through a byte, that byte contributes its high nibble, which is the one
+/// pushed first on-chain.
+pub fn truncate_flow_id(hash: u32, b_bits: usize) -> u32 {
+    debug_assert_eq!(b_bits % 4, 0, "b_bits must be a multiple of 4");
+    if b_bits % 8 == 0 {
+        return hash & flow_id_mask(b_bits);
+    }
+    let full_bytes = hash & flow_id_mask(b_bits - 4);
+    let high_nibble = (hash >> b_bits) & 0x0F;
+    full_bytes | (high_nibble << (b_bits - 4))
+}
 
-    let success_script = Builder::new().push_opcode(OP_TRUE).into_script();
+/// `nonce -> H(x||nonce)|_B` for a fixed `input`: the hot loop shared by
+/// every nonce search.
+///
+/// The hasher is primed with `input` once and cloned for every nonce, so the
+/// per-nonce work is reduced to a single 8-byte update and finalize.
+fn primed_flow_id(input: u32, b_bits: usize) -> impl Fn(u64) -> u32 {
+    let mut primed = Hasher::new();
+    primed.update(&input.to_le_bytes());
 
-    combine_scripts(&[
-        verify_signature_script,
-        reconstruct_x_script,
-        x_less_check_script,
-        compute_blake3_script,
-        drop_script,
-        prefix_cmp_script,
-        success_script,
-    ])
+    move |nonce| {
+        let mut hasher = primed.clone();
+        hasher.update(&nonce.to_le_bytes());
+        let digest = truncate_digest(hasher.finalize().as_bytes(), 0);
+        truncate_flow_id(digest, b_bits)
+    }
 }
 
-pub fn message_to_witness_limbs(x: u32, nonce: u64) -> Vec<Vec<u8>> {
-    let message = [
-        x.to_le_bytes(),
-        nonce.to_le_bytes()[0..4].try_into().unwrap(),
-        nonce.to_le_bytes()[4..8].try_into().unwrap(),
-    ]
-    .concat();
-
-    blake3_message_to_limbs(&message, LIMB_LEN)
-        .into_iter()
-        .map(|limb| encode_scriptnum(limb.into()))
-        .collect()
+/// Calculate `H(x||nonce)|_B` for a whole slice of nonces in one pass,
+/// hashing `input` only once.
+pub fn calculate_flow_ids_batch(
+    input: u32,
+    nonces: &[u64],
+    b_bits: usize,
+) -> Vec<u32> {
+    let flow_id = primed_flow_id(input, b_bits);
+    nonces.iter().map(|&nonce| flow_id(nonce)).collect()
 }
-/// A basic "hash rate" calibration
-pub fn benchmark_hash_rate(duration_secs: u64) -> u64 {
-    println!("Calibrating for {duration_secs} seconds...");
-    let pb = ProgressBar::new(100);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner} [{elapsed_precise}] [{bar:40.green/black}] {percent}% {msg}")
-            .unwrap(),
-    );
-    pb.enable_steady_tick(Duration::from_millis(100));
 
-    let start = Instant::now();
-    let end = start + Duration::from_secs(duration_secs);
+/// Derive a deterministic input `x` from a human-readable label.
+///
+/// The label is hashed with BLAKE3 and mapped into the open interval
+/// `(F1_THRESHOLD, F2_THRESHOLD)`, so the resulting `x` always satisfies both
+/// F1 and F2.
+pub fn input_from_label(label: &str) -> u32 {
+    let hash = blake3::hash(label.as_bytes());
 
-    let mut count = 0u64;
-    let mut nonce = 0u64;
-    let input = 123u32;
+    let span = F2_THRESHOLD - F1_THRESHOLD - 1;
+    F1_THRESHOLD + 1 + truncate_digest(hash.as_bytes(), 0) % span
+}
 
-    while Instant::now() < end {
-        let mut hasher = Hasher::new();
-        hasher.update(&input.to_le_bytes());
-        hasher.update(&nonce.to_le_bytes());
-        hasher.finalize();
-        nonce += 1;
-        count += 1;
+/// Finds a valid nonce `r` for a given input `x` such that `H(x, r)|_B` falls within the set `D`. (Off-chain logic)
+///
+/// This simulates the work performed by an Operator during the online phase.
+/// The expected number of hash attempts is `2^(B-L)`.
+///
+/// # Arguments
+/// * `input` - The input value `x`.
+/// * `b_bits` - The hash prefix length `B`.
+/// * `l_bits` - The parameter `L` defining the size of set `D`.
+///
+/// `B` and `L` are [`BBits`] and [`LBits`] so that swapping them is a type
+/// error rather than a silently different search space.
+///
+/// # Returns
+/// * `Ok((u64, u32))` - A tuple containing the found nonce `r` and the corresponding flow ID `d`.
+/// * `Err(CoreError)` - An error if `L` and `B` are invalid (`L >= 32` or `L > B`), or if a nonce cannot be found (e.g., due to overflow or excessive attempts).
+pub fn find_valid_nonce(
+    input: u32,
+    b_bits: BBits,
+    l_bits: LBits,
+) -> Result<(u64, u32), CoreError> {
+    find_valid_nonce_with_style(
+        input,
+        b_bits,
+        l_bits,
+        ProgressStyleConfig::default(),
+        None,
+    )
+}
+
+/// Same as [`find_valid_nonce`], drawing the progress bar with `style`.
+///
+/// `hash_rate` is a rate measured beforehand, e.g. by
+/// [`benchmark_hash_rate`], which the ETA starts from instead of waiting
+/// for the first batches.
+pub fn find_valid_nonce_with_style(
+    input: u32,
+    b_bits: BBits,
+    l_bits: LBits,
+    style: ProgressStyleConfig,
+    hash_rate: Option<f64>,
+) -> Result<(u64, u32), CoreError> {
+    search_nonce(
+        u64::from(input),
+        |nonce_base, batch| grind_kernel(input, nonce_base, batch, b_bits.0),
+        b_bits,
+        l_bits,
+        style,
+        None,
+        hash_rate,
+    )
+    .map(|(nonce, flow_id, _)| (nonce, flow_id))
+}
+
+/// Metrics of a nonce search.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NonceSearchStats {
+    /// Nonces hashed, the valid one included
+    pub attempts: u64,
+    pub elapsed: Duration,
+    /// `attempts` per second of `elapsed`
+    pub hash_rate: f64,
+}
+
+/// A snapshot of a running nonce search, pushed to the callback of
+/// [`find_valid_nonce_with_progress`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NonceProgress {
+    /// Nonces hashed so far
+    pub attempts: u64,
+    /// Expected attempts, 2^(B-L)
+    pub expected: u64,
+    /// Hashes per second since the start of the search
+    pub rate: f64,
+    /// Time left to reach `expected` at `rate`, zero once past it
+    pub eta: Duration,
+}
+
+impl NonceProgress {
+    fn new(stats: &NonceSearchStats, expected: u64) -> Self {
+        let left = expected.saturating_sub(stats.attempts) as f64;
+        Self {
+            attempts: stats.attempts,
+            expected,
+            rate: stats.hash_rate,
+            eta: Duration::try_from_secs_f64(left / stats.hash_rate)
+                .unwrap_or(Duration::MAX),
+        }
     }
+}
 
-    let dt = start.elapsed().as_secs_f64();
-    let rate = if dt > 0.0 { count as f64 / dt } else { 0.0 };
-    pb.finish_with_message(format!("~{rate:.2} H/s"));
-    rate as u64
+/// Same as [`find_valid_nonce`], pushing its progress to `progress` after
+/// every batch of nonces instead of drawing it on the terminal.
+///
+/// `None` keeps the terminal output, a [`ConsoleProgress`].
+pub fn find_valid_nonce_with_progress(
+    input: u32,
+    b_bits: BBits,
+    l_bits: LBits,
+    progress: Option<&dyn Fn(NonceProgress)>,
+) -> Result<(u64, u32), CoreError> {
+    search_nonce(
+        u64::from(input),
+        |nonce_base, batch| grind_kernel(input, nonce_base, batch, b_bits.0),
+        b_bits,
+        l_bits,
+        ProgressStyleConfig::default(),
+        progress,
+        None,
+    )
+    .map(|(nonce, flow_id, _)| (nonce, flow_id))
 }
 
-// for off-chain use
-pub fn blake3_message_to_limbs(message_bytes: &[u8], limb_len: u8) -> Vec<u32> {
-    let script =
-        blake3_push_message_script_with_limb(message_bytes, limb_len).compile();
-    let res = execute_script_buf(script);
+/// Same as [`find_valid_nonce`], also returning the search metrics.
+pub fn find_valid_nonce_with_stats(
+    input: u32,
+    b_bits: BBits,
+    l_bits: LBits,
+) -> Result<(u64, u32, NonceSearchStats), CoreError> {
+    search_nonce(
+        u64::from(input),
+        |nonce_base, batch| grind_kernel(input, nonce_base, batch, b_bits.0),
+        b_bits,
+        l_bits,
+        ProgressStyleConfig::default(),
+        None,
+        None,
+    )
+}
 
-    res.final_stack
-        .0
-        .iter_str()
-        .map(|v| {
-            let mut arr = [0u8; 4];
-            arr[..v.len()].copy_from_slice(&v);
-            u32::from_le_bytes(arr)
-        })
+/// Same as [`find_valid_nonce`] for a 64-bit input, hashed as the 16-byte
+/// message of [`MessageSchema::u64_input`].
+pub fn find_valid_nonce_u64(
+    input: u64,
+    b_bits: BBits,
+    l_bits: LBits,
+) -> Result<(u64, u32), CoreError> {
+    search_nonce(
+        input,
+        |nonce_base, batch| {
+            (nonce_base..nonce_base + batch as u64)
+                .map(|nonce| {
+                    truncate_flow_id(
+                        calculate_flow_id_u64(input, nonce),
+                        b_bits.0,
+                    )
+                })
+                .collect()
+        },
+        b_bits,
+        l_bits,
+        ProgressStyleConfig::default(),
+        None,
+        None,
+    )
+    .map(|(nonce, flow_id, _)| (nonce, flow_id))
+}
+
+/// Nonces hashed per [`grind_kernel`] call by the nonce search
+const GRIND_KERNEL_BATCH: usize = 1024;
+
+/// The grind hot loop as a pure batch: `H(x||nonce)|_B` for the `batch`
+/// consecutive nonces starting at `nonce_base`, in order.
+///
+/// It has no control flow or reporting, so another backend (e.g. a GPU)
+/// can implement it and plug into the same search. The nonce range must
+/// not overflow `u64`.
+pub fn grind_kernel(
+    input: u32,
+    nonce_base: u64,
+    batch: usize,
+    b_bits: usize,
+) -> Vec<u32> {
+    (nonce_base..nonce_base + batch as u64)
+        .map(primed_flow_id(input, b_bits))
         .collect()
 }
 
-pub fn build_script_hash_to_limbs() -> ScriptBuf {
-    let mut builder = Builder::new();
+/// Scans nonces from 0, [`GRIND_KERNEL_BATCH`] at a time, until one whose
+/// flow id lands in `D`. `kernel(nonce_base, batch)` returns the flow ids
+/// of a batch, like [`grind_kernel`]. `input` is only used for reporting,
+/// `style`, `progress` and `hash_rate` as for [`search_nonce_slice`].
+fn search_nonce(
+    input: u64,
+    kernel: impl Fn(u64, usize) -> Vec<u32>,
+    b_bits: BBits,
+    l_bits: LBits,
+    style: ProgressStyleConfig,
+    progress: Option<&dyn Fn(NonceProgress)>,
+    hash_rate: Option<f64>,
+) -> Result<(u64, u32, NonceSearchStats), CoreError> {
+    check_flow_params(b_bits.0, l_bits.0)?;
+    let expected = expected_attempts(b_bits.0, l_bits.0);
+    let max_attempts = max_search_attempts(expected)?;
 
-    for _ in 0..56 {
-        builder = builder.push_opcode(opcodes::all::OP_TOALTSTACK);
+    search_nonce_slice(
+        kernel,
+        b_bits,
+        l_bits,
+        style,
+        progress,
+        hash_rate,
+        0,
+        max_attempts,
+    )
+    .map_err(|err| match err {
+        CoreError::SearchIncomplete { .. } => CoreError::NonceSearchExhausted {
+            input,
+            attempts: max_attempts,
+            expected,
+        },
+        err => err,
+    })
+}
+
+/// Expected number of attempts (2^(B-L)) for progress reporting
+pub(crate) fn expected_attempts(b_bits: usize, l_bits: usize) -> u64 {
+    1u64.checked_shl((b_bits.saturating_sub(l_bits)) as u32)
+        .unwrap_or(u64::MAX)
+}
+
+/// A nonce search gives up after this many times its expected attempts.
+const SEARCH_BUDGET_FACTOR: u64 = 100;
+
+/// Nonces a search for `expected` attempts tries before giving up: the
+/// [`SEARCH_BUDGET_FACTOR`] budget, plus nonce 0.
+///
+/// This is a safety break that prevents infinite loops in case of
+/// configuration errors, shared by [`search_nonce`], [`grind_batch`] and
+/// [`select_flow_for_input`].
+fn max_search_attempts(expected: u64) -> Result<u64, CoreError> {
+    expected
+        .saturating_mul(SEARCH_BUDGET_FACTOR)
+        .checked_add(1)
+        .ok_or(CoreError::NonceOverflow)
+}
+
+/// [`search_nonce`] over the `max_attempts` nonces from `start_nonce`,
+/// failing with [`CoreError::SearchIncomplete`] when none of them selects a
+/// flow in `D`.
+///
+/// Progress goes to `progress` when given, else to a [`ConsoleProgress`]
+/// drawn with `style`, its ETA seeded with `hash_rate` if known.
+#[allow(clippy::too_many_arguments)]
+fn search_nonce_slice(
+    kernel: impl Fn(u64, usize) -> Vec<u32>,
+    b_bits: BBits,
+    l_bits: LBits,
+    style: ProgressStyleConfig,
+    progress: Option<&dyn Fn(NonceProgress)>,
+    hash_rate: Option<f64>,
+    start_nonce: u64,
+    max_attempts: u64,
+) -> Result<(u64, u32, NonceSearchStats), CoreError> {
+    check_flow_params(b_bits.0, l_bits.0)?;
+    if let Some(progress) = progress {
+        return grind_nonce_slice(
+            kernel,
+            b_bits,
+            l_bits,
+            start_nonce,
+            max_attempts,
+            progress,
+        );
     }
-    for i in 0..8 {
-        for j in (0..8).step_by(2) {
-            builder = builder
-                .push_int(j)
-                .push_opcode(opcodes::all::OP_ROLL)
-                .push_int(j + 1)
-                .push_opcode(opcodes::all::OP_ROLL)
-                .push_opcode(opcodes::all::OP_SWAP);
+
+    let console = ConsoleProgress::new(b_bits.0, l_bits.0, style, start_nonce)
+        .map_err(CoreError::ProgressStyle)?;
+    if let Some(rate) = hash_rate {
+        console.seed_hash_rate(rate);
+    }
+    let result = grind_nonce_slice(
+        kernel,
+        b_bits,
+        l_bits,
+        start_nonce,
+        max_attempts,
+        &|event| console.report(event),
+    );
+    match &result {
+        Ok((nonce, flow_id, _)) => console.success(*flow_id, *nonce),
+        Err(_) => console.failure(),
+    }
+    result
+}
+
+/// The loop of [`search_nonce_slice`], pushing a [`NonceProgress`] to
+/// `progress` after every kernel batch.
+fn grind_nonce_slice(
+    kernel: impl Fn(u64, usize) -> Vec<u32>,
+    b_bits: BBits,
+    l_bits: LBits,
+    start_nonce: u64,
+    max_attempts: u64,
+    progress: &dyn Fn(NonceProgress),
+) -> Result<(u64, u32, NonceSearchStats), CoreError> {
+    let end_nonce = start_nonce
+        .checked_add(max_attempts)
+        .ok_or(CoreError::NonceOverflow)?;
+    let expected = expected_attempts(b_bits.0, l_bits.0);
+
+    let start = Instant::now();
+    let stats = |attempts: u64| {
+        let elapsed = start.elapsed();
+        NonceSearchStats {
+            attempts,
+            elapsed,
+            hash_rate: attempts as f64 / elapsed.as_secs_f64().max(1e-9),
         }
-        if i != 7 {
-            for _ in 0..8 {
-                builder = builder.push_opcode(opcodes::all::OP_FROMALTSTACK);
+    };
+    let mut nonce_base = start_nonce;
+    while nonce_base < end_nonce {
+        let batch = (end_nonce - nonce_base).min(GRIND_KERNEL_BATCH as u64);
+        let flow_ids = kernel(nonce_base, batch as usize);
+        if let Some((offset, &prefix_b)) = flow_ids
+            .iter()
+            .enumerate()
+            .find(|&(_, &flow_id)| flow_id_in_range(flow_id, l_bits.0))
+        {
+            // Found a nonce `r` such that H(x, r)|_B = d ∈ D
+            let nonce = nonce_base + offset as u64;
+            let stats = stats(nonce - start_nonce + 1);
+            progress(NonceProgress::new(&stats, expected));
+            return Ok((nonce, prefix_b, stats));
+        }
+        // Every hash prefix of the batch was outside [0, 2^L - 1]
+        nonce_base += batch;
+        progress(NonceProgress::new(
+            &stats(nonce_base - start_nonce),
+            expected,
+        ));
+    }
+
+    Err(CoreError::SearchIncomplete {
+        last_nonce: end_nonce,
+    })
+}
+
+/// A bounded slice of [`find_valid_nonce`]: tries the `max_attempts`
+/// nonces from `start_nonce` on.
+///
+/// When none of them selects a flow in `D`, fails with
+/// [`CoreError::SearchIncomplete`], whose `last_nonce` is the
+/// `start_nonce` to resume the search from. Slices chained this way find
+/// the same nonce as a single [`find_valid_nonce`], which starts at 0.
+pub fn find_valid_nonce_from(
+    input: u32,
+    b_bits: BBits,
+    l_bits: LBits,
+    start_nonce: u64,
+    max_attempts: u64,
+) -> Result<(u64, u32), CoreError> {
+    search_nonce_slice(
+        |nonce_base, batch| grind_kernel(input, nonce_base, batch, b_bits.0),
+        b_bits,
+        l_bits,
+        ProgressStyleConfig::default(),
+        None,
+        None,
+        start_nonce,
+        max_attempts,
+    )
+    .map(|(nonce, flow_id, _)| (nonce, flow_id))
+}
+
+/// How often [`grind_batch`] forwards the workers' hash count to its
+/// reporter
+const BATCH_REPORT_POLL: Duration = Duration::from_millis(50);
+/// Hashes a [`grind_batch`] worker computes between two updates of the
+/// shared hash count
+const BATCH_COUNT_FLUSH: u64 = 1024;
+
+/// Grinds a valid nonce for each of `inputs`, like [`find_valid_nonce`] in a
+/// loop, but reporting to a single `reporter` across the whole batch.
+///
+/// The inputs are spread over at most `max_concurrency` worker threads,
+/// the number of logical cores when `None`. Every input is ground from
+/// nonce 0, so the results do not depend on the concurrency.
+///
+/// `reporter` should be created with the combined expected work,
+/// `inputs.len() * 2^(B-L)` hashes; it is fed the running total of hashes.
+///
+/// # Returns
+/// * `Ok(Vec<(u32, u64, u32)>)` - `(input, nonce, flow_id)` for every input, in order.
+/// * `Err(CoreError)` - An error if `L` and `B` are invalid, or if an input needs more than 100x the expected attempts.
+pub fn grind_batch(
+    inputs: &[u32],
+    b_bits: BBits,
+    l_bits: LBits,
+    max_concurrency: Option<NonZeroUsize>,
+    reporter: &mut NonceSearchProgress,
+) -> Result<Vec<(u32, u64, u32)>, CoreError> {
+    let (b_bits, l_bits) = (b_bits.0, l_bits.0);
+    check_flow_params(b_bits, l_bits)?;
+    let expected = expected_attempts(b_bits, l_bits);
+    let max_attempts = max_search_attempts(expected)?;
+
+    let max_concurrency = max_concurrency
+        .or_else(|| std::thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+    let workers = max_concurrency.min(inputs.len()).max(1);
+
+    let hashed = AtomicU64::new(0);
+    // Index of the first input known to have exhausted its attempts
+    let first_exhausted = AtomicUsize::new(usize::MAX);
+    let grind_one = |i: usize, input: u32| {
+        let flow_id_of = primed_flow_id(input, b_bits);
+
+        let mut unflushed = 0;
+        let mut found = None;
+        for nonce in 0..max_attempts {
+            let flow_id = flow_id_of(nonce);
+            unflushed += 1;
+            if flow_id_in_range(flow_id, l_bits) {
+                found = Some((nonce, flow_id));
+                break;
+            }
+            if unflushed == BATCH_COUNT_FLUSH {
+                hashed.fetch_add(unflushed, Ordering::Relaxed);
+                unflushed = 0;
+                // Another input already failed the batch
+                if first_exhausted.load(Ordering::Relaxed) != usize::MAX {
+                    return None;
+                }
+            }
+        }
+        hashed.fetch_add(unflushed, Ordering::Relaxed);
+        if found.is_none() {
+            first_exhausted.fetch_min(i, Ordering::Relaxed);
+        }
+        found
+    };
+
+    // Worker `w` grinds inputs `w`, `w + workers`, ...
+    let found = std::thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|worker| {
+                let grind_one = &grind_one;
+                scope.spawn(move || {
+                    inputs
+                        .iter()
+                        .enumerate()
+                        .skip(worker)
+                        .step_by(workers)
+                        .map(|(i, &input)| (i, grind_one(i, input)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        while !handles.iter().all(|handle| handle.is_finished()) {
+            reporter.update(hashed.load(Ordering::Relaxed));
+            std::thread::sleep(BATCH_REPORT_POLL);
+        }
+
+        let mut found = vec![None; inputs.len()];
+        for handle in handles {
+            for (i, result) in handle.join().expect("grind worker panicked") {
+                found[i] = result;
             }
         }
+        found
+    });
+
+    if let Some(&input) = inputs.get(first_exhausted.load(Ordering::Relaxed)) {
+        reporter.failure();
+        return Err(CoreError::NonceSearchExhausted {
+            input: u64::from(input),
+            attempts: max_attempts,
+            expected,
+        });
     }
 
-    for _ in 0..64 {
-        builder = builder.push_int(0);
+    let mut total_attempts: u64 = 0;
+    let mut results = Vec::with_capacity(inputs.len());
+    for (&input, found) in inputs.iter().zip(found) {
+        let (nonce, flow_id) =
+            found.expect("every input has a nonce unless one was exhausted");
+        total_attempts += nonce + 1;
+        results.push((input, nonce, flow_id));
     }
 
-    builder.into_script()
+    reporter.update(total_attempts);
+    reporter.batch_success(inputs.len(), total_attempts);
+    Ok(results)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use bitcoin_script::script;
-    use bitvm::{
-        execute_script_buf,
-        hash::blake3::{
-            blake3_push_message_script_with_limb, blake3_verify_output_script,
-        },
-    };
+/// Finds a nonce `r` such that `H(x, r)|_B` equals exactly `target_flow`.
+///
+/// Unlike [`find_valid_nonce`], which accepts any flow in `D`, this targets a
+/// single flow, so the expected work is `2^B` hashes. Returns `None` if no
+/// such nonce is found within `max_attempts`, or if `b_bits` is not a valid
+/// [`BBits`].
+pub fn find_nonce_for_flow(
+    input: u32,
+    target_flow: u32,
+    b_bits: BBits,
+    max_attempts: u64,
+) -> Option<u64> {
+    let b_bits = BBits::new(b_bits.0).ok()?.0;
+    let mask_b = flow_id_mask(b_bits);
+    if target_flow & !mask_b != 0 {
+        return None;
+    }
+
+    let flow_id = primed_flow_id(input, b_bits);
+    (0..max_attempts).find(|&nonce| flow_id(nonce) == target_flow)
+}
+
+/// Online operator step: grinds nonces for `input` until one selects a flow
+/// that is actually funded, and returns that nonce with the flow.
+///
+/// Funded flows outside `D` (`flow_id >= 2^L`) are ignored. The search gives
+/// up after 100x the expected `2^B / |funded ∩ D|` attempts and returns
+/// `None`, like [`find_valid_nonce`] does.
+pub fn select_flow_for_input(
+    input: u32,
+    funded_flows: &[PresignedFlow],
+    b_bits: BBits,
+    l_bits: LBits,
+) -> Option<(u64, &PresignedFlow)> {
+    let (b_bits, l_bits) = (b_bits.0, l_bits.0);
+    let funded: HashMap<u32, &PresignedFlow> = funded_flows
+        .iter()
+        .filter(|flow| flow_id_in_range(flow.flow_id, l_bits))
+        .map(|flow| (flow.flow_id, flow))
+        .collect();
+    if funded.is_empty() {
+        return None;
+    }
+
+    let expected_attempts =
+        (1u64.checked_shl(b_bits as u32).unwrap_or(u64::MAX))
+            / funded.len() as u64;
+    let max_attempts = max_search_attempts(expected_attempts.max(1)).ok()?;
+
+    let flow_id = primed_flow_id(input, b_bits);
+    (0..max_attempts).find_map(|nonce| {
+        funded.get(&flow_id(nonce)).map(|&flow| (nonce, flow))
+    })
+}
+
+/// Binomial coefficient `C(n, k)`, or `None` if it does not fit in a
+/// `usize`.
+pub fn binomial(n: usize, k: usize) -> Option<usize> {
+    if k > n {
+        return Some(0);
+    }
+    // Each partial product is C(n, i) * (n - i) = C(n, i + 1) * (i + 1), so
+    // the division is exact; widening keeps the product from overflowing
+    // before it
+    (0..k.min(n - k)).try_fold(1usize, |acc, i| {
+        let product = (acc as u128).checked_mul((n - i) as u128)?;
+        usize::try_from(product / (i + 1) as u128).ok()
+    })
+}
+
+/// Estimated bytes the signers must store after presigning: one Schnorr
+/// signature per `k`-of-`n` signer subset, flow and step, i.e.
+/// `C(n, k) * 2^L * steps * 64`, plus the locking script of every step of
+/// every flow.
+///
+/// With `C(n, k)` subsets this grows quickly with `k` up to `n / 2`, which
+/// makes the storage cost of a parameter choice concrete. Fails with
+/// [`CoreError::StorageOverflow`] if the estimate does not fit in a `usize`.
+pub fn presigning_storage_bytes(
+    config: &ColliderVmConfig,
+) -> Result<usize, CoreError> {
+    config.validate()?;
+    let flows = 1usize << config.l;
+    let signatures = binomial(config.n, config.k)
+        .and_then(|subsets| subsets.checked_mul(flows))
+        .and_then(|sigs| sigs.checked_mul(NUM_STEPS))
+        .and_then(|sigs| sigs.checked_mul(SCHNORR_SIG_SIZE))
+        .ok_or(CoreError::StorageOverflow)?;
+
+    // Every flow's script has the same length: only the prefix nibbles differ,
+    // and each one is pushed as a single opcode.
+    let secp = secp256k1::Secp256k1::new();
+    let sk =
+        secp256k1::SecretKey::from_slice(&[1u8; 32]).expect("valid secret key");
+    let pubkey =
+        PublicKey::new(secp256k1::PublicKey::from_secret_key(&secp, &sk));
+    let prefix = flow_id_to_prefix_bytes(0, config.b);
+    let scripts = build_script_f1_blake3_locked(
+        &pubkey,
+        &prefix,
+        config.b,
+        F1_THRESHOLD,
+        LIMB_LEN,
+    )
+    .len()
+        + build_script_f2_blake3_locked(
+            &pubkey,
+            &prefix,
+            config.b,
+            F2_THRESHOLD,
+            LIMB_LEN,
+        )
+        .len();
+
+    flows
+        .checked_mul(scripts)
+        .and_then(|scripts| scripts.checked_add(signatures))
+        .ok_or(CoreError::StorageOverflow)
+}
+
+/// Assigns every flow of `D` to `k` of the `n` signers for presigning.
+///
+/// Signer slots are dealt out round-robin: flow `d` goes to signers
+/// `(d*k + j) mod n` for `j` in `0..k`. Since `k <= n` those are `k`
+/// distinct signers, and every signer ends up with either
+/// `floor(2^L*k / n)` or `ceil(2^L*k / n)` flows. Signers that get no flow
+/// (only possible when `2^L*k < n`) have no entry in the map.
+///
+/// `m` plays no part: it counts the operators, who each pick any of the
+/// `2^L` presigned flows by grinding, so every flow needs its signatures
+/// regardless of how many operators there are.
+pub fn assign_flows_to_signers(
+    config: &ColliderVmConfig,
+) -> Result<HashMap<usize, Vec<u32>>, CoreError> {
+    config.validate()?;
+
+    let mut assignment: HashMap<usize, Vec<u32>> = HashMap::new();
+    for flow_id in 0..(1u32 << config.l) {
+        for j in 0..config.k {
+            let signer = (flow_id as usize * config.k + j) % config.n;
+            assignment.entry(signer).or_default().push(flow_id);
+        }
+    }
+    Ok(assignment)
+}
+
+/// Convert flow_id => its B/4 digest nibbles, the inverse of
+/// [`truncate_flow_id`]
+pub fn flow_id_to_prefix_bytes(flow_id: u32, b_bits: usize) -> Vec<u8> {
+    assert!(b_bits <= 32);
+    assert_eq!(b_bits % 4, 0, "b_bits must be multiple of 4");
+    let prefix_len = b_bits / 8;
+    let le4 = flow_id.to_le_bytes();
+    let flow_id_prefix_bytes = le4[..prefix_len].to_vec();
+    // Transform to nibbles
+    // For example: [0x12, 0x34] => [0x1, 0x2, 0x3, 0x4]
+    // Or: [0x0d, 0x00] => [0x0, 0xd, 0x0, 0x0]
+    let mut nibbles = Vec::with_capacity(flow_id_prefix_bytes.len() * 2);
+    for &byte in &flow_id_prefix_bytes {
+        // Extract high nibble (first 4 bits)
+        nibbles.push((byte >> 4) & 0x0F);
+        // Extract low nibble (last 4 bits)
+        nibbles.push(byte & 0x0F);
+    }
+    // A B that ends mid-byte keeps that byte's high nibble, stored in the
+    // top 4 bits of the flow id
+    if b_bits % 8 == 4 {
+        nibbles.push(((flow_id >> (b_bits - 4)) & 0x0F) as u8);
+    }
+    nibbles
+}
+
+/// Helper: combine scripts (by just concatenating the raw bytes).
+pub fn combine_scripts(fragments: &[ScriptBuf]) -> ScriptBuf {
+    let mut combined = Vec::new();
+    for frag in fragments {
+        combined.extend(frag.to_bytes());
+    }
+    ScriptBuf::from_bytes(combined)
+}
+
+/// A small helper script that pushes `prefix_data` and does OP_EQUALVERIFY
+/// This is used to check if the top of the stack matches the prefix
+/// For example, if the content of the stack is:
+/// [0x00, 0x0d, 0x00, 0x00]
+/// Then the script needs to check equality of each byte.
+/// We need to take care of the fact that the prefix is now in nibbles.
+/// Also the ordering of elements on the stack.
+/// We need to push the prefix in reverse order to the stack.
+pub fn build_prefix_equalverify(prefix_data: &[u8]) -> ScriptBuf {
+    let mut b = Builder::new();
+
+    // Check each nibble individually, pushing in reverse order to match stack evaluation
+    for &nibble in prefix_data.iter().rev() {
+        // For the nibble value, use push_int for accurate stack comparison
+        b = b.push_int(nibble as i64);
+        b = b.push_opcode(opcodes::all::OP_EQUALVERIFY);
+    }
+
+    b.into_script()
+}
+
+/// `OP_DEPTH <expected> OP_EQUALVERIFY`: fails unless exactly `expected`
+/// items are left on the stack.
+///
+/// Placed before the final `OP_TRUE`, it keeps leftover (possibly truthy)
+/// items from masking an incomplete prefix comparison.
+pub fn build_stack_depth_check(expected: usize) -> ScriptBuf {
+    Builder::new()
+        .push_opcode(opcodes::all::OP_DEPTH)
+        .push_int(expected as i64)
+        .push_opcode(opcodes::all::OP_EQUALVERIFY)
+        .into_script()
+}
+
+pub fn build_drop(items: usize) -> ScriptBuf {
+    let mut b = Builder::new();
+    for _ in 0..items {
+        b = b.push_opcode(opcodes::all::OP_DROP);
+    }
+    b.into_script()
+}
+
+// Reconstructs a 32-bit value `x` from limbs of `limb_len` size.
+// Only supports power-of-2 limb lengths for Bitcoin Script efficiency.
+fn build_script_reconstruct_x(limb_len: u8) -> ScriptBuf {
+    build_script_reconstruct_words(limb_len, 1)
+}
+
+// Reconstructs the first `words` 32-bit little-endian words of the message
+// from the bottom witness limbs, leaving them on the stack in message order
+// (the last word on top). A 64-bit x is two words, low word first: script
+// numbers are at most 4 bytes, so it cannot be rebuilt as a single value.
+// Each word must be below 2^31 to stay a valid script number.
+fn build_script_reconstruct_words(limb_len: u8, words: u8) -> ScriptBuf {
+    // Validate that limb_len_bits is a power of 2
+    assert!(
+        limb_len > 0 && (limb_len & (limb_len - 1)) == 0,
+        "limb_len_bits must be a power of 2"
+    );
+
+    let limbs_needed = 32u8.div_ceil(limb_len);
+
+    let mut b = Builder::new();
+    for word in 0..words {
+        b = b.push_int(0); // acc = 0
+
+        for i in 0..limbs_needed {
+            for _ in 0..limb_len {
+                b = b
+                    .push_opcode(opcodes::all::OP_DUP)
+                    .push_opcode(opcodes::all::OP_ADD);
+            }
+
+            // Pick the i-th limb of `word` from bottom of stack and add to
+            // the accumulator
+            let limb = i64::from(word) * i64::from(limbs_needed) + i64::from(i);
+            b = b
+                .push_opcode(opcodes::all::OP_DEPTH)
+                .push_opcode(opcodes::all::OP_1SUB) // depth - 1 (bottom index)
+                .push_int(limb) // limb index
+                .push_opcode(opcodes::all::OP_SUB) // (depth-1) - limb
+                .push_opcode(opcodes::all::OP_PICK) // copy limb to top
+                .push_opcode(opcodes::all::OP_ADD); // acc += limb
+        }
+    }
+
+    b.into_script()
+}
+
+/// Length in bits of the limbs a step witness pushes its message in.
+///
+/// blake3 accepts any limb length in [4, 32) but due to the way
+/// build_script_reconstruct_x works it must be a power of 2, so the valid
+/// values are 4, 8 and 16. The limb length trades the size of the BLAKE3
+/// snippet against the number of witness limbs it starts from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimbLen(u8);
+
+impl LimbLen {
+    pub fn new(limb_len: u8) -> Result<Self, CoreError> {
+        if !matches!(limb_len, 4 | 8 | 16) {
+            return Err(CoreError::LimbLenUnsupported { limb_len });
+        }
+        Ok(Self(limb_len))
+    }
+
+    /// The limb length in bits
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Largest witness push of a single limb: `2^bits - 1` as a script
+    /// number, used to size witnesses before the message is known.
+    pub fn max_limb_push(self) -> Vec<u8> {
+        encode_scriptnum((1i64 << self.0) - 1)
+    }
+}
+
+/// Default limb length for blake3
+pub const LIMB_LEN: LimbLen = LimbLen(4);
+
+/// Build an F1 script with onchain BLAKE3, checking x>threshold and the top (b_bits/4) nibbles match flow_id_prefix.
+///
+/// The demo uses [`F1_THRESHOLD`] and [`LIMB_LEN`]. Script arithmetic is
+/// limited to 4-byte numbers, so a `threshold` above `i32::MAX` makes the
+/// script unspendable. The witness must push the message in `limb_len`-bit
+/// limbs, see [`message_to_witness_limbs_with_limb`].
+pub fn build_script_f1_blake3_locked(
+    signer_pubkey: &PublicKey,
+    flow_id_prefix: &[u8],
+    b_bits: usize,
+    threshold: u32,
+    limb_len: LimbLen,
+) -> ScriptBuf {
+    build_script_f1_fragments(
+        signer_pubkey,
+        flow_id_prefix,
+        b_bits,
+        threshold,
+        limb_len,
+    )
+    .combine()
+}
+
+/// The F1 locking script split into the fragments it is layered from, in
+/// execution order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct F1Fragments {
+    /// `<signer_pubkey> OP_CHECKSIGVERIFY`
+    pub signature: ScriptBuf,
+    /// Rebuilds x from its witness limbs
+    pub reconstruct: ScriptBuf,
+    /// x > threshold
+    pub predicate: ScriptBuf,
+    /// BLAKE3 of the message limbs
+    pub blake3: ScriptBuf,
+    /// Drops the digest nibbles outside the flow id prefix
+    pub drop: ScriptBuf,
+    /// Compares the remaining nibbles to the flow id prefix
+    pub prefix: ScriptBuf,
+    /// Requires an empty stack
+    pub depth_check: ScriptBuf,
+    /// The final OP_TRUE
+    pub success: ScriptBuf,
+}
+
+impl F1Fragments {
+    /// The fragments, in execution order.
+    pub fn to_vec(&self) -> Vec<ScriptBuf> {
+        vec![
+            self.signature.clone(),
+            self.reconstruct.clone(),
+            self.predicate.clone(),
+            self.blake3.clone(),
+            self.drop.clone(),
+            self.prefix.clone(),
+            self.depth_check.clone(),
+            self.success.clone(),
+        ]
+    }
+
+    /// The full F1 locking script.
+    pub fn combine(&self) -> ScriptBuf {
+        combine_scripts(&self.to_vec())
+    }
+}
+
+/// Same script as [`build_script_f1_blake3_locked`], returned as its
+/// individual fragments.
+pub fn build_script_f1_fragments(
+    signer_pubkey: &PublicKey,
+    flow_id_prefix: &[u8],
+    _b_bits: usize,
+    threshold: u32,
+    limb_len: LimbLen,
+) -> F1Fragments {
+    let schema = MessageSchema::default();
+    F1Fragments {
+        signature: build_script_verify_signature(signer_pubkey),
+        reconstruct: build_script_reconstruct_x(limb_len.bits()),
+        predicate: build_script_f1_predicate(threshold),
+        blake3: build_script_blake3_compute(
+            schema.total_msg_len(),
+            limb_len.bits(),
+        ),
+        drop: build_drop(BLAKE3_DIGEST_NIBBLES - flow_id_prefix.len()),
+        prefix: build_prefix_equalverify(flow_id_prefix),
+        depth_check: build_stack_depth_check(0),
+        success: Builder::new().push_opcode(OP_TRUE).into_script(),
+    }
+}
+
+/// Byte size of the BLAKE3 locking script of a step checking `predicate`
+/// with a `b_bits` prefix and `limb_len`-bit limbs, without assembling it.
+///
+/// Every fragment but the BLAKE3 compute snippet has a closed-form size;
+/// the snippet is built on its own to measure it. The result is exact for
+/// [`build_script_f1_blake3_locked`] and the other step builders.
+pub fn estimate_locking_script_size(
+    b_bits: usize,
+    limb_len: LimbLen,
+    predicate: &ScriptPredicate,
+) -> usize {
+    let total_msg_len = MessageSchema::default().total_msg_len();
+    let blake3 =
+        build_script_blake3_compute(total_msg_len, limb_len.bits()).len();
+    let prefix_nibbles = b_bits / 4;
+
+    // <x-only key> OP_CHECKSIGVERIFY
+    let signature = 1 + 32 + 1;
+    let reconstruct = build_script_reconstruct_x(limb_len.bits()).len();
+    // One OP_DROP per digest nibble outside the prefix
+    let drop = BLAKE3_DIGEST_NIBBLES - prefix_nibbles;
+    // <nibble> OP_EQUALVERIFY, each nibble a single-byte small int push
+    let prefix = 2 * prefix_nibbles;
+    // OP_DEPTH OP_0 OP_EQUALVERIFY, then OP_TRUE
+    let tail = 3 + 1;
+    signature
+        + reconstruct
+        + predicate.to_script().len()
+        + blake3
+        + drop
+        + prefix
+        + tail
+}
+
+/// Structured (uncompiled) form of [`build_script_f1_blake3_locked`], for
+/// callers that want to compose or re-optimize it further. The hashed
+/// message length is taken from `schema`.
+///
+/// Panics if `schema`'s x is not 4 bytes wide, such as
+/// [`MessageSchema::u64_input`].
+pub fn build_script_f1_blake3_locked_structured(
+    signer_pubkey: &PublicKey,
+    flow_id_prefix: &[u8],
+    _b_bits: usize,
+    threshold: u32,
+    limb_len: LimbLen,
+    schema: &MessageSchema,
+) -> Script {
+    script! {
+        { build_script_verify_signature(signer_pubkey) }
+        { build_script_step_body_with_limb(
+            build_script_f1_predicate(threshold),
+            flow_id_prefix,
+            schema,
+            limb_len,
+        ) }
+    }
+}
+
+/// Build an F2 script with onchain BLAKE3, checking x<threshold and prefix
+///
+/// The demo uses [`F2_THRESHOLD`] and [`LIMB_LEN`].
+pub fn build_script_f2_blake3_locked(
+    signer_pubkey: &PublicKey,
+    flow_id_prefix: &[u8],
+    b_bits: usize,
+    threshold: u32,
+    limb_len: LimbLen,
+) -> ScriptBuf {
+    build_script_f2_blake3_locked_structured(
+        signer_pubkey,
+        flow_id_prefix,
+        b_bits,
+        threshold,
+        limb_len,
+        &MessageSchema::default(),
+    )
+    .compile()
+}
+
+/// Structured (uncompiled) form of [`build_script_f2_blake3_locked`], for
+/// callers that want to compose or re-optimize it further. The hashed
+/// message length is taken from `schema`.
+///
+/// Panics if `schema`'s x is not 4 bytes wide, such as
+/// [`MessageSchema::u64_input`].
+pub fn build_script_f2_blake3_locked_structured(
+    signer_pubkey: &PublicKey,
+    flow_id_prefix: &[u8],
+    _b_bits: usize,
+    threshold: u32,
+    limb_len: LimbLen,
+    schema: &MessageSchema,
+) -> Script {
+    script! {
+        { build_script_verify_signature(signer_pubkey) }
+        { build_script_step_body_with_limb(
+            build_script_f2_predicate(threshold),
+            flow_id_prefix,
+            schema,
+            limb_len,
+        ) }
+    }
+}
+
+/// Build a step script with onchain BLAKE3 and the prefix check, gated on an
+/// arbitrary `predicate` instead of F1's or F2's fixed comparison.
+pub fn build_script_predicate_locked(
+    signer_pubkey: &PublicKey,
+    flow_id_prefix: &[u8],
+    _b_bits: usize,
+    predicate: &ScriptPredicate,
+) -> ScriptBuf {
+    script! {
+        { build_script_verify_signature(signer_pubkey) }
+        { build_script_step_body(
+            predicate.to_script(),
+            flow_id_prefix,
+            &MessageSchema::default(),
+        ) }
+    }
+    .compile()
+}
+
+/// `<signer_pubkey> OP_CHECKSIGVERIFY`, the first part of every step's
+/// locking script.
+pub(crate) fn build_script_verify_signature(
+    signer_pubkey: &PublicKey,
+) -> ScriptBuf {
+    let mut b = Builder::new();
+    b = b.push_x_only_key(&XOnlyPublicKey::from(signer_pubkey.inner));
+    b.push_opcode(opcodes::all::OP_CHECKSIGVERIFY).into_script()
+}
+
+/// `<pk_1> OP_CHECKSIG <pk_2> OP_CHECKSIGADD ... <pk_n> OP_CHECKSIGADD
+/// <threshold> OP_NUMEQUALVERIFY`: at least `threshold` of the
+/// `signer_pubkeys` must sign, on-chain instead of a MuSig2 aggregate.
+///
+/// The witness has one slot per key, empty for the keys not signing, with
+/// the one of `pk_1` on top.
+fn build_script_verify_multisig(
+    signer_pubkeys: &[PublicKey],
+    threshold: usize,
+) -> Result<ScriptBuf, CoreError> {
+    if !(1..=signer_pubkeys.len()).contains(&threshold) {
+        return Err(CoreError::ThresholdOutOfRange {
+            threshold,
+            signers: signer_pubkeys.len(),
+        });
+    }
+    let mut b = Builder::new();
+    for (i, pk) in signer_pubkeys.iter().enumerate() {
+        b = b.push_x_only_key(&XOnlyPublicKey::from(pk.inner));
+        b = b.push_opcode(if i == 0 {
+            opcodes::all::OP_CHECKSIG
+        } else {
+            opcodes::all::OP_CHECKSIGADD
+        });
+    }
+    Ok(b.push_int(threshold as i64)
+        .push_opcode(opcodes::all::OP_NUMEQUALVERIFY)
+        .into_script())
+}
+
+/// [`build_script_f1_blake3_locked`] (with [`F1_THRESHOLD`] and
+/// [`LIMB_LEN`]) requiring `threshold` of `signer_pubkeys` to sign, see
+/// `transactions::finalize_multisig_lock_tx` for the witness.
+///
+/// Spending needs no MuSig2 round between the signers, at the cost of one
+/// key and one signature slot per signer instead of a single one. Fails
+/// with [`CoreError::ThresholdOutOfRange`] unless `threshold` is in
+/// `[1, signer_pubkeys.len()]`.
+pub fn build_script_f1_multisig_locked(
+    signer_pubkeys: &[PublicKey],
+    threshold: usize,
+    flow_id_prefix: &[u8],
+) -> Result<ScriptBuf, CoreError> {
+    Ok(combine_scripts(&[
+        build_script_verify_multisig(signer_pubkeys, threshold)?,
+        build_script_step_body(
+            build_script_f1_predicate(F1_THRESHOLD),
+            flow_id_prefix,
+            &MessageSchema::default(),
+        ),
+    ]))
+}
+
+/// Same as [`build_script_f1_blake3_locked`] (with [`LIMB_LEN`]) for
+/// [`HashChoice::Blake3`], or its `OP_SHA256` counterpart. The witness
+/// comes from [`step_witness_with_hash`].
+///
+/// [`HashChoice::Sha256`] needs `OP_CAT` and fails with
+/// [`CoreError::OpCatNotAllowed`] unless `allow_op_cat` is set.
+pub fn build_script_f1_locked_with_hash(
+    signer_pubkey: &PublicKey,
+    flow_id_prefix: &[u8],
+    b_bits: usize,
+    threshold: u32,
+    hash: HashChoice,
+    allow_op_cat: bool,
+) -> Result<ScriptBuf, CoreError> {
+    match hash {
+        HashChoice::Blake3 => Ok(build_script_f1_blake3_locked(
+            signer_pubkey,
+            flow_id_prefix,
+            b_bits,
+            threshold,
+            LIMB_LEN,
+        )),
+        HashChoice::Sha256 if !allow_op_cat => Err(CoreError::OpCatNotAllowed),
+        HashChoice::Sha256 => Ok(combine_scripts(&[
+            build_script_verify_signature(signer_pubkey),
+            build_script_sha256_step_body(
+                build_script_f1_predicate(threshold),
+                flow_id_prefix,
+            ),
+        ])),
+    }
+}
+
+/// Same as [`build_script_f2_blake3_locked`] (with [`LIMB_LEN`]) for
+/// [`HashChoice::Blake3`], or its `OP_SHA256` counterpart. The witness
+/// comes from [`step_witness_with_hash`].
+///
+/// [`HashChoice::Sha256`] needs `OP_CAT` and fails with
+/// [`CoreError::OpCatNotAllowed`] unless `allow_op_cat` is set.
+pub fn build_script_f2_locked_with_hash(
+    signer_pubkey: &PublicKey,
+    flow_id_prefix: &[u8],
+    b_bits: usize,
+    threshold: u32,
+    hash: HashChoice,
+    allow_op_cat: bool,
+) -> Result<ScriptBuf, CoreError> {
+    match hash {
+        HashChoice::Blake3 => Ok(build_script_f2_blake3_locked(
+            signer_pubkey,
+            flow_id_prefix,
+            b_bits,
+            threshold,
+            LIMB_LEN,
+        )),
+        HashChoice::Sha256 if !allow_op_cat => Err(CoreError::OpCatNotAllowed),
+        HashChoice::Sha256 => Ok(combine_scripts(&[
+            build_script_verify_signature(signer_pubkey),
+            build_script_sha256_step_body(
+                build_script_f2_predicate(threshold),
+                flow_id_prefix,
+            ),
+        ])),
+    }
+}
+
+/// The `OP_SHA256` step body: checks `predicate` on x, hashes
+/// `x || nonce` and compares the digest to `flow_id_prefix` (nibbles, as
+/// from [`flow_id_to_prefix_bytes`]) followed by the witness suffix.
+///
+/// Expects the witness of [`step_witness_with_hash`]: the digest suffix,
+/// the 8-byte nonce and x as a script number, x on top. x must be positive:
+/// a negative zero would pad to the bytes of another x.
+pub(crate) fn build_script_sha256_step_body(
+    predicate: ScriptBuf,
+    flow_id_prefix: &[u8],
+) -> ScriptBuf {
+    assert_eq!(
+        flow_id_prefix.len() % 2,
+        0,
+        "the SHA-256 scripts need a B multiple of 8"
+    );
+    let prefix = PushBytesBuf::try_from(
+        flow_id_prefix
+            .chunks(2)
+            .map(|nibbles| (nibbles[0] << 4) | nibbles[1])
+            .collect::<Vec<_>>(),
+    )
+    .expect("at most 4 prefix bytes");
+
+    let x_guard = Builder::new()
+        .push_opcode(opcodes::all::OP_DUP)
+        .push_int(0)
+        .push_opcode(opcodes::all::OP_GREATERTHAN)
+        .push_opcode(opcodes::all::OP_VERIFY)
+        .push_opcode(opcodes::all::OP_DUP)
+        .into_script();
+    // Zero-pad the minimal encoding of x to its 4 little-endian bytes
+    let mut pad = Builder::new();
+    for _ in 0..4 {
+        pad = pad
+            .push_opcode(opcodes::all::OP_SIZE)
+            .push_int(4)
+            .push_opcode(opcodes::all::OP_LESSTHAN)
+            .push_opcode(opcodes::all::OP_IF)
+            .push_slice([0u8])
+            .push_opcode(opcodes::all::OP_CAT)
+            .push_opcode(opcodes::all::OP_ENDIF);
+    }
+    let hash_check = Builder::new()
+        .push_opcode(opcodes::all::OP_SWAP)
+        .push_opcode(opcodes::all::OP_SIZE)
+        .push_int(8)
+        .push_opcode(opcodes::all::OP_EQUALVERIFY)
+        .push_opcode(opcodes::all::OP_CAT)
+        .push_opcode(opcodes::all::OP_SHA256)
+        // prefix || suffix, against the digest
+        .push_opcode(opcodes::all::OP_SWAP)
+        .push_slice(prefix)
+        .push_opcode(opcodes::all::OP_SWAP)
+        .push_opcode(opcodes::all::OP_CAT)
+        .push_opcode(opcodes::all::OP_EQUALVERIFY)
+        .into_script();
+    combine_scripts(&[
+        x_guard,
+        predicate,
+        pad.into_script(),
+        hash_check,
+        build_stack_depth_check(0),
+        Builder::new().push_opcode(OP_TRUE).into_script(),
+    ])
+}
+
+/// The step witness for scripts built with `hash`, without the signature:
+/// the message limbs for BLAKE3, or the digest suffix past the `B`-bit
+/// prefix, the nonce and x for SHA-256.
+pub fn step_witness_with_hash(
+    x: u32,
+    nonce: u64,
+    b_bits: usize,
+    hash: HashChoice,
+) -> Vec<Vec<u8>> {
+    match hash {
+        HashChoice::Blake3 => message_to_witness_limbs(x, nonce),
+        HashChoice::Sha256 => {
+            let message = MessageSchema::default()
+                .encode(&[&x.to_le_bytes(), &nonce.to_le_bytes()]);
+            let digest = Sha256FlowHasher.digest(&message);
+            vec![
+                digest[b_bits / 8..].to_vec(),
+                nonce.to_le_bytes().to_vec(),
+                encode_scriptnum(i64::from(x)),
+            ]
+        }
+    }
+}
+
+/// A comparison of the reconstructed x against constants, as run on-chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScriptPredicate {
+    /// x > threshold (`OP_GREATERTHAN`)
+    GreaterThan(u32),
+    /// x >= threshold (`OP_GREATERTHANOREQUAL`)
+    GreaterThanOrEqual(u32),
+    /// x < threshold (`OP_LESSTHAN`)
+    LessThan(u32),
+    /// x <= threshold (`OP_LESSTHANOREQUAL`)
+    LessThanOrEqual(u32),
+    /// x == value (`OP_NUMEQUAL`)
+    Equal(u32),
+    /// min <= x < max (`OP_WITHIN`): the lower bound is inclusive, the upper
+    /// bound exclusive
+    InRange(u32, u32),
+}
+
+impl ScriptPredicate {
+    /// F1: x > F1_THRESHOLD
+    pub const F1: ScriptPredicate = ScriptPredicate::GreaterThan(F1_THRESHOLD);
+    /// F2: x < F2_THRESHOLD
+    pub const F2: ScriptPredicate = ScriptPredicate::LessThan(F2_THRESHOLD);
+
+    /// `<constants> <comparison> OP_VERIFY`, run with x on top of the stack.
+    pub fn to_script(&self) -> ScriptBuf {
+        let (constants, opcode) = match *self {
+            ScriptPredicate::GreaterThan(t) => {
+                (vec![t], opcodes::all::OP_GREATERTHAN)
+            }
+            ScriptPredicate::GreaterThanOrEqual(t) => {
+                (vec![t], opcodes::all::OP_GREATERTHANOREQUAL)
+            }
+            ScriptPredicate::LessThan(t) => {
+                (vec![t], opcodes::all::OP_LESSTHAN)
+            }
+            ScriptPredicate::LessThanOrEqual(t) => {
+                (vec![t], opcodes::all::OP_LESSTHANOREQUAL)
+            }
+            ScriptPredicate::Equal(v) => (vec![v], opcodes::all::OP_NUMEQUAL),
+            ScriptPredicate::InRange(min, max) => {
+                (vec![min, max], opcodes::all::OP_WITHIN)
+            }
+        };
+        let mut b = Builder::new();
+        for constant in constants {
+            b = b.push_int(constant as i64);
+        }
+        b.push_opcode(opcode)
+            .push_opcode(opcodes::all::OP_VERIFY)
+            .into_script()
+    }
+}
+
+/// Off-chain twin of [`ScriptPredicate::to_script`]: whether the on-chain
+/// check accepts `x`.
+///
+/// Script arithmetic only takes 4-byte numbers, so any operand above
+/// `i32::MAX` makes the comparison fail, whatever its outcome would be.
+pub fn predicate_holds(predicate: &ScriptPredicate, x: u32) -> bool {
+    let fits = |n: u32| n <= i32::MAX as u32;
+    match *predicate {
+        ScriptPredicate::GreaterThan(t) => fits(x) && fits(t) && x > t,
+        ScriptPredicate::GreaterThanOrEqual(t) => fits(x) && fits(t) && x >= t,
+        ScriptPredicate::LessThan(t) => fits(x) && fits(t) && x < t,
+        ScriptPredicate::LessThanOrEqual(t) => fits(x) && fits(t) && x <= t,
+        ScriptPredicate::Equal(v) => fits(x) && fits(v) && x == v,
+        ScriptPredicate::InRange(min, max) => {
+            fits(x) && fits(min) && fits(max) && min <= x && x < max
+        }
+    }
+}
+
+/// The inputs accepted by every one of `predicates`, as an inclusive
+/// `(min, max)` range, or `None` if no input is.
+///
+/// Each predicate accepts a single range of script numbers, so their
+/// intersection is a range too. With no predicates this is every input a
+/// script number can hold, `[0, i32::MAX]`.
+pub fn feasible_input_range(
+    predicates: &[ScriptPredicate],
+) -> Option<(u32, u32)> {
+    const MAX: u32 = i32::MAX as u32;
+    predicates.iter().try_fold((0, MAX), |(lo, hi), predicate| {
+        let (min, max) = match *predicate {
+            ScriptPredicate::GreaterThan(t) => (t.checked_add(1)?, MAX),
+            ScriptPredicate::GreaterThanOrEqual(t) => (t, MAX),
+            ScriptPredicate::LessThan(t) if t <= MAX => (0, t.checked_sub(1)?),
+            ScriptPredicate::LessThanOrEqual(t) if t <= MAX => (0, t),
+            ScriptPredicate::Equal(v) => (v, v),
+            ScriptPredicate::InRange(min, max) if max <= MAX => {
+                (min, max.checked_sub(1)?)
+            }
+            // An operand above i32::MAX fails every comparison
+            _ => return None,
+        };
+        let (lo, hi) = (lo.max(min), hi.min(max));
+        (lo <= hi).then_some((lo, hi))
+    })
+}
+
+/// Rejects a set of step predicates that no input can satisfy, before any
+/// grinding or presigning is spent on it. Returns the feasible range, see
+/// [`feasible_input_range`].
+pub fn check_predicates_feasible(
+    predicates: &[ScriptPredicate],
+) -> Result<(u32, u32), CoreError> {
+    feasible_input_range(predicates)
+        .ok_or_else(|| CoreError::InfeasiblePredicates(predicates.to_vec()))
+}
+
+/// F1 predicate on the reconstructed x: x > threshold
+pub(crate) fn build_script_f1_predicate(threshold: u32) -> ScriptBuf {
+    ScriptPredicate::GreaterThan(threshold).to_script()
+}
+
+/// F2 predicate on the reconstructed x: x < threshold
+pub(crate) fn build_script_f2_predicate(threshold: u32) -> ScriptBuf {
+    ScriptPredicate::LessThan(threshold).to_script()
+}
+
+/// Number of nibbles the BLAKE3 compute script leaves on the stack
+const BLAKE3_DIGEST_NIBBLES: usize = 64;
+
+/// On-chain BLAKE3 over a `total_msg_len`-byte message given as
+/// `limb_len`-bit limbs, optimized.
+///
+/// The digest is left as [`BLAKE3_DIGEST_NIBBLES`] nibbles whatever the
+/// limb length, so the drop count after it does not depend on `limb_len`.
+fn build_script_blake3_compute(
+    total_msg_len: usize,
+    limb_len: u8,
+) -> ScriptBuf {
+    let compiled =
+        blake3_compute_script_with_limb(total_msg_len, limb_len).compile();
+    // Important: Optimize the compute script
+    let optimized = optimizer::optimize(compiled);
+    ScriptBuf::from_bytes(optimized.to_bytes())
+}
+
+/// Everything a step's locking script runs after the signature check: it
+/// reconstructs x from the witness limbs, applies `predicate`, hashes the
+/// message with BLAKE3 and compares the digest prefix to `flow_id_prefix`.
+///
+/// Runs without a transaction context, which makes it usable for local
+/// execution of a step.
+pub(crate) fn build_script_step_body(
+    predicate: ScriptBuf,
+    flow_id_prefix: &[u8],
+    schema: &MessageSchema,
+) -> ScriptBuf {
+    build_script_step_body_with_limb(
+        predicate,
+        flow_id_prefix,
+        schema,
+        LIMB_LEN,
+    )
+}
+
+/// [`build_script_step_body`] for a witness pushed in `limb_len`-bit limbs.
+///
+/// Panics if `schema`'s x is not 4 bytes: the predicate would only check
+/// its low word.
+pub(crate) fn build_script_step_body_with_limb(
+    predicate: ScriptBuf,
+    flow_id_prefix: &[u8],
+    schema: &MessageSchema,
+    limb_len: LimbLen,
+) -> ScriptBuf {
+    let x_width = schema.fields()[0].width;
+    assert_eq!(x_width, 4, "step scripts need a 4-byte x, got {x_width}");
+    let prefix_len = flow_id_prefix.len();
+    let total_msg_len = schema.total_msg_len();
+
+    // 1) Reconstruct x from its first 32 / limb_len limbs
+    let reconstruct_x_script = build_script_reconstruct_x(limb_len.bits());
+
+    // 2) BLAKE3 compute snippet - OPTIMIZED
+    let compute_blake3_script =
+        build_script_blake3_compute(total_msg_len, limb_len.bits());
+
+    // 3) drop limbs we don't need for prefix check
+    // Needed nibbles: prefix_len (because now represented as nibbles) or B / 4
+    let needed_nibbles = prefix_len;
+    let drop_script = build_drop(BLAKE3_DIGEST_NIBBLES - needed_nibbles);
+
+    // 4) compare prefix => OP_EQUALVERIFY
+    let prefix_cmp_script = build_prefix_equalverify(flow_id_prefix);
+
+    // 5) nothing may be left over for OP_TRUE to sit on
+    let depth_check_script = build_stack_depth_check(0);
+
+    // 6) push OP_TRUE
+    let success_script = Builder::new().push_opcode(OP_TRUE).into_script();
+
+    combine_scripts(&[
+        reconstruct_x_script,
+        predicate,
+        compute_blake3_script,
+        drop_script,
+        prefix_cmp_script,
+        depth_check_script,
+        success_script,
+    ])
+}
+
+/// Runs `witness || locking` and returns the peak number of stack items
+/// (main + alt stack) reached during execution.
+///
+/// Useful to check the headroom against the 1000-element consensus limit.
+pub fn measure_max_stack(witness: &ScriptBuf, locking: &ScriptBuf) -> usize {
+    let script = combine_scripts(&[witness.clone(), locking.clone()]);
+    execute_script_buf(script).stats.max_nb_stack_items
+}
+
+/// Executes `script` step by step and counts how many times each opcode is
+/// run. Pushes are counted under their push opcode.
+///
+/// Unlike `execute_script_buf`'s aggregated `stats`, this pinpoints the hot
+/// opcodes of a script (e.g. the BLAKE3 compute snippet).
+pub fn profile_script(script: ScriptBuf) -> HashMap<Opcode, u64> {
+    let mut exec = Exec::new(
+        ExecCtx::Tapscript,
+        Options::default(),
+        TxTemplate {
+            tx: Transaction {
+                version: Version::TWO,
+                lock_time: absolute::LockTime::ZERO,
+                input: vec![],
+                output: vec![],
+            },
+            prevouts: vec![],
+            input_idx: 0,
+            taproot_annex_scriptleaf: Some((TapLeafHash::all_zeros(), None)),
+        },
+        script,
+        vec![],
+    )
+    .expect("error creating exec");
+
+    let mut counts = HashMap::new();
+    loop {
+        let next = exec
+            .remaining_script()
+            .as_bytes()
+            .first()
+            .map(|&byte| Opcode::from(byte));
+        if exec.exec_next().is_err() {
+            break;
+        }
+        if let Some(opcode) = next {
+            *counts.entry(opcode).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+pub fn message_to_witness_limbs(x: u32, nonce: u64) -> Vec<Vec<u8>> {
+    message_to_witness_limbs_with_schema(
+        &MessageSchema::default(),
+        &[&x.to_le_bytes(), &nonce.to_le_bytes()],
+    )
+}
+
+/// Pushes of the witness stack `items`, bottom first: the script that puts
+/// them on the stack when run ahead of a locking script.
+pub(crate) fn witness_pushes(items: &[Vec<u8>]) -> ScriptBuf {
+    let mut b = Builder::new();
+    for item in items {
+        let item: &bitcoin::script::PushBytes = item
+            .as_slice()
+            .try_into()
+            .expect("witness item fits a push");
+        b = b.push_slice(item);
+    }
+    b.into_script()
+}
+
+/// Witness limbs for `x || nonce` pushed in `limb_len`-bit limbs, for step
+/// scripts built with a non-default limb length.
+pub fn message_to_witness_limbs_with_limb(
+    x: u32,
+    nonce: u64,
+    limb_len: LimbLen,
+) -> Vec<Vec<u8>> {
+    let message = MessageSchema::default()
+        .encode(&[&x.to_le_bytes(), &nonce.to_le_bytes()]);
+
+    blake3_message_to_limbs(&message, limb_len.bits())
+        .into_iter()
+        .map(|limb| encode_scriptnum(limb.into()))
+        .collect()
+}
+
+/// Witness limbs for the 16-byte message of a 64-bit `x`, see
+/// [`MessageSchema::u64_input`].
+pub fn message_to_witness_limbs_u64(x: u64, nonce: u64) -> Vec<Vec<u8>> {
+    message_to_witness_limbs_with_schema(
+        &MessageSchema::u64_input(),
+        &[&x.to_le_bytes(), &nonce.to_le_bytes()],
+    )
+}
+
+/// Witness limbs for a message laid out by `schema`.
+pub fn message_to_witness_limbs_with_schema(
+    schema: &MessageSchema,
+    values: &[&[u8]],
+) -> Vec<Vec<u8>> {
+    let message = schema.encode(values);
+
+    blake3_message_to_limbs(&message, LIMB_LEN.bits())
+        .into_iter()
+        .map(|limb| encode_scriptnum(limb.into()))
+        .collect()
+}
+
+/// Recovers `(x, nonce)` from the message limbs at the start of a step's
+/// script-path witness (limbs, signature, script, control block), pushed
+/// in `limb_len`-bit limbs.
+///
+/// The limbs carry the message as little-endian `u32` words, each pushed as
+/// `32 / limb_len` limbs, most significant first (the order the locking
+/// script rebuilds x in). The decoded pair is re-encoded and must give back
+/// the exact same limbs, so malformed or tampered limbs are rejected rather
+/// than misread.
+pub fn decode_witness_message(
+    witness: &Witness,
+    limb_len: LimbLen,
+) -> Option<(u32, u64)> {
+    let num_limbs = message_to_witness_limbs_with_limb(0, 0, limb_len).len();
+    if witness.len() != num_limbs + 3 {
+        return None;
+    }
+    let limbs = witness.iter().take(num_limbs).collect::<Vec<_>>();
+
+    let bits = limb_len.bits();
+    let limbs_per_word = (32 / bits) as usize;
+    let mut words = [0u32; 3];
+    for (i, word) in words.iter_mut().enumerate() {
+        for limb in limbs.get(i * limbs_per_word..(i + 1) * limbs_per_word)? {
+            // Script numbers are little-endian; a non-minimal or negative
+            // push fails the re-encoding check below
+            if limb.len() > limb_len.max_limb_push().len() {
+                return None;
+            }
+            let value = limb
+                .iter()
+                .rev()
+                .fold(0u32, |acc, &byte| (acc << 8) | u32::from(byte));
+            if value >= 1 << bits {
+                return None;
+            }
+            *word = (*word << bits) | value;
+        }
+    }
+    let x = words[0];
+    let nonce = u64::from(words[1]) | (u64::from(words[2]) << 32);
+
+    let reencoded = message_to_witness_limbs_with_limb(x, nonce, limb_len);
+    reencoded
+        .iter()
+        .map(Vec::as_slice)
+        .eq(limbs.iter().copied())
+        .then_some((x, nonce))
+}
+
+/// Off-chain version of the on-chain prefix check: the flow selected by
+/// `x || nonce`, if it lies in `D`, and `None` otherwise.
+///
+/// Nothing is printed, so this is cheap enough to screen operator-submitted
+/// witnesses before broadcasting.
+pub fn verify_flow_membership(
+    x: u32,
+    nonce: u64,
+    b_bits: BBits,
+    l_bits: LBits,
+) -> Option<u32> {
+    let (b_bits, l_bits) = (b_bits.0, l_bits.0);
+    check_flow_params(b_bits, l_bits).ok()?;
+    let flow_id = truncate_flow_id(calculate_flow_id(x, nonce), b_bits);
+    flow_id_in_range(flow_id, l_bits).then_some(flow_id)
+}
+
+/// Auditor check on the witness revealing `x || nonce` (the input of the tx
+/// spending a step output, e.g. f2_tx for F1): recomputes the flow id from
+/// the witness message, pushed in `limb_len`-bit limbs, and checks that it
+/// is `expected_flow_id` and lies in `D`.
+pub fn verify_witness_flow(
+    witness: &Witness,
+    expected_flow_id: u32,
+    b_bits: BBits,
+    l_bits: LBits,
+    limb_len: LimbLen,
+) -> bool {
+    let Some((x, nonce)) = decode_witness_message(witness, limb_len) else {
+        return false;
+    };
+    verify_flow_membership(x, nonce, b_bits, l_bits) == Some(expected_flow_id)
+}
+
+/// What [`benchmark_hash_rate`] measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchMode {
+    /// BLAKE3 of `x || nonce` alone
+    RawHash,
+    /// The nonce search loop: [`grind_kernel`] batches, with the prefix
+    /// extraction and the flow id membership check
+    FullFlowCheck,
+}
+
+/// A basic "hash rate" calibration.
+///
+/// [`BenchMode::FullFlowCheck`] runs the same code as [`find_valid_nonce`],
+/// so its figure is the one nonce searches achieve; pass it to
+/// [`find_valid_nonce_with_style`] to start their ETA from it.
+pub fn benchmark_hash_rate(duration_secs: u64, mode: BenchMode) -> u64 {
+    println!("Calibrating for {duration_secs} seconds...");
+    let pb = ProgressBar::new(100);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner} [{elapsed_precise}] [{bar:40.green/black}] {percent}% {msg}")
+            .unwrap(),
+    );
+    pb.enable_steady_tick(Duration::from_millis(100));
+
+    let start = Instant::now();
+    let end = start + Duration::from_secs(duration_secs);
+
+    let mut count = 0u64;
+    let mut nonce = 0u64;
+    let input = 123u32;
+
+    while Instant::now() < end {
+        match mode {
+            BenchMode::RawHash => {
+                let mut hasher = Hasher::new();
+                hasher.update(&input.to_le_bytes());
+                hasher.update(&nonce.to_le_bytes());
+                std::hint::black_box(hasher.finalize());
+                nonce += 1;
+                count += 1;
+            }
+            BenchMode::FullFlowCheck => {
+                // Checked against D = {0}, as the search does
+                let flow_ids =
+                    grind_kernel(input, nonce, GRIND_KERNEL_BATCH, 32);
+                std::hint::black_box(flow_ids.iter().any(|&id| id < 1));
+                nonce += GRIND_KERNEL_BATCH as u64;
+                count += GRIND_KERNEL_BATCH as u64;
+            }
+        }
+    }
+
+    let dt = start.elapsed().as_secs_f64();
+    let rate = if dt > 0.0 { count as f64 / dt } else { 0.0 };
+    pb.finish_with_message(format!("~{rate:.2} H/s"));
+    rate as u64
+}
+
+// for off-chain use
+pub fn blake3_message_to_limbs(message_bytes: &[u8], limb_len: u8) -> Vec<u32> {
+    let script =
+        blake3_push_message_script_with_limb(message_bytes, limb_len).compile();
+    let res = execute_script_buf(script);
+
+    res.final_stack
+        .0
+        .iter_str()
+        .map(|v| {
+            let mut arr = [0u8; 4];
+            arr[..v.len()].copy_from_slice(&v);
+            u32::from_le_bytes(arr)
+        })
+        .collect()
+}
+
+/// Self-check of the bitvm BLAKE3 snippet against the layout the locking
+/// scripts assume.
+///
+/// Pushes a known message as witness limbs, runs the same optimized compute
+/// script the step scripts embed and checks that the stack holds exactly
+/// the [`BLAKE3_DIGEST_NIBBLES`] nibbles of `blake3::hash`, as laid out by
+/// [`blake3_limbs`]. A bitvm update changing that layout would silently
+/// break the drop and prefix comparison, so callers should fail loudly.
+pub fn verify_blake3_snippet_compat() -> Result<(), String> {
+    let (x, nonce) = (114u32, 3543u64);
+    let schema = MessageSchema::default();
+    let message = schema.encode(&[&x.to_le_bytes(), &nonce.to_le_bytes()]);
+
+    let res = execute_script_buf(combine_scripts(&[
+        witness_pushes(&message_to_witness_limbs(x, nonce)),
+        build_script_blake3_compute(schema.total_msg_len(), LIMB_LEN.bits()),
+    ]));
+    if !res.success {
+        return Err(format!(
+            "bitvm BLAKE3 compute script failed: {:?}",
+            res.error
+        ));
+    }
+
+    let stack = res
+        .final_stack
+        .0
+        .iter_str()
+        .map(|v| v.first().copied().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let expected = blake3_limbs(&message, LIMB_LEN.bits());
+    if stack.len() != BLAKE3_DIGEST_NIBBLES || stack != expected {
+        return Err(format!(
+            "bitvm BLAKE3 snippet output changed: expected {} nibbles {}, got {} items {}",
+            expected.len(),
+            hex::encode(&expected),
+            stack.len(),
+            hex::encode(&stack)
+        ));
+    }
+    Ok(())
+}
+
+/// Expected stack content (bottom to top) after running the BLAKE3 compute
+/// script over `message` pushed with `limb_len`-bit limbs.
+///
+/// bitvm leaves the 32-byte digest as 64 nibbles, high nibble first for each
+/// byte, with the last digest byte on top. The layout does not depend on
+/// `limb_len`, which only affects how the message is pushed.
+pub fn blake3_limbs(message: &[u8], limb_len: u8) -> Vec<u8> {
+    assert!(
+        (4..32).contains(&limb_len),
+        "bitvm blake3 accepts limb lengths in [4, 32)"
+    );
+    blake3::hash(message)
+        .as_bytes()
+        .iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0F])
+        .collect()
+}
+
+pub fn build_script_hash_to_limbs() -> ScriptBuf {
+    let mut builder = Builder::new();
+
+    for _ in 0..56 {
+        builder = builder.push_opcode(opcodes::all::OP_TOALTSTACK);
+    }
+    for i in 0..8 {
+        for j in (0..8).step_by(2) {
+            builder = builder
+                .push_int(j)
+                .push_opcode(opcodes::all::OP_ROLL)
+                .push_int(j + 1)
+                .push_opcode(opcodes::all::OP_ROLL)
+                .push_opcode(opcodes::all::OP_SWAP);
+        }
+        if i != 7 {
+            for _ in 0..8 {
+                builder = builder.push_opcode(opcodes::all::OP_FROMALTSTACK);
+            }
+        }
+    }
+
+    for _ in 0..64 {
+        builder = builder.push_int(0);
+    }
+
+    builder.into_script()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin_script::script;
+    use bitvm::{
+        execute_script_buf,
+        hash::blake3::{
+            blake3_push_message_script_with_limb, blake3_verify_output_script,
+        },
+    };
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_calculate_flow_ids_batch_matches_single() {
+        let input = 123u32;
+        let b_bits = 16;
+        let l_bits = 4;
+        // 16x the expected 2^(B-L) attempts, so some flow ids land in D
+        let nonces: Vec<u64> = (0..1 << 16).collect();
+
+        let batch = calculate_flow_ids_batch(input, &nonces, b_bits);
+        assert_eq!(batch.len(), nonces.len());
+
+        let mask_b = flow_id_mask(b_bits);
+        for (&nonce, &flow_id) in nonces.iter().zip(batch.iter()) {
+            let single = calculate_flow_id(input, nonce) & mask_b;
+            assert_eq!(flow_id, single, "mismatch for nonce {nonce}");
+        }
+
+        // The in-range subset is what a nonce search sees: it starts at the
+        // nonce find_valid_nonce returns
+        let in_range = nonces
+            .iter()
+            .zip(&batch)
+            .filter(|&(_, &flow_id)| flow_id_in_range(flow_id, l_bits))
+            .map(|(&nonce, &flow_id)| (nonce, flow_id))
+            .collect::<Vec<_>>();
+        assert!(!in_range.is_empty());
+        assert_eq!(
+            in_range[0],
+            find_valid_nonce(input, BBits(b_bits), LBits(l_bits)).unwrap()
+        );
+        for &(nonce, flow_id) in &in_range {
+            assert_eq!(
+                verify_flow_membership(
+                    input,
+                    nonce,
+                    BBits(b_bits),
+                    LBits(l_bits)
+                ),
+                Some(flow_id)
+            );
+        }
+    }
+
+    #[test]
+    fn test_grind_kernel_matches_single() {
+        let input = 123u32;
+        for b_bits in [12, 16] {
+            let nonce_base = 5_000u64;
+            let flow_ids = grind_kernel(input, nonce_base, 300, b_bits);
+            assert_eq!(flow_ids.len(), 300);
+            for (nonce, &flow_id) in (nonce_base..).zip(&flow_ids) {
+                assert_eq!(
+                    flow_id,
+                    truncate_flow_id(calculate_flow_id(input, nonce), b_bits),
+                    "mismatch for nonce {nonce}"
+                );
+            }
+        }
+        assert!(grind_kernel(input, 0, 0, 16).is_empty());
+    }
+
+    #[test]
+    fn test_sha256_flow_id_onchain_offchain_agreement() {
+        let mut rng = StdRng::seed_from_u64(277);
+        for b_bits in [8, 16, 24, 32] {
+            let l_bits = b_bits - 6;
+            for _ in 0..3 {
+                let x = rng.gen_range(F1_THRESHOLD + 1..=i32::MAX as u32);
+                let (nonce, flow_id) = (0..)
+                    .map(|nonce| {
+                        let flow_id = truncate_flow_id(
+                            calculate_flow_id_with_hash(
+                                HashChoice::Sha256,
+                                x,
+                                nonce,
+                            ),
+                            b_bits,
+                        );
+                        (nonce, flow_id)
+                    })
+                    .find(|&(_, flow_id)| flow_id < 1 << l_bits)
+                    .unwrap();
+                let body = build_script_sha256_step_body(
+                    build_script_f1_predicate(F1_THRESHOLD),
+                    &flow_id_to_prefix_bytes(flow_id, b_bits),
+                );
+
+                for nonce in nonce..nonce + 3 {
+                    let offchain = truncate_flow_id(
+                        calculate_flow_id_with_hash(
+                            HashChoice::Sha256,
+                            x,
+                            nonce,
+                        ),
+                        b_bits,
+                    );
+                    let witness = witness_pushes(&step_witness_with_hash(
+                        x,
+                        nonce,
+                        b_bits,
+                        HashChoice::Sha256,
+                    ));
+                    let res = execute_script_buf(combine_scripts(&[
+                        witness,
+                        body.clone(),
+                    ]));
+                    assert_eq!(
+                        res.success,
+                        offchain == flow_id,
+                        "B={b_bits} x={x} nonce={nonce}: off-chain flow {offchain}, script locked to {flow_id}"
+                    );
+                }
+
+                // The predicate still applies, and x may not be a negative
+                // zero padding to the bytes of x = 128
+                let mut witness = step_witness_with_hash(
+                    x,
+                    nonce,
+                    b_bits,
+                    HashChoice::Sha256,
+                );
+                *witness.last_mut().unwrap() = vec![0x80];
+                assert!(
+                    !execute_script_buf(combine_scripts(&[
+                        witness_pushes(&witness),
+                        body.clone()
+                    ]))
+                    .success
+                );
+            }
+        }
+
+        let secp = secp256k1::Secp256k1::new();
+        let (_, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        let pubkey = PublicKey::new(pk);
+        let prefix = flow_id_to_prefix_bytes(3, 16);
+        let [blake3, sha256] =
+            [HashChoice::Blake3, HashChoice::Sha256].map(|hash| {
+                build_script_f1_locked_with_hash(
+                    &pubkey,
+                    &prefix,
+                    16,
+                    F1_THRESHOLD,
+                    hash,
+                    true,
+                )
+                .unwrap()
+            });
+        assert_eq!(
+            blake3,
+            build_script_f1_blake3_locked(
+                &pubkey,
+                &prefix,
+                16,
+                F1_THRESHOLD,
+                LIMB_LEN
+            )
+        );
+        assert!(sha256.len() * 100 < blake3.len());
+
+        // OP_CAT is an OP_SUCCESS where it is not active: no opt-in, no script
+        for build in [
+            build_script_f1_locked_with_hash,
+            build_script_f2_locked_with_hash,
+        ] {
+            assert!(matches!(
+                build(
+                    &pubkey,
+                    &prefix,
+                    16,
+                    F1_THRESHOLD,
+                    HashChoice::Sha256,
+                    false
+                ),
+                Err(CoreError::OpCatNotAllowed)
+            ));
+            assert!(
+                build(
+                    &pubkey,
+                    &prefix,
+                    16,
+                    F1_THRESHOLD,
+                    HashChoice::Blake3,
+                    false
+                )
+                .is_ok()
+            );
+        }
+    }
+
+    #[test]
+    fn test_estimate_locking_script_size() {
+        let secp = secp256k1::Secp256k1::new();
+        let (_, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        let signer_pubkey = PublicKey::new(pk);
+        for b_bits in [8, 12, 16, 32] {
+            let flow_id_prefix = flow_id_to_prefix_bytes(0x0d, b_bits);
+            for bits in [4, 8] {
+                let limb_len = LimbLen::new(bits).unwrap();
+                let actual = build_script_f1_blake3_locked(
+                    &signer_pubkey,
+                    &flow_id_prefix,
+                    b_bits,
+                    F1_THRESHOLD,
+                    limb_len,
+                )
+                .len();
+                let estimate = estimate_locking_script_size(
+                    b_bits,
+                    limb_len,
+                    &ScriptPredicate::F1,
+                );
+                assert_eq!(
+                    estimate, actual,
+                    "B={b_bits} limb_len={bits}: estimated {estimate}, built {actual}"
+                );
+            }
+        }
+    }
+
+    /// Witness pushes and F1 locking script without the signature check,
+    /// which needs a transaction context to run.
+    fn f1_body_scripts(x: u32) -> (ScriptBuf, ScriptBuf) {
+        let (nonce, flow_id) =
+            find_valid_nonce(x, BBits(16), LBits(4)).unwrap();
+        let flow_id_prefix = flow_id_to_prefix_bytes(flow_id, 16);
+
+        let witness = witness_pushes(&message_to_witness_limbs(x, nonce));
+
+        let compute = optimizer::optimize(
+            blake3_compute_script_with_limb(12, LIMB_LEN.bits()).compile(),
+        );
+        let locking = combine_scripts(&[
+            build_script_reconstruct_x(LIMB_LEN.bits()),
+            Builder::new()
+                .push_int(F1_THRESHOLD as i64)
+                .push_opcode(opcodes::all::OP_GREATERTHAN)
+                .push_opcode(opcodes::all::OP_VERIFY)
+                .into_script(),
+            ScriptBuf::from_bytes(compute.to_bytes()),
+            build_drop(64 - flow_id_prefix.len()),
+            build_prefix_equalverify(&flow_id_prefix),
+            Builder::new().push_opcode(OP_TRUE).into_script(),
+        ]);
+
+        (witness, locking)
+    }
+
+    #[test]
+    fn test_nibble_aligned_prefix() {
+        let x = 123u32;
+        for (b_bits, l_bits) in [(4, 2), (12, 4), (20, 12)] {
+            let (nonce, flow_id) =
+                find_valid_nonce(x, BBits(b_bits), LBits(l_bits)).unwrap();
+            let flow_id_prefix = flow_id_to_prefix_bytes(flow_id, b_bits);
+            assert_eq!(flow_id_prefix.len(), b_bits / 4);
+
+            let witness = witness_pushes(&message_to_witness_limbs(x, nonce));
+            let body_for = |prefix: &[u8]| {
+                build_script_step_body(
+                    build_script_f1_predicate(F1_THRESHOLD),
+                    prefix,
+                    &MessageSchema::default(),
+                )
+            };
+
+            let res = execute_script_buf(combine_scripts(&[
+                witness.clone(),
+                body_for(&flow_id_prefix),
+            ]));
+            assert!(res.success, "B={b_bits} rejected its own flow");
+
+            // The trailing half-byte nibble is checked too
+            let mut tampered = flow_id_prefix.clone();
+            *tampered.last_mut().unwrap() ^= 1;
+            let res = execute_script_buf(combine_scripts(&[
+                witness,
+                body_for(&tampered),
+            ]));
+            assert!(!res.success, "B={b_bits} accepted a wrong last nibble");
+        }
+
+        assert_eq!(
+            BBits::new(10),
+            Err(CoreError::HashPrefixMisaligned { b_bits: 10 })
+        );
+    }
+
+    #[test]
+    fn test_verify_flow_membership_matches_script() {
+        let (b_bits, l_bits) = (8, 2);
+        for x in [114u32, 150] {
+            let (valid_nonce, flow_id) =
+                find_valid_nonce(x, BBits(b_bits), LBits(l_bits)).unwrap();
+            assert_eq!(
+                verify_flow_membership(
+                    x,
+                    valid_nonce,
+                    BBits(b_bits),
+                    LBits(l_bits)
+                ),
+                Some(flow_id)
+            );
+
+            for nonce in [valid_nonce, valid_nonce + 1, valid_nonce + 2] {
+                let witness =
+                    witness_pushes(&message_to_witness_limbs(x, nonce));
+                // The flows in D whose locking script accepts this witness
+                let accepted = (0..1u32 << l_bits)
+                    .filter(|&d| {
+                        let body = build_script_step_body(
+                            build_script_f1_predicate(F1_THRESHOLD),
+                            &flow_id_to_prefix_bytes(d, b_bits),
+                            &MessageSchema::default(),
+                        );
+                        execute_script_buf(combine_scripts(&[
+                            witness.clone(),
+                            body,
+                        ]))
+                        .success
+                    })
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    verify_flow_membership(
+                        x,
+                        nonce,
+                        BBits(b_bits),
+                        LBits(l_bits)
+                    ),
+                    accepted.first().copied(),
+                    "x={x} nonce={nonce}"
+                );
+                assert!(accepted.len() <= 1);
+            }
+        }
+        assert_eq!(verify_flow_membership(114, 0, BBits(8), LBits(12)), None);
+    }
+
+    #[test]
+    fn test_prefix_check_leaves_empty_stack() {
+        let x = 123u32;
+        let (nonce, flow_id) =
+            find_valid_nonce(x, BBits(16), LBits(4)).unwrap();
+        let flow_id_prefix = flow_id_to_prefix_bytes(flow_id, 16);
+        let (witness, _) = f1_body_scripts(x);
+
+        // Everything up to and including the prefix OP_EQUALVERIFY chain
+        let compute = optimizer::optimize(
+            blake3_compute_script_with_limb(12, LIMB_LEN.bits()).compile(),
+        );
+        let up_to_prefix = combine_scripts(&[
+            witness.clone(),
+            build_script_reconstruct_x(LIMB_LEN.bits()),
+            build_script_f1_predicate(F1_THRESHOLD),
+            ScriptBuf::from_bytes(compute.to_bytes()),
+            build_drop(64 - flow_id_prefix.len()),
+            build_prefix_equalverify(&flow_id_prefix),
+        ]);
+        let res = execute_script_buf(up_to_prefix);
+        assert_eq!(res.final_stack.0.len(), 0, "nonce {nonce} left items");
+
+        // The full body, depth check included, still accepts
+        let body = build_script_step_body(
+            build_script_f1_predicate(F1_THRESHOLD),
+            &flow_id_prefix,
+            &MessageSchema::default(),
+        );
+        let res = execute_script_buf(combine_scripts(&[witness, body]));
+        assert!(res.success);
+        assert_eq!(res.final_stack.0.len(), 1);
+    }
+
+    #[test]
+    fn test_stack_depth_check_rejects_junk() {
+        let flow_id_prefix = vec![0x00, 0x0d, 0x00, 0x00];
+        let push_prefix = |b: Builder| {
+            flow_id_prefix
+                .iter()
+                .fold(b, |b, nibble| b.push_int(*nibble as i64))
+        };
+        let hardened = combine_scripts(&[
+            build_prefix_equalverify(&flow_id_prefix),
+            build_stack_depth_check(0),
+            Builder::new().push_opcode(OP_TRUE).into_script(),
+        ]);
+
+        let clean = push_prefix(Builder::new()).into_script();
+        let res =
+            execute_script_buf(combine_scripts(&[clean, hardened.clone()]));
+        assert!(res.success);
+
+        // A truthy junk item below the prefix survives the comparison
+        let junk = push_prefix(Builder::new().push_int(1)).into_script();
+        let res = execute_script_buf(combine_scripts(&[junk, hardened]));
+        assert!(!res.success, "junk below the prefix must be rejected");
+    }
+
+    #[test]
+    fn test_measure_max_stack_f1_body() {
+        let (witness, locking) = f1_body_scripts(123);
+
+        let peak = measure_max_stack(&witness, &locking);
+        println!("F1 peak stack depth: {peak} / 1000");
+        assert!(peak > 0);
+        assert!(peak < 1000, "F1 peak stack depth {peak} exceeds limit");
+    }
+
+    #[test]
+    fn test_profile_script_f1_body() {
+        let (witness, locking) = f1_body_scripts(123);
+        let profile = profile_script(combine_scripts(&[witness, locking]));
+
+        let mut counts = profile.iter().collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.cmp(a.1));
+        for (opcode, count) in counts.iter().take(10) {
+            println!("{opcode:?}: {count}");
+        }
+
+        // BLAKE3's u32 additions dominate the run
+        let (top, _) = counts[0];
+        assert_eq!(*top, opcodes::all::OP_ADD, "top opcodes: {counts:?}");
+    }
+
+    #[test]
+    fn test_calculate_flow_id_offset() {
+        let (input, nonce) = (123u32, 3543u64);
+        assert_eq!(
+            calculate_flow_id_offset(input, nonce, 0),
+            calculate_flow_id(input, nonce)
+        );
+
+        let ids = (0..=28)
+            .map(|offset| calculate_flow_id_offset(input, nonce, offset))
+            .collect::<std::collections::HashSet<_>>();
+        assert!(ids.len() > 20, "only {} distinct windows", ids.len());
+        assert_ne!(
+            calculate_flow_id_offset(input, nonce, 0),
+            calculate_flow_id_offset(input, nonce, 4)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "offset must be in [0, 28]")]
+    fn test_calculate_flow_id_offset_out_of_range() {
+        calculate_flow_id_offset(123, 0, 29);
+    }
+
+    /// Reference [`FlowHasher`] calling the one-shot `blake3::hash`.
+    struct OneShotBlake3;
+
+    impl FlowHasher for OneShotBlake3 {
+        fn digest(&self, message: &[u8]) -> [u8; 32] {
+            *blake3::hash(message).as_bytes()
+        }
+    }
+
+    /// Checks that `h1` and `h2` agree on every `x || nonce` message, and so
+    /// on every flow id.
+    fn assert_hashers_agree<H1: FlowHasher, H2: FlowHasher>(
+        h1: &H1,
+        h2: &H2,
+        inputs: &[u32],
+        nonces: &[u64],
+    ) {
+        for &input in inputs {
+            for &nonce in nonces {
+                let message = MessageSchema::default()
+                    .encode(&[&input.to_le_bytes(), &nonce.to_le_bytes()]);
+                assert_eq!(
+                    h1.digest(&message),
+                    h2.digest(&message),
+                    "x={input} nonce={nonce}"
+                );
+                assert_eq!(
+                    calculate_flow_id_with_hasher(h1, input, nonce),
+                    calculate_flow_id_with_hasher(h2, input, nonce)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_hashers_agree() {
+        let inputs = [0u32, 114, u32::MAX];
+        let nonces = [0u64, 1, 3543, u64::MAX];
+        assert_hashers_agree(
+            &Blake3FlowHasher,
+            &OneShotBlake3,
+            &inputs,
+            &nonces,
+        );
+        for (&input, &nonce) in inputs.iter().zip(&nonces) {
+            assert_eq!(
+                calculate_flow_id_with_hasher(&Blake3FlowHasher, input, nonce),
+                calculate_flow_id(input, nonce)
+            );
+        }
+    }
+
+    /// Naive reference for [`find_valid_nonce`]: hashes `x || nonce` from
+    /// scratch for every nonce, starting at 0, and reads the flow id off the
+    /// first `B/4` digest nibbles.
+    fn brute_force_first_valid_nonce(
+        input: u32,
+        b_bits: usize,
+        l_bits: usize,
+    ) -> u64 {
+        (0u64..)
+            .find(|nonce| {
+                let mut message = input.to_le_bytes().to_vec();
+                message.extend_from_slice(&nonce.to_le_bytes());
+                let hash = blake3::hash(&message);
+                let mut prefix = hash.as_bytes()[..b_bits / 8].to_vec();
+                if b_bits % 8 == 4 {
+                    prefix.push(hash.as_bytes()[b_bits / 8] >> 4);
+                }
+                prefix.resize(4, 0);
+                let flow_id = u32::from_le_bytes(prefix.try_into().unwrap());
+                flow_id < (1u32 << l_bits)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_benchmark_hash_rate_modes() {
+        assert_eq!(benchmark_hash_rate(0, BenchMode::FullFlowCheck), 0);
+        assert!(benchmark_hash_rate(1, BenchMode::RawHash) > 0);
+        let full = benchmark_hash_rate(1, BenchMode::FullFlowCheck);
+        assert!(full > 0);
+
+        // The measured rate seeds the search, which still finds the nonce
+        assert_eq!(
+            find_valid_nonce_with_style(
+                114,
+                BBits(16),
+                LBits(8),
+                ProgressStyleConfig::default(),
+                Some(full as f64),
+            ),
+            find_valid_nonce(114, BBits(16), LBits(8))
+        );
+    }
+
+    #[test]
+    fn test_find_valid_nonce_with_stats() {
+        let (b_bits, l_bits) = (BBits(16), LBits(8));
+        let (nonce, flow_id, stats) =
+            find_valid_nonce_with_stats(114, b_bits, l_bits).unwrap();
+        assert_eq!(
+            (nonce, flow_id),
+            find_valid_nonce(114, b_bits, l_bits).unwrap()
+        );
+        assert_eq!(stats.attempts, nonce + 1);
+        assert!(stats.hash_rate > 0.0);
+    }
+
+    #[test]
+    fn test_resumed_nonce_search_matches_full_search() {
+        let (b_bits, l_bits) = (BBits(16), LBits(8));
+        for input in [114u32, 123, 0xdead_beef] {
+            let full = find_valid_nonce(input, b_bits, l_bits).unwrap();
+
+            // Slices smaller than a kernel batch, resumed until one hits
+            let mut start_nonce = 0;
+            let mut slices = 0;
+            let resumed = loop {
+                slices += 1;
+                match find_valid_nonce_from(
+                    input,
+                    b_bits,
+                    l_bits,
+                    start_nonce,
+                    7,
+                ) {
+                    Ok(found) => break found,
+                    Err(CoreError::SearchIncomplete { last_nonce }) => {
+                        assert_eq!(last_nonce, start_nonce + 7);
+                        start_nonce = last_nonce;
+                    }
+                    Err(err) => panic!("unexpected error: {err}"),
+                }
+            };
+            assert_eq!(resumed, full, "input {input}");
+            assert_eq!(slices, full.0 / 7 + 1);
+        }
+
+        assert_eq!(
+            find_valid_nonce_from(114, b_bits, l_bits, 42, 0),
+            Err(CoreError::SearchIncomplete { last_nonce: 42 })
+        );
+        assert_eq!(
+            find_valid_nonce_from(114, b_bits, l_bits, u64::MAX, 2),
+            Err(CoreError::NonceOverflow)
+        );
+    }
+
+    #[test]
+    fn test_nonce_search_progress_callback() {
+        let (b_bits, l_bits) = (BBits(24), LBits(8));
+        let events = std::cell::RefCell::new(Vec::new());
+        let (nonce, flow_id) = find_valid_nonce_with_progress(
+            114,
+            b_bits,
+            l_bits,
+            Some(&|event: NonceProgress| events.borrow_mut().push(event)),
+        )
+        .unwrap();
+        assert_eq!(
+            (nonce, flow_id),
+            find_valid_nonce(114, b_bits, l_bits).unwrap()
+        );
+
+        // One event per batch tried, the last one counting the valid nonce
+        let events = events.into_inner();
+        assert_eq!(events.len() as u64, nonce / GRIND_KERNEL_BATCH as u64 + 1);
+        assert!(
+            events
+                .windows(2)
+                .all(|pair| pair[0].attempts < pair[1].attempts)
+        );
+        assert_eq!(events.last().unwrap().attempts, nonce + 1);
+        assert!(events.iter().all(|event| event.expected == 1 << 16));
+    }
+
+    #[test]
+    fn test_find_valid_nonce_matches_brute_force() {
+        for input in [0u32, 1, 114, 123, 0xdead_beef] {
+            for (b_bits, l_bits) in
+                [(4, 2), (8, 2), (8, 4), (12, 4), (16, 8), (20, 12)]
+            {
+                let (nonce, _) =
+                    find_valid_nonce(input, BBits(b_bits), LBits(l_bits))
+                        .unwrap();
+                assert_eq!(
+                    nonce,
+                    brute_force_first_valid_nonce(input, b_bits, l_bits),
+                    "x={input} B={b_bits} L={l_bits}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_flow_range_at_32_bits() {
+        let b_bits = BBits(32);
+
+        // L = 31: half of the prefixes are in D
+        let (_, flow_id) = find_valid_nonce(114, b_bits, LBits(31)).unwrap();
+        assert!(flow_id < 1 << 31);
+        assert!(flow_id_in_range((1 << 31) - 1, 31));
+        assert!(!flow_id_in_range(1 << 31, 31));
+
+        // L = 32: every prefix is in D, so nonce 0 always works
+        let (nonce, flow_id) =
+            find_valid_nonce(114, b_bits, LBits(32)).unwrap();
+        assert_eq!(nonce, 0);
+        assert_eq!(flow_id, truncate_flow_id(calculate_flow_id(114, 0), 32));
+        assert!(flow_id_in_range(u32::MAX, 32));
+        assert_eq!(
+            verify_flow_membership(114, 0, b_bits, LBits(32)),
+            Some(flow_id)
+        );
+
+        // L = 33 cannot fit in the 32-bit prefix
+        let too_wide = CoreError::FlowSpaceExceedsPrefix {
+            l_bits: 33,
+            b_bits: 32,
+        };
+        assert_eq!(LBits::new(33, b_bits), Err(too_wide.clone()));
+        assert_eq!(find_valid_nonce(114, b_bits, LBits(33)), Err(too_wide));
+        assert!(flow_id_in_range(u32::MAX, 33));
+    }
+
+    #[test]
+    fn test_flow_space_must_fit_u32() {
+        assert!(find_valid_nonce(114, BBits(8), LBits(12)).is_err());
+
+        let mut config = ColliderVmConfig {
+            n: 3,
+            m: 2,
+            l: 32,
+            b: 32,
+            k: 2,
+        };
+        assert_eq!(
+            config.validate(),
+            Err(CoreError::FlowSpaceOutOfRange { l_bits: 32 })
+        );
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "L must be less than 32, got 32"
+        );
+        config.l = 31;
+        assert_eq!(config.validate(), Ok(()));
+        config.b = 16;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_config_new_rejects_invalid_fields() {
+        let config = ColliderVmConfig::new(3, 2, 4, 16, 2).unwrap();
+        assert_eq!(config.validate(), Ok(()));
+
+        for ((n, m, l, b, k), expected) in [
+            ((0, 2, 4, 16, 2), CoreError::NoSigners),
+            ((3, 0, 4, 16, 2), CoreError::NoOperators),
+            (
+                (3, 2, 4, 16, 0),
+                CoreError::SignerSubsetOutOfRange { k: 0, n: 3 },
+            ),
+            (
+                (3, 2, 4, 16, 4),
+                CoreError::SignerSubsetOutOfRange { k: 4, n: 3 },
+            ),
+            (
+                (3, 2, 4, 40, 2),
+                CoreError::HashPrefixOutOfRange {
+                    prefix: 40,
+                    max: 32,
+                },
+            ),
+            (
+                (3, 2, 4, 10, 2),
+                CoreError::HashPrefixMisaligned { b_bits: 10 },
+            ),
+            (
+                (3, 2, 32, 32, 2),
+                CoreError::FlowSpaceOutOfRange { l_bits: 32 },
+            ),
+            (
+                (3, 2, 12, 8, 2),
+                CoreError::FlowSpaceExceedsPrefix {
+                    l_bits: 12,
+                    b_bits: 8,
+                },
+            ),
+        ] {
+            assert_eq!(ColliderVmConfig::new(n, m, l, b, k), Err(expected));
+        }
+
+        // Configs built field by field go through the same checks
+        let mut config = config;
+        config.b = 40;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_flow_bits_newtypes() {
+        let b_bits = BBits::new(16).unwrap();
+        assert_eq!(LBits::new(4, b_bits), Ok(LBits(4)));
+        assert_eq!(LBits::new(16, b_bits), Ok(LBits(16)));
+        assert_eq!(
+            LBits::new(17, b_bits),
+            Err(CoreError::FlowSpaceExceedsPrefix {
+                l_bits: 17,
+                b_bits: 16
+            })
+        );
+        assert_eq!(LBits::new(32, BBits::new(32).unwrap()), Ok(LBits(32)));
+        assert_eq!(
+            BBits::new(33).unwrap_err().to_string(),
+            "B must be at most 32, got 33"
+        );
+    }
+
+    #[test]
+    fn test_grind_batch() {
+        let inputs = [114, 150, 7, 114];
+        let (b_bits, l_bits) = (12, 4);
+        let mut reporter = NonceSearchProgress::new(
+            inputs.len() as u64 * (1 << (b_bits - l_bits)),
+        );
+        let results = grind_batch(
+            &inputs,
+            BBits(b_bits),
+            LBits(l_bits),
+            None,
+            &mut reporter,
+        )
+        .unwrap();
+
+        assert_eq!(results.len(), inputs.len());
+        for (&input, &(x, nonce, flow_id)) in inputs.iter().zip(&results) {
+            assert_eq!(x, input);
+            assert!(flow_id < 1 << l_bits);
+            assert_eq!(
+                truncate_flow_id(calculate_flow_id(x, nonce), b_bits),
+                flow_id
+            );
+            assert_eq!(nonce, brute_force_first_valid_nonce(x, b_bits, l_bits));
+        }
+
+        // A single worker grinds the inputs one after the other, with the
+        // same results
+        for max_concurrency in [1, 3] {
+            let mut reporter = NonceSearchProgress::new(
+                inputs.len() as u64 * (1 << (b_bits - l_bits)),
+            );
+            assert_eq!(
+                grind_batch(
+                    &inputs,
+                    BBits(b_bits),
+                    LBits(l_bits),
+                    NonZeroUsize::new(max_concurrency),
+                    &mut reporter,
+                ),
+                Ok(results.clone())
+            );
+        }
+
+        let mut reporter = NonceSearchProgress::new(1);
+        assert_eq!(
+            grind_batch(&inputs, BBits(8), LBits(12), None, &mut reporter),
+            Err(CoreError::FlowSpaceExceedsPrefix {
+                l_bits: 12,
+                b_bits: 8
+            })
+        );
+    }
+
+    #[test]
+    fn test_verify_witness_flow() {
+        let (b_bits, l_bits) = (16, 4);
+        let x = 114;
+        let (nonce, flow_id) =
+            find_valid_nonce(x, BBits(b_bits), LBits(l_bits)).unwrap();
+        let witness_for = |limbs: Vec<Vec<u8>>| {
+            let mut witness = Witness::new();
+            for limb in limbs {
+                witness.push(limb);
+            }
+            witness.push([0u8; SCHNORR_SIG_SIZE]);
+            witness.push([OP_TRUE.to_u8()]);
+            witness.push([0xc0u8; 33]);
+            witness
+        };
+
+        let witness = witness_for(message_to_witness_limbs(x, nonce));
+        assert_eq!(
+            decode_witness_message(&witness, LIMB_LEN),
+            Some((x, nonce))
+        );
+        assert!(verify_witness_flow(
+            &witness,
+            flow_id,
+            BBits(b_bits),
+            LBits(l_bits),
+            LIMB_LEN
+        ));
+        assert!(!verify_witness_flow(
+            &witness,
+            flow_id + 1,
+            BBits(b_bits),
+            LBits(l_bits),
+            LIMB_LEN
+        ));
+
+        // Another nonce for the same x no longer lands on the claimed flow
+        let tampered = (nonce + 1..)
+            .find(|n| {
+                calculate_flow_id(x, *n) & flow_id_mask(b_bits) != flow_id
+            })
+            .unwrap();
+        let witness = witness_for(message_to_witness_limbs(x, tampered));
+        assert!(!verify_witness_flow(
+            &witness,
+            flow_id,
+            BBits(b_bits),
+            LBits(l_bits),
+            LIMB_LEN
+        ));
+
+        // An out-of-range limb is rejected outright
+        let mut limbs = message_to_witness_limbs(x, nonce);
+        limbs[0] = vec![0x10];
+        let witness = witness_for(limbs);
+        assert_eq!(decode_witness_message(&witness, LIMB_LEN), None);
+        assert!(!verify_witness_flow(
+            &witness,
+            flow_id,
+            BBits(b_bits),
+            LBits(l_bits),
+            LIMB_LEN
+        ));
+
+        // Longer limbs decode with their own limb length only
+        for bits in [8, 16] {
+            let limb_len = LimbLen::new(bits).unwrap();
+            let witness = witness_for(message_to_witness_limbs_with_limb(
+                x, nonce, limb_len,
+            ));
+            assert_eq!(
+                decode_witness_message(&witness, limb_len),
+                Some((x, nonce))
+            );
+            assert_eq!(decode_witness_message(&witness, LIMB_LEN), None);
+            assert!(verify_witness_flow(
+                &witness,
+                flow_id,
+                BBits(b_bits),
+                LBits(l_bits),
+                limb_len
+            ));
+        }
+    }
+
+    #[test]
+    fn test_find_nonce_for_flow() {
+        let input = 123u32;
+        let b_bits = 8;
+        for target_flow in [0u32, 7, 0xff] {
+            let nonce =
+                find_nonce_for_flow(input, target_flow, BBits(b_bits), 1 << 16)
+                    .expect("2^B attempts expected, 2^16 allowed");
+            assert_eq!(
+                calculate_flow_id(input, nonce) & flow_id_mask(b_bits),
+                target_flow
+            );
+        }
+
+        // A nibble-aligned B keeps the digest's high nibble in the top bits
+        let nonce = find_nonce_for_flow(input, 0xabc, BBits(12), 1 << 16)
+            .expect("2^B attempts expected, 2^16 allowed");
+        assert_eq!(
+            truncate_flow_id(calculate_flow_id(input, nonce), 12),
+            0xabc
+        );
+
+        // A target wider than B bits can never be reached
+        assert_eq!(
+            find_nonce_for_flow(input, 0x100, BBits(b_bits), 1 << 16),
+            None
+        );
+        // Nor can any target with an invalid B
+        assert_eq!(find_nonce_for_flow(input, 0, BBits(6), 1 << 16), None);
+        assert_eq!(find_nonce_for_flow(input, 0, BBits(40), 1 << 16), None);
+    }
+
+    #[test]
+    fn test_structured_builders_compile_to_same_bytes() {
+        let secp = secp256k1::Secp256k1::new();
+        let (_, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        let signer_pubkey = PublicKey::new(pk);
+        let flow_id_prefix = flow_id_to_prefix_bytes(0x0d, 16);
+        // The locking script is the concatenation of its fragments
+        let fragments = |predicate: ScriptBuf| {
+            combine_scripts(&[
+                build_script_verify_signature(&signer_pubkey),
+                build_script_reconstruct_x(LIMB_LEN.bits()),
+                predicate,
+                build_script_blake3_compute(
+                    MessageSchema::default().total_msg_len(),
+                    LIMB_LEN.bits(),
+                ),
+                build_drop(BLAKE3_DIGEST_NIBBLES - flow_id_prefix.len()),
+                build_prefix_equalverify(&flow_id_prefix),
+                build_stack_depth_check(0),
+                Builder::new().push_opcode(OP_TRUE).into_script(),
+            ])
+        };
+
+        let f1 = build_script_f1_blake3_locked_structured(
+            &signer_pubkey,
+            &flow_id_prefix,
+            16,
+            F1_THRESHOLD,
+            LIMB_LEN,
+            &MessageSchema::default(),
+        );
+        let expected = fragments(build_script_f1_predicate(F1_THRESHOLD));
+        assert_eq!(f1.compile(), expected);
+        assert_eq!(
+            build_script_f1_fragments(
+                &signer_pubkey,
+                &flow_id_prefix,
+                16,
+                F1_THRESHOLD,
+                LIMB_LEN
+            )
+            .combine(),
+            expected
+        );
+
+        let f2 = build_script_f2_blake3_locked_structured(
+            &signer_pubkey,
+            &flow_id_prefix,
+            16,
+            F2_THRESHOLD,
+            LIMB_LEN,
+            &MessageSchema::default(),
+        );
+        assert_eq!(
+            f2.compile(),
+            fragments(build_script_f2_predicate(F2_THRESHOLD))
+        );
+
+        // The structured form can be composed further before compiling
+        let composed = script! {
+            { build_script_f1_blake3_locked_structured(
+                &signer_pubkey,
+                &flow_id_prefix,
+                16,
+                F1_THRESHOLD,
+                LIMB_LEN,
+                &MessageSchema::default(),
+            ) }
+            OP_DROP
+        }
+        .compile();
+        let mut expected = expected.to_bytes();
+        expected.push(opcodes::all::OP_DROP.to_u8());
+        assert_eq!(composed.to_bytes(), expected);
+    }
+
+    #[test]
+    fn test_limb_len_script_sizes() {
+        let secp = secp256k1::Secp256k1::new();
+        let (_, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        let signer_pubkey = PublicKey::new(pk);
+        let x = 123u32;
+        let (nonce, flow_id) =
+            find_valid_nonce(x, BBits(16), LBits(4)).unwrap();
+        let flow_id_prefix = flow_id_to_prefix_bytes(flow_id, 16);
+
+        let mut sizes = Vec::new();
+        for bits in [4u8, 8, 16] {
+            let limb_len = LimbLen::new(bits).unwrap();
+            let f1 = build_script_f1_blake3_locked(
+                &signer_pubkey,
+                &flow_id_prefix,
+                16,
+                F1_THRESHOLD,
+                limb_len,
+            );
+            let limbs = message_to_witness_limbs_with_limb(x, nonce, limb_len);
+            assert_eq!(limbs.len(), 12 * 8 / bits as usize);
+
+            // The body still accepts its witness with any supported limb
+            let witness = witness_pushes(&limbs);
+            let body = build_script_step_body_with_limb(
+                build_script_f1_predicate(F1_THRESHOLD),
+                &flow_id_prefix,
+                &MessageSchema::default(),
+                limb_len,
+            );
+            let res = execute_script_buf(combine_scripts(&[witness, body]));
+            assert!(res.success, "limb_len {bits} rejected its witness");
+            sizes.push((f1.len(), limbs.len()));
+        }
+
+        // Longer limbs need fewer witness items, paid for by a larger
+        // script splitting them back into the nibbles BLAKE3 works on
+        for pair in sizes.windows(2) {
+            let [(script_a, limbs_a), (script_b, limbs_b)] = pair else {
+                unreachable!()
+            };
+            assert!(limbs_b < limbs_a, "{sizes:?}");
+            assert!(script_b > script_a, "{sizes:?}");
+        }
+    }
+
+    #[test]
+    fn test_limb_len_new() {
+        for bits in [4, 8, 16] {
+            assert_eq!(LimbLen::new(bits).unwrap().bits(), bits);
+        }
+        for bits in [0, 2, 3, 12, 32] {
+            assert_eq!(
+                LimbLen::new(bits),
+                Err(CoreError::LimbLenUnsupported { limb_len: bits })
+            );
+        }
+        assert_eq!(LIMB_LEN.max_limb_push(), vec![0x0f]);
+        assert_eq!(LimbLen::new(8).unwrap().max_limb_push(), vec![0xff, 0]);
+    }
+
+    #[test]
+    fn test_f1_fragments_combine_to_builder_output() {
+        let secp = secp256k1::Secp256k1::new();
+        let (_, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        let signer_pubkey = PublicKey::new(pk);
+        let flow_id_prefix = flow_id_to_prefix_bytes(0x0d, 16);
+
+        let fragments = build_script_f1_fragments(
+            &signer_pubkey,
+            &flow_id_prefix,
+            16,
+            F1_THRESHOLD,
+            LIMB_LEN,
+        );
+        assert!(fragments.to_vec().iter().all(|f| !f.is_empty()));
+        assert_eq!(
+            fragments.combine().to_bytes(),
+            build_script_f1_blake3_locked(
+                &signer_pubkey,
+                &flow_id_prefix,
+                16,
+                F1_THRESHOLD,
+                LIMB_LEN
+            )
+            .to_bytes()
+        );
+        assert_eq!(
+            fragments.combine(),
+            build_script_f1_blake3_locked_structured(
+                &signer_pubkey,
+                &flow_id_prefix,
+                16,
+                F1_THRESHOLD,
+                LIMB_LEN,
+                &MessageSchema::default()
+            )
+            .compile()
+        );
+        assert_eq!(
+            fragments.predicate,
+            build_script_f1_predicate(F1_THRESHOLD)
+        );
+    }
+
+    #[test]
+    fn test_predicate_holds_matches_script() {
+        let predicates = [
+            ScriptPredicate::F1,
+            ScriptPredicate::F2,
+            ScriptPredicate::GreaterThanOrEqual(F1_THRESHOLD),
+            ScriptPredicate::LessThanOrEqual(F2_THRESHOLD),
+        ];
+        let boundaries = [
+            0,
+            F1_THRESHOLD - 1,
+            F1_THRESHOLD,
+            F1_THRESHOLD + 1,
+            F2_THRESHOLD - 1,
+            F2_THRESHOLD,
+            F2_THRESHOLD + 1,
+            i32::MAX as u32,
+            i32::MAX as u32 + 1,
+            u32::MAX,
+        ];
+        for predicate in predicates {
+            for x in boundaries {
+                let script = combine_scripts(&[
+                    Builder::new().push_int(x as i64).into_script(),
+                    predicate.to_script(),
+                    Builder::new().push_opcode(OP_TRUE).into_script(),
+                ]);
+                assert_eq!(
+                    predicate_holds(&predicate, x),
+                    execute_script_buf(script).success,
+                    "{predicate:?} x={x}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_feasible_input_range() {
+        let f1_f2 = [ScriptPredicate::F1, ScriptPredicate::F2];
+        assert_eq!(feasible_input_range(&f1_f2), Some((101, 199)));
+        assert_eq!(check_predicates_feasible(&f1_f2), Ok((101, 199)));
+
+        let contradictory = [
+            ScriptPredicate::GreaterThan(200),
+            ScriptPredicate::LessThan(100),
+        ];
+        assert_eq!(feasible_input_range(&contradictory), None);
+        assert_eq!(
+            check_predicates_feasible(&contradictory),
+            Err(CoreError::InfeasiblePredicates(contradictory.to_vec()))
+        );
+
+        assert_eq!(feasible_input_range(&[]), Some((0, i32::MAX as u32)));
+        assert_eq!(
+            feasible_input_range(&[
+                ScriptPredicate::InRange(10, 20),
+                ScriptPredicate::GreaterThanOrEqual(19),
+            ]),
+            Some((19, 19))
+        );
+        assert_eq!(feasible_input_range(&[ScriptPredicate::LessThan(0)]), None);
+        assert_eq!(
+            feasible_input_range(&[ScriptPredicate::LessThan(u32::MAX)]),
+            None
+        );
+
+        // The range agrees with the on-chain predicates at its edges
+        let (lo, hi) = feasible_input_range(&f1_f2).unwrap();
+        for (x, inside) in
+            [(lo - 1, false), (lo, true), (hi, true), (hi + 1, false)]
+        {
+            assert_eq!(
+                f1_f2.iter().all(|p| predicate_holds(p, x)),
+                inside,
+                "x={x}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_configurable_thresholds() {
+        for threshold in [0, u32::MAX - 1] {
+            for (predicate, script) in [
+                (
+                    ScriptPredicate::GreaterThan(threshold),
+                    build_script_f1_predicate(threshold),
+                ),
+                (
+                    ScriptPredicate::LessThan(threshold),
+                    build_script_f2_predicate(threshold),
+                ),
+            ] {
+                // The threshold push round-trips through the script encoding
+                let pushed = match script.instructions().next() {
+                    Some(Ok(bitcoin::script::Instruction::PushBytes(
+                        bytes,
+                    ))) => bytes.as_bytes().to_vec(),
+                    other => panic!("expected a push, got {other:?}"),
+                };
+                assert_eq!(pushed, encode_scriptnum(threshold as i64));
+
+                for x in [0, 1, threshold, threshold.wrapping_add(1), u32::MAX]
+                {
+                    let exec = combine_scripts(&[
+                        Builder::new().push_int(x as i64).into_script(),
+                        script.clone(),
+                        Builder::new().push_opcode(OP_TRUE).into_script(),
+                    ]);
+                    assert_eq!(
+                        predicate_holds(&predicate, x),
+                        execute_script_buf(exec).success,
+                        "{predicate:?} x={x}"
+                    );
+                }
+            }
+        }
+        assert!(predicate_holds(&ScriptPredicate::GreaterThan(0), 1));
+
+        let secp = secp256k1::Secp256k1::new();
+        let (_, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        let signer_pubkey = PublicKey::new(pk);
+        let flow_id_prefix = flow_id_to_prefix_bytes(0x0d, 16);
+        assert_ne!(
+            build_script_f1_blake3_locked(
+                &signer_pubkey,
+                &flow_id_prefix,
+                16,
+                0,
+                LIMB_LEN
+            ),
+            build_script_f1_blake3_locked(
+                &signer_pubkey,
+                &flow_id_prefix,
+                16,
+                F1_THRESHOLD,
+                LIMB_LEN
+            )
+        );
+    }
+
+    #[test]
+    fn test_predicate_locked_variants() {
+        // (predicate, accepted x, rejected x)
+        let cases = [
+            (ScriptPredicate::GreaterThan(100), 101, 100),
+            (ScriptPredicate::GreaterThanOrEqual(100), 100, 99),
+            (ScriptPredicate::LessThan(200), 199, 200),
+            (ScriptPredicate::LessThanOrEqual(200), 200, 201),
+            (ScriptPredicate::Equal(150), 150, 151),
+            (ScriptPredicate::InRange(100, 200), 100, 200),
+        ];
+        for (predicate, accepted, rejected) in cases {
+            for (x, expected) in [(accepted, true), (rejected, false)] {
+                assert_eq!(predicate_holds(&predicate, x), expected);
+
+                let (nonce, flow_id) =
+                    find_valid_nonce(x, BBits(16), LBits(4)).unwrap();
+                let flow_id_prefix = flow_id_to_prefix_bytes(flow_id, 16);
+                let body = build_script_step_body(
+                    predicate.to_script(),
+                    &flow_id_prefix,
+                    &MessageSchema::default(),
+                );
+                let res = execute_script_buf(combine_scripts(&[
+                    witness_pushes(&message_to_witness_limbs(x, nonce)),
+                    body,
+                ]));
+                assert_eq!(res.success, expected, "{predicate:?} x={x}");
+            }
+        }
+
+        // OP_WITHIN includes its lower bound and excludes its upper one
+        let in_range = ScriptPredicate::InRange(100, 200);
+        for (x, expected) in
+            [(99, false), (100, true), (199, true), (200, false)]
+        {
+            let script = combine_scripts(&[
+                Builder::new().push_int(x as i64).into_script(),
+                in_range.to_script(),
+                Builder::new().push_opcode(OP_TRUE).into_script(),
+            ]);
+            assert_eq!(execute_script_buf(script).success, expected, "x={x}");
+            assert_eq!(predicate_holds(&in_range, x), expected, "x={x}");
+        }
+
+        // F1 is just one instance of the generic builder
+        let secp = secp256k1::Secp256k1::new();
+        let (_, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        let signer_pubkey = PublicKey::new(pk);
+        let flow_id_prefix = flow_id_to_prefix_bytes(0x0d, 16);
+        assert_eq!(
+            build_script_predicate_locked(
+                &signer_pubkey,
+                &flow_id_prefix,
+                16,
+                &ScriptPredicate::F1,
+            ),
+            build_script_f1_blake3_locked(
+                &signer_pubkey,
+                &flow_id_prefix,
+                16,
+                F1_THRESHOLD,
+                LIMB_LEN
+            )
+        );
+    }
+
+    #[test]
+    fn test_u64_input_round_trip() {
+        // A non-zero high word, which a check of the low word alone misses
+        let x = (1u64 << 32) + 150;
+        let (b_bits, l_bits) = (16, 4);
+        let (nonce, flow_id) =
+            find_valid_nonce_u64(x, BBits(b_bits), LBits(l_bits)).unwrap();
+        assert_eq!(
+            calculate_flow_id_u64(x, nonce) & flow_id_mask(b_bits),
+            flow_id
+        );
+
+        let schema = MessageSchema::u64_input();
+        assert_eq!(schema.total_msg_len(), 16);
+        let limbs = message_to_witness_limbs_u64(x, nonce);
+        assert_eq!(limbs.len(), 16 * 8 / LIMB_LEN.bits() as usize);
+        let witness = witness_pushes(&limbs);
+
+        // Both words of x come back, the high one on top
+        let reconstruct = |high: u64| {
+            combine_scripts(&[
+                witness.clone(),
+                build_script_reconstruct_words(LIMB_LEN.bits(), 2),
+                Builder::new()
+                    .push_int(high as i64)
+                    .push_opcode(opcodes::all::OP_EQUALVERIFY)
+                    .push_int((x as u32) as i64)
+                    .push_opcode(opcodes::all::OP_EQUALVERIFY)
+                    .into_script(),
+                build_drop(limbs.len()),
+                Builder::new().push_opcode(OP_TRUE).into_script(),
+            ])
+        };
+        assert!(execute_script_buf(reconstruct(x >> 32)).success);
+        assert!(!execute_script_buf(reconstruct(0)).success);
+    }
+
+    #[test]
+    #[should_panic(expected = "step scripts need a 4-byte x, got 8")]
+    fn test_step_builders_reject_u64_input() {
+        // F2 requires x < threshold: x = 2^32 + 150 is above it, but its low
+        // word is not, so a script checking only that word would accept it
+        let secp = secp256k1::Secp256k1::new();
+        let (_, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        build_script_f2_blake3_locked_structured(
+            &PublicKey::new(pk),
+            &flow_id_to_prefix_bytes(0, 16),
+            16,
+            F2_THRESHOLD,
+            LIMB_LEN,
+            &MessageSchema::u64_input(),
+        );
+    }
+
+    #[test]
+    fn test_message_schema_with_extra_field() {
+        let schema = MessageSchema::default().with_field("salt", 4);
+        assert_eq!(schema.total_msg_len(), 16);
+        assert_eq!(schema.fields().len(), 3);
+
+        let x = 123u32;
+        let salt = 0xdeadbeefu32.to_le_bytes();
+        let b_bits = 8;
+
+        // Grind a nonce over the extended message
+        let (nonce, flow_id) = (0u64..)
+            .map(|nonce| {
+                let flow_id = calculate_flow_id_with_schema(
+                    &schema,
+                    &[&x.to_le_bytes(), &nonce.to_le_bytes(), &salt],
+                ) & flow_id_mask(b_bits);
+                (nonce, flow_id)
+            })
+            .find(|(_, flow_id)| *flow_id < 4)
+            .unwrap();
+        let flow_id_prefix = flow_id_to_prefix_bytes(flow_id, b_bits);
+
+        let witness = witness_pushes(&message_to_witness_limbs_with_schema(
+            &schema,
+            &[&x.to_le_bytes(), &nonce.to_le_bytes(), &salt],
+        ));
+
+        // F1 body without the signature check, sized from the schema
+        let compute = optimizer::optimize(
+            blake3_compute_script_with_limb(
+                schema.total_msg_len(),
+                LIMB_LEN.bits(),
+            )
+            .compile(),
+        );
+        let locking = combine_scripts(&[
+            build_script_reconstruct_x(LIMB_LEN.bits()),
+            Builder::new()
+                .push_int(F1_THRESHOLD as i64)
+                .push_opcode(opcodes::all::OP_GREATERTHAN)
+                .push_opcode(opcodes::all::OP_VERIFY)
+                .into_script(),
+            ScriptBuf::from_bytes(compute.to_bytes()),
+            build_drop(64 - flow_id_prefix.len()),
+            build_prefix_equalverify(&flow_id_prefix),
+            Builder::new().push_opcode(OP_TRUE).into_script(),
+        ]);
+
+        let res = execute_script_buf(combine_scripts(&[witness, locking]));
+        assert!(res.success, "extended message must verify");
+
+        // The full builder picks up the longer message as well
+        let secp = secp256k1::Secp256k1::new();
+        let (_, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        let signer_pubkey = PublicKey::new(pk);
+        assert_ne!(
+            build_script_f1_blake3_locked_structured(
+                &signer_pubkey,
+                &flow_id_prefix,
+                b_bits,
+                F1_THRESHOLD,
+                LIMB_LEN,
+                &schema
+            )
+            .compile(),
+            build_script_f1_blake3_locked(
+                &signer_pubkey,
+                &flow_id_prefix,
+                b_bits,
+                F1_THRESHOLD,
+                LIMB_LEN
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "field `salt` is 4 bytes, got 2")]
+    fn test_message_schema_rejects_wrong_width() {
+        let schema = MessageSchema::default().with_field("salt", 4);
+        schema.encode(&[&[0; 4], &[0; 8], &[0; 2]]);
+    }
+
+    #[test]
+    fn test_blake3_limbs_match_compute_script() {
+        let message = [
+            0x7b, 0x00, 0x00, 0x00, 0xd9, 0x0d, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00,
+        ];
+        let limbs = blake3_limbs(&message, LIMB_LEN.bits());
+        assert_eq!(limbs.len(), 64);
+
+        // The nibbles reassemble into the off-chain digest
+        let digest = limbs
+            .chunks(2)
+            .map(|pair| (pair[0] << 4) | pair[1])
+            .collect::<Vec<_>>();
+        assert_eq!(digest, blake3::hash(&message).as_bytes());
+
+        // ...and match what the on-chain compute script leaves on the stack
+        let push =
+            blake3_push_message_script_with_limb(&message, LIMB_LEN.bits())
+                .compile();
+        let compute = optimizer::optimize(
+            blake3_compute_script_with_limb(message.len(), LIMB_LEN.bits())
+                .compile(),
+        );
+        let res = execute_script_buf(combine_scripts(&[
+            ScriptBuf::from_bytes(push.to_bytes()),
+            ScriptBuf::from_bytes(compute.to_bytes()),
+        ]));
+        let stack = res
+            .final_stack
+            .0
+            .iter_str()
+            .map(|v| v.first().copied().unwrap_or(0))
+            .collect::<Vec<_>>();
+        assert_eq!(stack, limbs);
+    }
+
+    #[test]
+    fn test_verify_blake3_snippet_compat() {
+        assert_eq!(verify_blake3_snippet_compat(), Ok(()));
+    }
+
+    #[test]
+    fn test_select_flow_for_input() {
+        let input = 123u32;
+        let (b_bits, l_bits) = (8, 4);
+        let funded = [3u32, 7, 11, 20]
+            .into_iter()
+            .map(|flow_id| PresignedFlow {
+                flow_id,
+                steps: vec![],
+            })
+            .collect::<Vec<_>>();
+
+        let (nonce, flow) =
+            select_flow_for_input(input, &funded, BBits(b_bits), LBits(l_bits))
+                .unwrap();
+        let flow_id = calculate_flow_id(input, nonce) & flow_id_mask(b_bits);
+        assert_eq!(flow.flow_id, flow_id);
+        assert!([3, 7, 11].contains(&flow_id), "chose flow {flow_id}");
+
+        // It is the first nonce hitting any funded flow in D
+        let first = (0u64..)
+            .find(|&nonce| {
+                let id = calculate_flow_id(input, nonce) & flow_id_mask(b_bits);
+                [3, 7, 11].contains(&id)
+            })
+            .unwrap();
+        assert_eq!(nonce, first);
+
+        // Flow 20 is outside D, so it is never selected
+        let outside = [PresignedFlow {
+            flow_id: 20,
+            steps: vec![],
+        }];
+        assert!(
+            select_flow_for_input(
+                input,
+                &outside,
+                BBits(b_bits),
+                LBits(l_bits)
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_total_flow_set_weight() {
+        let step = |outputs: usize, leaf_len: usize| PresignedStep {
+            tx_template: Transaction {
+                version: Version::TWO,
+                lock_time: absolute::LockTime::ZERO,
+                input: vec![bitcoin::TxIn::default()],
+                output: vec![
+                    TxOut {
+                        value: Amount::from_sat(1_000),
+                        script_pubkey: ScriptBuf::new(),
+                    };
+                    outputs
+                ],
+            },
+            prevouts: vec![],
+            spent_leaf: ScriptBuf::from_bytes(vec![OP_TRUE.to_u8(); leaf_len]),
+            sighash_message: Message::from_digest([0u8; 32]),
+            signatures: HashMap::new(),
+            limb_len: LIMB_LEN,
+        };
+        let flows = (0..3)
+            .map(|flow_id| PresignedFlow {
+                flow_id,
+                steps: vec![step(1, 100), step(2, 200)],
+            })
+            .collect::<Vec<_>>();
+
+        let expected = flows
+            .iter()
+            .flat_map(|flow| &flow.steps)
+            .map(|step| step.weight())
+            .sum::<usize>();
+        assert_eq!(total_flow_set_weight(&flows), expected);
+        assert_eq!(
+            total_flow_set_weight(&flows),
+            3 * (step(1, 100).weight() + step(2, 200).weight())
+        );
+        assert_eq!(total_flow_set_weight(&[]), 0);
+
+        // The witness, and the spent leaf in particular, is counted
+        let small = step(1, 100);
+        assert!(small.weight() > small.tx_template.weight().to_wu() as usize);
+        assert_eq!(step(1, 150).weight() - small.weight(), 50);
+
+        // ... and so are the limbs, at the step's own limb length: 6 pushes
+        // of 3 bytes for 16-bit limbs, against 24 single-byte ones
+        let wide = PresignedStep {
+            limb_len: LimbLen::new(16).unwrap(),
+            ..small.clone()
+        };
+        assert_eq!(small.weight() - wide.weight(), 24 * 2 - 6 * 4);
+    }
+
+    #[test]
+    fn test_missing_signers() {
+        let secp = secp256k1::Secp256k1::new();
+        let keypairs = (0..3)
+            .map(|_| secp.generate_keypair(&mut rand::thread_rng()))
+            .collect::<Vec<_>>();
+        let required = keypairs
+            .iter()
+            .map(|(_, pk)| PublicKey::new(*pk))
+            .collect::<Vec<_>>();
+
+        let sighash_message = Message::from_digest([4u8; 32]);
+        let mut step = PresignedStep {
+            tx_template: Transaction {
+                version: Version::TWO,
+                lock_time: absolute::LockTime::ZERO,
+                input: vec![],
+                output: vec![],
+            },
+            prevouts: vec![],
+            spent_leaf: ScriptBuf::new(),
+            sighash_message,
+            signatures: HashMap::new(),
+            limb_len: LIMB_LEN,
+        };
+        assert_eq!(step.missing_signers(&required), required);
+
+        for (sk, pk) in &keypairs[..2] {
+            let keypair = secp256k1::Keypair::from_secret_key(&secp, sk);
+            step.signatures.insert(
+                XOnlyPublicKey::from(*pk).serialize().to_vec(),
+                secp.sign_schnorr_no_aux_rand(&sighash_message, &keypair),
+            );
+        }
+        assert_eq!(step.missing_signers(&required), vec![required[2]]);
+        assert!(step.missing_signers(&required[..2]).is_empty());
+    }
+
+    #[test]
+    fn test_presigning_storage_grows_with_subsets() {
+        assert_eq!(binomial(5, 0), Some(1));
+        assert_eq!(binomial(5, 2), Some(10));
+        assert_eq!(binomial(5, 5), Some(1));
+        assert_eq!(binomial(3, 4), Some(0));
+        assert_eq!(binomial(64, 32), Some(1_832_624_140_942_590_534));
+        assert_eq!(binomial(200, 100), None);
+
+        let config = |k| ColliderVmConfig {
+            n: 5,
+            m: 2,
+            l: 4,
+            b: 16,
+            k,
+        };
+        let k1 = presigning_storage_bytes(&config(1)).unwrap(); // C(5,1) = 5
+        let k2 = presigning_storage_bytes(&config(2)).unwrap(); // C(5,2) = 10
+        let k5 = presigning_storage_bytes(&config(5)).unwrap(); // C(5,5) = 1
+        assert!(k5 < k1 && k1 < k2);
+
+        // Each extra subset adds one signature per flow and step
+        let per_subset = (1 << 4) * NUM_STEPS * SCHNORR_SIG_SIZE;
+        assert_eq!(k2 - k1, 5 * per_subset);
+        assert_eq!(k1 - k5, 4 * per_subset);
+
+        let huge = ColliderVmConfig {
+            n: 64,
+            m: 2,
+            l: 31,
+            b: 32,
+            k: 32,
+        };
+        assert_eq!(
+            presigning_storage_bytes(&huge),
+            Err(CoreError::StorageOverflow)
+        );
+        assert_eq!(
+            presigning_storage_bytes(&config(6)),
+            Err(CoreError::SignerSubsetOutOfRange { k: 6, n: 5 })
+        );
+    }
+
+    #[test]
+    fn test_assign_flows_to_signers() {
+        for (n, k) in [(5, 1), (5, 2), (5, 5), (3, 2), (7, 3)] {
+            let config = ColliderVmConfig {
+                n,
+                m: 2,
+                l: 4,
+                b: 16,
+                k,
+            };
+            let assignment = assign_flows_to_signers(&config).unwrap();
+
+            // Every flow is covered by exactly k distinct signers
+            for flow_id in 0..(1u32 << config.l) {
+                let signers = assignment
+                    .iter()
+                    .filter(|(_, flows)| flows.contains(&flow_id))
+                    .count();
+                assert_eq!(signers, k, "flow {flow_id} with n={n} k={k}");
+            }
+
+            // Each signer gets floor or ceil of 2^L * k / n flows
+            let total = (1usize << config.l) * k;
+            assert_eq!(assignment.len(), n);
+            for flows in assignment.values() {
+                assert!(
+                    flows.len() == total / n
+                        || flows.len() == total.div_ceil(n),
+                    "{} flows with n={n} k={k}",
+                    flows.len()
+                );
+            }
+        }
+
+        let config = ColliderVmConfig {
+            n: 3,
+            m: 2,
+            l: 4,
+            b: 16,
+            k: 4,
+        };
+        assert_eq!(
+            assign_flows_to_signers(&config),
+            Err(CoreError::SignerSubsetOutOfRange { k: 4, n: 3 })
+        );
+    }
+
+    #[test]
+    fn test_input_from_label() {
+        for label in ["alice", "bob", "", "collidervm"] {
+            let x = input_from_label(label);
+            assert_eq!(x, input_from_label(label));
+            assert!(x > F1_THRESHOLD && x < F2_THRESHOLD, "{label} => {x}");
+        }
+        assert_ne!(input_from_label("alice"), input_from_label("bob"));
+    }
 
     #[test]
     fn test_f1_witness_script() {
@@ -0,0 +1,340 @@
+//! Where the demo gets the UTXO funding its flow from: the wallet of a
+//! local node, or an Esplora HTTP API watching the funding address.
+
+use anyhow::Context;
+use bitcoin::{Address, Amount, OutPoint, Txid};
+use bitcoincore_rpc::RpcApi;
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// A way to obtain an output paying the funding address.
+pub trait FundingSource {
+    /// An unspent output paying `address` at least `min_amount`, with its
+    /// value.
+    fn find_utxo(
+        &self,
+        address: &Address,
+        min_amount: Amount,
+    ) -> anyhow::Result<(OutPoint, Amount)>;
+}
+
+/// Funds the address from the wallet of a local node, asking for
+/// `min_amount`. The value returned is the one of the output the wallet
+/// actually created; a wallet taking the fee out of the amount sent leaves
+/// it short, which is an error.
+pub struct RpcFundingSource<'a, R: RpcApi> {
+    pub client: &'a R,
+}
+
+impl<R: RpcApi> FundingSource for RpcFundingSource<'_, R> {
+    fn find_utxo(
+        &self,
+        address: &Address,
+        min_amount: Amount,
+    ) -> anyhow::Result<(OutPoint, Amount)> {
+        let txid = self
+            .client
+            .send_to_address(
+                address, min_amount, None, None, None, None, None, None,
+            )
+            .context("failed to fund the address from the wallet")?;
+        let tx = self.client.get_raw_transaction(&txid, None)?;
+        let vout = tx
+            .output
+            .iter()
+            .position(|out| out.script_pubkey == address.script_pubkey())
+            .context("funding tx does not pay the funding address")?;
+        let value = tx.output[vout].value;
+        anyhow::ensure!(
+            value >= min_amount,
+            "funding output {txid}:{vout} holds {} sat, less than the {} sat requested",
+            value.to_sat(),
+            min_amount.to_sat()
+        );
+        Ok((
+            OutPoint {
+                txid,
+                vout: vout as u32,
+            },
+            value,
+        ))
+    }
+}
+
+/// Uses an output that already pays the funding address, e.g. funded before
+/// an earlier run with the same keys, looked up in the node by `outpoint`.
+pub struct RpcExistingUtxo<'a, R: RpcApi> {
+    pub client: &'a R,
+    pub outpoint: OutPoint,
+}
+
+impl<R: RpcApi> FundingSource for RpcExistingUtxo<'_, R> {
+    fn find_utxo(
+        &self,
+        address: &Address,
+        min_amount: Amount,
+    ) -> anyhow::Result<(OutPoint, Amount)> {
+        let OutPoint { txid, vout } = self.outpoint;
+        let tx = self
+            .client
+            .get_raw_transaction(&txid, None)
+            .with_context(|| format!("failed to fetch funding tx {txid}"))?;
+        let output = tx.output.get(vout as usize).with_context(|| {
+            format!("funding tx {txid} has no output {vout}")
+        })?;
+        anyhow::ensure!(
+            output.script_pubkey == address.script_pubkey(),
+            "funding output {txid}:{vout} does not pay {address}"
+        );
+        anyhow::ensure!(
+            output.value >= min_amount,
+            "funding output {txid}:{vout} holds {} sat, less than the {} sat requested",
+            output.value.to_sat(),
+            min_amount.to_sat()
+        );
+        Ok((self.outpoint, output.value))
+    }
+}
+
+/// Looks the funding UTXO up through an Esplora HTTP API (e.g.
+/// `https://mempool.space/signet/api`), for an address funded by other
+/// means, without a local node.
+pub struct EsploraFundingSource {
+    base_url: String,
+    client: reqwest::blocking::Client,
+}
+
+impl EsploraFundingSource {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: reqwest::blocking::Client::new(),
+        }
+    }
+}
+
+/// An entry of Esplora's `GET /address/:address/utxo`.
+#[derive(Deserialize, Debug)]
+struct EsploraUtxo {
+    txid: String,
+    vout: u32,
+    value: u64,
+    status: EsploraTxStatus,
+}
+
+#[derive(Deserialize, Debug)]
+struct EsploraTxStatus {
+    confirmed: bool,
+}
+
+/// The first UTXO worth at least `min_amount`, preferring confirmed ones.
+fn select_utxo(
+    utxos: &[EsploraUtxo],
+    min_amount: Amount,
+) -> anyhow::Result<Option<(OutPoint, Amount)>> {
+    let large_enough = |utxo: &&EsploraUtxo| utxo.value >= min_amount.to_sat();
+    let Some(utxo) = utxos
+        .iter()
+        .filter(large_enough)
+        .find(|utxo| utxo.status.confirmed)
+        .or_else(|| utxos.iter().find(large_enough))
+    else {
+        return Ok(None);
+    };
+    let txid = Txid::from_str(&utxo.txid)
+        .with_context(|| format!("invalid txid {}", utxo.txid))?;
+    Ok(Some((
+        OutPoint {
+            txid,
+            vout: utxo.vout,
+        },
+        Amount::from_sat(utxo.value),
+    )))
+}
+
+impl FundingSource for EsploraFundingSource {
+    fn find_utxo(
+        &self,
+        address: &Address,
+        min_amount: Amount,
+    ) -> anyhow::Result<(OutPoint, Amount)> {
+        let url = format!("{}/address/{address}/utxo", self.base_url);
+        let utxos: Vec<EsploraUtxo> = self
+            .client
+            .get(&url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.json())
+            .with_context(|| format!("failed to query {url}"))?;
+        select_utxo(&utxos, min_amount)?.with_context(|| {
+            format!(
+                "no UTXO of at least {} sat pays {address} yet, fund it and rerun with the same --seed",
+                min_amount.to_sat()
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::consensus::encode::serialize_hex;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Network, ScriptBuf, Transaction, TxIn, TxOut, WPubkeyHash};
+
+    /// Answers `sendtoaddress` and `getrawtransaction` with a canned
+    /// funding tx.
+    struct MockRpc {
+        funding_tx: Transaction,
+    }
+
+    impl RpcApi for MockRpc {
+        fn call<T: for<'a> serde::de::Deserialize<'a>>(
+            &self,
+            cmd: &str,
+            _args: &[serde_json::Value],
+        ) -> bitcoincore_rpc::Result<T> {
+            let result = match cmd {
+                "sendtoaddress" => {
+                    serde_json::json!(
+                        self.funding_tx.compute_txid().to_string()
+                    )
+                }
+                "getrawtransaction" => {
+                    serde_json::json!(serialize_hex(&self.funding_tx))
+                }
+                _ => panic!("unexpected RPC call {cmd}"),
+            };
+            Ok(serde_json::from_value(result)?)
+        }
+    }
+
+    #[test]
+    fn test_rpc_source_returns_funded_amount() {
+        let address = Address::p2wsh(&ScriptBuf::new(), Network::Regtest);
+        let funding_tx = |value: u64| Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            // The wallet put its change first
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(7_000_000),
+                    script_pubkey: ScriptBuf::new_p2wpkh(
+                        &WPubkeyHash::all_zeros(),
+                    ),
+                },
+                TxOut {
+                    value: Amount::from_sat(value),
+                    script_pubkey: address.script_pubkey(),
+                },
+            ],
+        };
+
+        let rpc = MockRpc {
+            funding_tx: funding_tx(150_000),
+        };
+        let (outpoint, value) = RpcFundingSource { client: &rpc }
+            .find_utxo(&address, Amount::from_sat(150_000))
+            .unwrap();
+        assert_eq!(outpoint.txid, rpc.funding_tx.compute_txid());
+        assert_eq!(outpoint.vout, 1);
+        assert_eq!(value, Amount::from_sat(150_000));
+
+        // A wallet taking the fee out of the amount sent pays too little
+        let rpc = MockRpc {
+            funding_tx: funding_tx(149_800),
+        };
+        let err = RpcFundingSource { client: &rpc }
+            .find_utxo(&address, Amount::from_sat(150_000))
+            .unwrap_err();
+        assert!(err.to_string().contains("149800 sat"), "{err}");
+    }
+
+    #[test]
+    fn test_rpc_existing_utxo() {
+        let address = Address::p2wsh(&ScriptBuf::new(), Network::Regtest);
+        let funding_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(7_000_000),
+                    script_pubkey: ScriptBuf::new_p2wpkh(
+                        &WPubkeyHash::all_zeros(),
+                    ),
+                },
+                TxOut {
+                    value: Amount::from_sat(150_000),
+                    script_pubkey: address.script_pubkey(),
+                },
+            ],
+        };
+        let txid = funding_tx.compute_txid();
+        let rpc = MockRpc { funding_tx };
+        let source = |vout| RpcExistingUtxo {
+            client: &rpc,
+            outpoint: OutPoint { txid, vout },
+        };
+
+        let (outpoint, value) = source(1)
+            .find_utxo(&address, Amount::from_sat(150_000))
+            .unwrap();
+        assert_eq!(outpoint, OutPoint { txid, vout: 1 });
+        assert_eq!(value, Amount::from_sat(150_000));
+
+        // Another address's output, a missing one, or one too small
+        assert!(
+            source(0)
+                .find_utxo(&address, Amount::from_sat(150_000))
+                .is_err()
+        );
+        assert!(
+            source(2)
+                .find_utxo(&address, Amount::from_sat(150_000))
+                .is_err()
+        );
+        assert!(
+            source(1)
+                .find_utxo(&address, Amount::from_sat(150_001))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_select_utxo() {
+        let utxos: Vec<EsploraUtxo> = serde_json::from_str(
+            r#"[
+                {"txid": "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+                 "vout": 0, "value": 200000, "status": {"confirmed": false}},
+                {"txid": "0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098",
+                 "vout": 1, "value": 1000, "status": {"confirmed": true}},
+                {"txid": "9b0fc92260312ce44e74ef369f5c66bbb85848f2eddd5a7a1cde251e54ccfdd5",
+                 "vout": 2, "value": 150000, "status": {"confirmed": true, "block_height": 1}}
+            ]"#,
+        )
+        .unwrap();
+
+        // The confirmed UTXO wins over the larger unconfirmed one
+        let (outpoint, value) = select_utxo(&utxos, Amount::from_sat(150_000))
+            .unwrap()
+            .unwrap();
+        assert_eq!(outpoint.vout, 2);
+        assert_eq!(value, Amount::from_sat(150_000));
+
+        // Only the unconfirmed one is large enough
+        let (outpoint, _) = select_utxo(&utxos, Amount::from_sat(150_001))
+            .unwrap()
+            .unwrap();
+        assert_eq!(outpoint.vout, 0);
+
+        assert!(
+            select_utxo(&utxos, Amount::from_sat(300_000))
+                .unwrap()
+                .is_none()
+        );
+    }
+}
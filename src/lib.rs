@@ -1,5 +1,7 @@
 pub mod core;
+pub mod funding;
 pub mod musig2;
 pub mod output;
 pub mod transactions;
+pub mod transcript;
 pub mod utils;
@@ -5,15 +5,94 @@ use musig2::{
     secp256k1::{PublicKey, Secp256k1, SecretKey},
     sign_partial,
 };
-use rand::RngCore;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
 
+/// `N` random signer keypairs, seeded from OS randomness.
 pub fn generate_keys<const N: usize>() -> [(SecretKey, PublicKey); N] {
+    let mut seed = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut seed);
+    generate_keys_from_seed_bytes(seed)
+}
+
+/// Same as [`generate_keys`], but the keys are derived from the 32-byte
+/// `seed` with a ChaCha-based [`StdRng`], so the same seed always yields the
+/// same keys. Only for regression tests and reproducible demo runs.
+pub fn generate_keys_from_seed_bytes<const N: usize>(
+    seed: [u8; 32],
+) -> [(SecretKey, PublicKey); N] {
+    generate_keys_with_rng(&mut StdRng::from_seed(seed))
+}
+
+/// Same as [`generate_keys`], but the keys are derived from `seed`, so the
+/// same seed always yields the same keys. Only for reproducible demo runs.
+pub fn generate_keys_from_seed<const N: usize>(
+    seed: u64,
+) -> [(SecretKey, PublicKey); N] {
+    generate_keys_with_rng(&mut StdRng::seed_from_u64(seed))
+}
+
+/// `n` random signer keypairs, for a committee size only known at runtime.
+pub fn generate_keys_n(n: usize) -> Vec<(SecretKey, PublicKey)> {
+    generate_keys_n_with_rng(n, &mut rand::thread_rng())
+}
+
+/// Same as [`generate_keys_n`], but derived from `seed` like
+/// [`generate_keys_from_seed`]: the first `N` keys match it for the same
+/// seed.
+pub fn generate_keys_n_from_seed(
+    n: usize,
+    seed: u64,
+) -> Vec<(SecretKey, PublicKey)> {
+    generate_keys_n_with_rng(n, &mut StdRng::seed_from_u64(seed))
+}
+
+fn generate_keys_with_rng<const N: usize>(
+    rng: &mut impl Rng,
+) -> [(SecretKey, PublicKey); N] {
+    generate_keys_n_with_rng(N, rng).try_into().unwrap()
+}
+
+fn generate_keys_n_with_rng(
+    n: usize,
+    rng: &mut impl Rng,
+) -> Vec<(SecretKey, PublicKey)> {
     let secp = Secp256k1::new();
-    (0..N)
-        .map(|_| secp.generate_keypair(&mut rand::thread_rng()))
-        .collect::<Vec<_>>()
-        .try_into()
-        .unwrap()
+    (0..n).map(|_| secp.generate_keypair(rng)).collect()
+}
+
+/// Error aggregating signer keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAggregationError {
+    /// No signer keys were given
+    NoKeys,
+}
+
+impl std::fmt::Display for KeyAggregationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyAggregationError::NoKeys => {
+                write!(f, "at least one signer key is needed to aggregate")
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeyAggregationError {}
+
+/// MuSig2 key aggregation of `keys`: the context the signers share and the
+/// aggregated key the scripts check against.
+///
+/// An empty `keys` fails with [`KeyAggregationError::NoKeys`].
+pub fn aggregate_pubkeys(
+    keys: &[PublicKey],
+) -> anyhow::Result<(KeyAggContext, PublicKey)> {
+    if keys.is_empty() {
+        return Err(KeyAggregationError::NoKeys.into());
+    }
+    let ctx = KeyAggContext::new(keys.iter().copied())?;
+    let aggregated_pubkey = ctx.aggregated_pubkey();
+    Ok((ctx, aggregated_pubkey))
 }
 
 fn generate_nonce(
@@ -21,10 +100,11 @@ fn generate_nonce(
     aggregated_pubkey: impl Into<Point>,
     message: impl AsRef<[u8]>,
     signer_index: usize,
+    rng: &mut impl RngCore,
 ) -> (SecNonce, PubNonce) {
     // TODO: add signature for the nonce if you are use in for production.
     let mut nonce_seed = [0u8; 32];
-    rand::rngs::OsRng.fill_bytes(&mut nonce_seed);
+    rng.fill_bytes(&mut nonce_seed);
 
     let secnonce = SecNonce::build(nonce_seed)
         .with_seckey(key.0)
@@ -85,27 +165,90 @@ pub fn simulate_musig2(
     keys: &[(SecretKey, PublicKey)],
     message: &secp256k1::Message,
 ) -> anyhow::Result<LiftedSignature> {
+    Ok(simulate_musig2_verbose(keys, message)?.final_signature)
+}
+
+/// Same as [`simulate_musig2`], but the signing nonces are derived from
+/// `seed` and the message, so the signature is reproducible.
+///
+/// A nonce is only safe as long as it is never reused for a different
+/// message under the same key: mixing the message in keeps that property
+/// within one seed, but this must not be used outside of tests and demos.
+pub fn simulate_musig2_seeded(
+    keys: &[(SecretKey, PublicKey)],
+    message: &secp256k1::Message,
+    seed: u64,
+) -> anyhow::Result<LiftedSignature> {
+    Ok(simulate_musig2_seeded_verbose(keys, message, seed)?.final_signature)
+}
+
+/// Everything exchanged during a simulated MuSig2 signing ceremony, in
+/// signer order.
+#[derive(Debug, Clone)]
+pub struct Musig2Rounds {
+    /// Round 1: each signer's public nonce
+    pub public_nonces: Vec<PubNonce>,
+    /// Sum of the public nonces
+    pub aggregated_nonce: AggNonce,
+    /// Round 2: each signer's partial signature
+    pub partial_signatures: Vec<PartialSignature>,
+    /// The aggregated signature, as returned by [`simulate_musig2`]
+    pub final_signature: LiftedSignature,
+}
+
+/// Same as [`simulate_musig2`], but returning the nonces and partial
+/// signatures of both rounds along with the final signature.
+pub fn simulate_musig2_verbose(
+    keys: &[(SecretKey, PublicKey)],
+    message: &secp256k1::Message,
+) -> anyhow::Result<Musig2Rounds> {
+    simulate_musig2_with_rng(keys, message, &mut rand::rngs::OsRng)
+}
+
+/// Same as [`simulate_musig2_seeded`], but returning the nonces and partial
+/// signatures of both rounds along with the final signature.
+pub fn simulate_musig2_seeded_verbose(
+    keys: &[(SecretKey, PublicKey)],
+    message: &secp256k1::Message,
+    seed: u64,
+) -> anyhow::Result<Musig2Rounds> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&seed.to_le_bytes());
+    hasher.update(message.as_ref());
+    let mut rng = StdRng::from_seed(*hasher.finalize().as_bytes());
+    simulate_musig2_with_rng(keys, message, &mut rng)
+}
+
+fn simulate_musig2_with_rng(
+    keys: &[(SecretKey, PublicKey)],
+    message: &secp256k1::Message,
+    rng: &mut impl RngCore,
+) -> anyhow::Result<Musig2Rounds> {
     let message = message.as_ref();
     let n_of_n_public_keys: Vec<_> =
         keys.iter().map(|(_, pubkey)| *pubkey).collect();
 
-    let ctx = KeyAggContext::new(n_of_n_public_keys.clone())?;
-    let aggregated_pubkey: PublicKey = ctx.aggregated_pubkey();
+    let (_, aggregated_pubkey) = aggregate_pubkeys(&n_of_n_public_keys)?;
 
     let partial_nonce_pairs = keys
         .iter()
         .enumerate()
         .map(|(index, keypair)| {
-            generate_nonce(keypair, aggregated_pubkey, message, index)
+            generate_nonce(
+                keypair,
+                aggregated_pubkey,
+                message,
+                index,
+                &mut *rng,
+            )
         })
         .collect::<Vec<_>>();
 
-    let aggregated_nonce = partial_nonce_pairs
+    let public_nonces = partial_nonce_pairs
         .iter()
         .map(|(_, pubnonce)| pubnonce.clone())
-        .collect::<Vec<PubNonce>>()
-        .iter()
-        .sum();
+        .collect::<Vec<PubNonce>>();
+    let aggregated_nonce: AggNonce = public_nonces.iter().sum();
 
     let partial_signatures = keys
         .iter()
@@ -118,21 +261,25 @@ pub fn simulate_musig2(
                 secnonce,
                 &aggregated_nonce,
             )
-            .unwrap()
         })
-        .collect::<Vec<_>>();
+        .collect::<anyhow::Result<Vec<_>>>()?;
 
-    gen_aggregated_signature(
+    let final_signature = gen_aggregated_signature(
         n_of_n_public_keys,
         message,
         &aggregated_nonce,
+        partial_signatures.clone(),
+    )?;
+    Ok(Musig2Rounds {
+        public_nonces,
+        aggregated_nonce,
         partial_signatures,
-    )
+        final_signature,
+    })
 }
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::utils::inner_from;
     use secp256k1::constants::MESSAGE_SIZE;
     #[test]
     fn test_musig2_functional_api() {
@@ -157,11 +304,107 @@ mod tests {
     }
 
     #[test]
-    fn test_wrap_value() {
-        let secp = Secp256k1::new();
-        let keypair = secp.generate_keypair(&mut rand::thread_rng());
-        let keypair_sk: bitcoin::secp256k1::SecretKey = inner_from(keypair.0);
-        let keypair_sk_sk: SecretKey = inner_from(keypair_sk);
-        assert_eq!(keypair_sk_sk, keypair.0);
+    fn test_musig2_runtime_signer_count() {
+        let keys = generate_keys_n(5);
+        assert_eq!(keys.len(), 5);
+        let message = secp256k1::Message::from_digest([3u8; MESSAGE_SIZE]);
+
+        let signature = simulate_musig2(&keys, &message).unwrap();
+        let pubkeys = keys.iter().map(|key| key.1).collect::<Vec<_>>();
+        let (_, agg_pubkey) = aggregate_pubkeys(&pubkeys).unwrap();
+        musig2::verify_single(agg_pubkey, signature, message.as_ref())
+            .expect("aggregated signature must be valid");
+
+        assert_eq!(
+            generate_keys_n_from_seed(3, 7),
+            generate_keys_from_seed::<3>(7).to_vec()
+        );
+        assert_eq!(
+            generate_keys_n_from_seed(5, 7)[..3],
+            generate_keys_from_seed::<3>(7)
+        );
+    }
+
+    #[test]
+    fn test_seeded_musig2_is_reproducible() {
+        let keys = generate_keys_from_seed::<3>(7);
+        assert_eq!(keys, generate_keys_from_seed::<3>(7));
+        assert_ne!(keys, generate_keys_from_seed::<3>(8));
+
+        let message = secp256k1::Message::from_digest([2u8; MESSAGE_SIZE]);
+        let signature = simulate_musig2_seeded(&keys, &message, 7).unwrap();
+        assert_eq!(
+            signature,
+            simulate_musig2_seeded(&keys, &message, 7).unwrap()
+        );
+        assert_ne!(
+            signature,
+            simulate_musig2_seeded(&keys, &message, 8).unwrap()
+        );
+
+        let ctx = KeyAggContext::new(keys.iter().map(|key| key.1)).unwrap();
+        let agg_pubkey: PublicKey = ctx.aggregated_pubkey();
+        musig2::verify_single(agg_pubkey, signature, message.as_ref())
+            .expect("seeded signature must be valid");
+    }
+
+    #[test]
+    fn test_generate_keys_from_seed_bytes() {
+        let keys = generate_keys_from_seed_bytes::<3>([7u8; 32]);
+        assert_eq!(keys, generate_keys_from_seed_bytes::<3>([7u8; 32]));
+        assert_ne!(keys, generate_keys_from_seed_bytes::<3>([8u8; 32]));
+        assert_ne!(keys[0], keys[1]);
+        assert_ne!(generate_keys::<3>(), generate_keys::<3>());
+    }
+
+    #[test]
+    fn test_simulate_musig2_verbose() {
+        let keys = generate_keys_from_seed::<3>(7);
+        let message = secp256k1::Message::from_digest([5u8; MESSAGE_SIZE]);
+
+        let rounds =
+            simulate_musig2_seeded_verbose(&keys, &message, 7).unwrap();
+        assert_eq!(rounds.public_nonces.len(), keys.len());
+        assert_eq!(rounds.partial_signatures.len(), keys.len());
+        assert_eq!(
+            rounds.aggregated_nonce,
+            rounds.public_nonces.iter().sum::<AggNonce>()
+        );
+        assert_eq!(
+            rounds.final_signature.serialize(),
+            simulate_musig2_seeded(&keys, &message, 7)
+                .unwrap()
+                .serialize()
+        );
+
+        let pubkeys = keys.iter().map(|key| key.1).collect::<Vec<_>>();
+        let (_, agg_pubkey) = aggregate_pubkeys(&pubkeys).unwrap();
+        let rounds = simulate_musig2_verbose(&keys, &message).unwrap();
+        musig2::verify_single(
+            agg_pubkey,
+            rounds.final_signature,
+            message.as_ref(),
+        )
+        .expect("verbose signature must be valid");
+    }
+
+    #[test]
+    fn test_aggregate_pubkeys() {
+        let pubkeys = |seed| {
+            generate_keys_from_seed::<3>(seed)
+                .iter()
+                .map(|key| key.1)
+                .collect::<Vec<_>>()
+        };
+        let (ctx, agg_pubkey) = aggregate_pubkeys(&pubkeys(7)).unwrap();
+        assert_eq!(agg_pubkey, aggregate_pubkeys(&pubkeys(7)).unwrap().1);
+        assert_ne!(agg_pubkey, aggregate_pubkeys(&pubkeys(8)).unwrap().1);
+        assert_eq!(ctx.aggregated_pubkey::<PublicKey>(), agg_pubkey);
+
+        let err = aggregate_pubkeys(&[]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<KeyAggregationError>(),
+            Some(&KeyAggregationError::NoKeys)
+        );
     }
 }
@@ -1,21 +1,34 @@
-use anyhow::Result;
-use serde::Serialize;
+use crate::core::{
+    BBits, F1_THRESHOLD, F2_THRESHOLD, LBits, LIMB_LEN, PresignedFlow,
+    build_script_f1_blake3_locked, build_script_f2_blake3_locked,
+    calculate_flow_id, flow_id_to_prefix_bytes, truncate_flow_id,
+};
+use crate::musig2::aggregate_pubkeys;
+use crate::transactions::run_spend;
+use crate::utils::pubkey_inner_from;
+use anyhow::{Context, Result};
+use bitcoin::consensus::encode::deserialize_hex;
+use bitcoin::script::{Builder, PushBytesBuf};
+use bitcoin::{PrivateKey, ScriptBuf, Transaction, Witness};
+use bitvm::{ExecuteInfo, execute_script_buf};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct KeyInfo {
     pub signers: Vec<KeyPair>,
     // pub operator: KeyPair,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct KeyPair {
     //pub address: String,
     pub wif: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct TransactionInfo {
     pub f1: TxInfo,
     pub f2: TxInfo,
@@ -24,36 +37,594 @@ pub struct TransactionInfo {
     pub flow_id: u32,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct TxInfo {
     pub txid: String,
-    pub file_path: String,
+    /// Path of the raw tx file, `None` when files were not written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_path: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct DemoOutput {
+    /// Layout version of this output, see [`DemoOutput::CURRENT_VERSION`].
+    /// Outputs written before the field existed have the version 1 layout.
+    #[serde(default = "legacy_schema_version")]
+    pub schema_version: u32,
     pub keys: KeyInfo,
     pub transactions: Option<TransactionInfo>,
     pub input_x: u32,
     pub parameters: DemoParameters,
+    /// Free-form context about the run (timestamp, network, crate version,
+    /// `--meta` entries)
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
 }
 
-#[derive(Serialize)]
+impl DemoOutput {
+    /// Layout version written by this build, bumped on every change to the
+    /// shape of the JSON
+    pub const CURRENT_VERSION: u32 = 1;
+}
+
+fn legacy_schema_version() -> u32 {
+    1
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct DemoParameters {
     pub required_amount_sat: u64,
     pub l_param: usize,
     pub b_param: usize,
 }
 
+/// Writes `output` as pretty JSON to `<output_dir>/<path>`, creating
+/// `output_dir` (and its parents) if needed. IO errors name the path that
+/// was being created or written.
 pub fn write_demo_output_to_file(
     output: &DemoOutput,
     output_dir: &str,
     path: &str,
 ) -> Result<()> {
     let dir = Path::new(output_dir);
-    fs::create_dir_all(dir)?;
+    fs::create_dir_all(dir).with_context(|| {
+        format!("failed to create output directory {}", dir.display())
+    })?;
     let file_path = dir.join(path);
     println!("Writing demo output to file: {file_path:?}");
-    fs::write(file_path, serde_json::to_string_pretty(output)?)?;
+    fs::write(&file_path, serde_json::to_string_pretty(output)?).with_context(
+        || format!("failed to write demo output to {}", file_path.display()),
+    )?;
+    Ok(())
+}
+
+/// Reads a `DemoOutput` back from a JSON file written by
+/// [`write_demo_output_to_file`].
+///
+/// The schema version is checked before the rest of the file, so an output
+/// of another version is rejected as such rather than as a parse error.
+pub fn load_demo_output(path: &Path) -> Result<DemoOutput> {
+    let json = fs::read_to_string(path).with_context(|| {
+        format!("failed to read demo output from {}", path.display())
+    })?;
+    let value: serde_json::Value =
+        serde_json::from_str(&json).with_context(|| {
+            format!("failed to parse demo output {}", path.display())
+        })?;
+    let version = match value.get("schema_version") {
+        None => legacy_schema_version(),
+        Some(version) => version
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .with_context(|| {
+            format!("invalid schema_version {version} in {}", path.display())
+        })?,
+    };
+    if version != DemoOutput::CURRENT_VERSION {
+        anyhow::bail!(
+            "{} has demo output schema version {version}, this build only reads version {}",
+            path.display(),
+            DemoOutput::CURRENT_VERSION
+        );
+    }
+    serde_json::from_value(value).with_context(|| {
+        format!("failed to parse demo output {}", path.display())
+    })
+}
+
+/// Writes `flows` as pretty JSON to `path`, for an operator to reload with
+/// [`load_presigned_flows`].
+pub fn save_presigned_flows(
+    flows: &[PresignedFlow],
+    path: &Path,
+) -> Result<()> {
+    fs::write(path, serde_json::to_string_pretty(flows)?).with_context(|| {
+        format!("failed to write presigned flows to {}", path.display())
+    })
+}
+
+/// Reads the flows written by [`save_presigned_flows`].
+pub fn load_presigned_flows(path: &Path) -> Result<Vec<PresignedFlow>> {
+    let json = fs::read_to_string(path).with_context(|| {
+        format!("failed to read presigned flows from {}", path.display())
+    })?;
+    serde_json::from_str(&json).with_context(|| {
+        format!("failed to parse presigned flows {}", path.display())
+    })
+}
+
+/// A field whose value differs between two demo outputs. `None` stands
+/// for a field missing from that output (no transactions).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+/// The fields compared by [`diff_demo_outputs`], flattened to strings.
+fn diffable_fields(output: &DemoOutput) -> Vec<(&'static str, Option<String>)> {
+    let tx = output.transactions.as_ref();
+    vec![
+        ("input_x", Some(output.input_x.to_string())),
+        ("transactions.nonce", tx.map(|t| t.nonce.to_string())),
+        ("transactions.flow_id", tx.map(|t| t.flow_id.to_string())),
+        ("transactions.f1.txid", tx.map(|t| t.f1.txid.clone())),
+        ("transactions.f2.txid", tx.map(|t| t.f2.txid.clone())),
+        (
+            "transactions.spending.txid",
+            tx.map(|t| t.spending.txid.clone()),
+        ),
+        (
+            "parameters.required_amount_sat",
+            Some(output.parameters.required_amount_sat.to_string()),
+        ),
+        (
+            "parameters.l_param",
+            Some(output.parameters.l_param.to_string()),
+        ),
+        (
+            "parameters.b_param",
+            Some(output.parameters.b_param.to_string()),
+        ),
+    ]
+}
+
+/// Compares the input, txids, nonce, flow id and parameters of two demo
+/// runs and returns the fields that differ, in a fixed order.
+///
+/// Keys and metadata are left out: they differ between any two unseeded
+/// runs.
+pub fn diff_demo_outputs(a: &DemoOutput, b: &DemoOutput) -> Vec<FieldDiff> {
+    diffable_fields(a)
+        .into_iter()
+        .zip(diffable_fields(b))
+        .filter(|((_, left), (_, right))| left != right)
+        .map(|((field, left), (_, right))| FieldDiff {
+            field: field.to_string(),
+            left,
+            right,
+        })
+        .collect()
+}
+
+/// Reads the raw transaction `name` of a run, from `tx_dir` when given or
+/// else from its recorded path, and checks its recorded txid.
+fn read_demo_tx(
+    info: &TxInfo,
+    name: &str,
+    tx_dir: Option<&Path>,
+) -> Result<Transaction> {
+    let path = match (tx_dir, &info.file_path) {
+        (Some(dir), _) => dir.join(format!("{name}.tx")),
+        (None, Some(path)) => PathBuf::from(path),
+        (None, None) => {
+            anyhow::bail!("no file recorded for the {name} tx, pass --tx-dir")
+        }
+    };
+    let hex = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let tx: Transaction = deserialize_hex(hex.trim()).with_context(|| {
+        format!("invalid transaction in {}", path.display())
+    })?;
+    let txid = tx.compute_txid().to_string();
+    anyhow::ensure!(
+        txid == info.txid,
+        "{} has txid {txid}, the run recorded {}",
+        path.display(),
+        info.txid
+    );
+    Ok(tx)
+}
+
+/// The key the locks of `output` were built for: the MuSig2 aggregate of
+/// the recorded signers, or the single signer's key.
+fn demo_signer_pubkey(output: &DemoOutput) -> Result<bitcoin::PublicKey> {
+    let secp = bitcoin::secp256k1::Secp256k1::new();
+    let pubkeys = output
+        .keys
+        .signers
+        .iter()
+        .map(|key| {
+            let pk = PrivateKey::from_wif(&key.wif)?.public_key(&secp).inner;
+            Ok(musig2::secp256k1::PublicKey::from_slice(&pk.serialize())?)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let pubkey = match pubkeys.as_slice() {
+        [pk] => *pk,
+        _ => aggregate_pubkeys(&pubkeys)?.1,
+    };
+    Ok(bitcoin::PublicKey::new(pubkey_inner_from(pubkey)))
+}
+
+/// Checks that input 0 of `tx` spends through `lock`.
+fn check_revealed_leaf(
+    tx_name: &str,
+    tx: &Transaction,
+    lock: &ScriptBuf,
+) -> Result<(), String> {
+    let leaf = tx
+        .input
+        .first()
+        .and_then(|input| input.witness.tapscript())
+        .map(|script| script.as_bytes());
+    match leaf {
+        Some(leaf) if leaf == lock.as_bytes() => Ok(()),
+        Some(_) => Err(format!("{tx_name} reveals a different leaf script")),
+        None => Err(format!("{tx_name} has no script path witness")),
+    }
+}
+
+/// Re-checks the run recorded in the demo output at `path` without a node,
+/// reading its transactions from `tx_dir` when given.
+///
+/// The F1 and F2 locking scripts are rebuilt from the recorded input,
+/// nonce, flow id, parameters and signer keys, and the witness of each
+/// spend is executed against them, signature included. Returns each check
+/// with its outcome, in order; recorded `B`/`L` parameters that no flow
+/// can use fail the first check and skip the others.
+pub fn replay_demo_output(
+    path: &Path,
+    tx_dir: Option<&Path>,
+) -> Result<Vec<(&'static str, Result<(), String>)>> {
+    let output = load_demo_output(path)?;
+    let txs = output
+        .transactions
+        .as_ref()
+        .context("the demo output records no transactions")?;
+    let f1_tx = read_demo_tx(&txs.f1, "f1", tx_dir)?;
+    let f2_tx = read_demo_tx(&txs.f2, "f2", tx_dir)?;
+    let spending_tx = read_demo_tx(&txs.spending, "spending", tx_dir)?;
+
+    let (x, nonce, flow_id) = (output.input_x, txs.nonce, txs.flow_id);
+    let (b, l) = (output.parameters.b_param, output.parameters.l_param);
+    if let Err(err) = BBits::new(b).and_then(|b_bits| LBits::new(l, b_bits)) {
+        return Ok(vec![("parameters", Err(err.to_string()))]);
+    }
+    let signer = demo_signer_pubkey(&output)?;
+    let prefix = flow_id_to_prefix_bytes(flow_id, b);
+    let f1_lock = build_script_f1_blake3_locked(
+        &signer,
+        &prefix,
+        b,
+        F1_THRESHOLD,
+        LIMB_LEN,
+    );
+    let f2_lock = build_script_f2_blake3_locked(
+        &signer,
+        &prefix,
+        b,
+        F2_THRESHOLD,
+        LIMB_LEN,
+    );
+
+    let computed = truncate_flow_id(calculate_flow_id(x, nonce), b);
+    Ok(vec![
+        ("parameters", Ok(())),
+        (
+            "flow id",
+            if computed != flow_id {
+                Err(format!("H(x, nonce)|_B is {computed}, recorded {flow_id}"))
+            } else if u64::from(flow_id) >= 1 << l {
+                Err(format!("flow {flow_id} is outside D (L={l})"))
+            } else {
+                Ok(())
+            },
+        ),
+        ("F1 script", check_revealed_leaf("f2", &f2_tx, &f1_lock)),
+        (
+            "F1 spend",
+            run_spend("f2", &f2_tx, &f1_tx).map_err(|err| err.to_string()),
+        ),
+        (
+            "F2 script",
+            check_revealed_leaf("spending", &spending_tx, &f2_lock),
+        ),
+        (
+            "F2 spend",
+            run_spend("spending", &spending_tx, &f2_tx)
+                .map_err(|err| err.to_string()),
+        ),
+    ])
+}
+
+/// A locking script and the witness stack it failed on, hex-encoded, as
+/// written by [`export_failing_case`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FailingCase {
+    /// Witness stack items, bottom first
+    pub witness: Vec<String>,
+    pub locking_script: String,
+}
+
+/// Writes `witness` and `locking_script` as a self-contained JSON case
+/// that [`load_and_run_case`] re-executes offline.
+///
+/// `witness` is the stack the locking script starts from, i.e. a
+/// script-path witness without its script and control block. The script
+/// runs without a transaction context, so a signature check in it fails
+/// unless it was stripped beforehand.
+pub fn export_failing_case(
+    witness: &Witness,
+    locking_script: &ScriptBuf,
+    path: &Path,
+) -> Result<()> {
+    let case = FailingCase {
+        witness: witness.iter().map(hex::encode).collect(),
+        locking_script: hex::encode(locking_script.as_bytes()),
+    };
+    fs::write(path, serde_json::to_string_pretty(&case)?).with_context(
+        || format!("failed to write failing case to {}", path.display()),
+    )?;
     Ok(())
 }
+
+/// Reads a case written by [`export_failing_case`] and runs its locking
+/// script on its witness with `execute_script_buf`.
+pub fn load_and_run_case(path: &Path) -> Result<ExecuteInfo> {
+    let json = fs::read_to_string(path).with_context(|| {
+        format!("failed to read failing case from {}", path.display())
+    })?;
+    let case: FailingCase = serde_json::from_str(&json).with_context(|| {
+        format!("failed to parse failing case {}", path.display())
+    })?;
+
+    let witness = case
+        .witness
+        .iter()
+        .map(hex::decode)
+        .collect::<Result<Vec<_>, _>>()?;
+    let locking_script =
+        ScriptBuf::from_bytes(hex::decode(&case.locking_script)?);
+    Ok(execute_script_buf(witness_then_script(
+        witness,
+        &locking_script,
+    )?))
+}
+
+/// Pushes of the `witness` stack items, bottom first, followed by
+/// `locking_script`: the script `execute_script_buf` runs for a spend.
+fn witness_then_script(
+    witness: impl IntoIterator<Item = Vec<u8>>,
+    locking_script: &ScriptBuf,
+) -> Result<ScriptBuf> {
+    let mut script = Builder::new();
+    for (i, item) in witness.into_iter().enumerate() {
+        let item = PushBytesBuf::try_from(item)
+            .with_context(|| format!("witness item {i} is not pushable"))?;
+        script = script.push_slice(item);
+    }
+    let mut script = script.into_script().into_bytes();
+    script.extend(locking_script.as_bytes());
+    Ok(ScriptBuf::from_bytes(script))
+}
+
+/// A self-contained script test vector: the witness pushes and the locking
+/// script as one hex script, and whether it is expected to succeed.
+///
+/// Meant for other interpreters (e.g. a WASM build in a browser demo) to
+/// check they agree with `execute_script_buf`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ScriptVector {
+    pub script: String,
+    pub expected_success: bool,
+}
+
+/// Builds the [`ScriptVector`] of `locking` spent with the `witness` stack,
+/// recording the outcome of running it locally. Like
+/// [`export_failing_case`], `witness` excludes the script and control
+/// block.
+pub fn export_script_vector(
+    witness: &Witness,
+    locking: &ScriptBuf,
+) -> ScriptVector {
+    let script = witness_then_script(witness.to_vec(), locking)
+        .expect("witness items are at most the size of a block");
+    ScriptVector {
+        script: hex::encode(script.as_bytes()),
+        expected_success: execute_script_buf(script).success,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        BBits, F1_THRESHOLD, LBits, MessageSchema, build_script_f1_predicate,
+        build_script_step_body, find_valid_nonce, flow_id_to_prefix_bytes,
+        message_to_witness_limbs,
+    };
+
+    fn demo_output(nonce: u64) -> DemoOutput {
+        let tx_info = |txid: &str| TxInfo {
+            txid: txid.to_string(),
+            file_path: None,
+        };
+        DemoOutput {
+            schema_version: DemoOutput::CURRENT_VERSION,
+            keys: KeyInfo { signers: vec![] },
+            transactions: Some(TransactionInfo {
+                f1: tx_info("aa"),
+                f2: tx_info("bb"),
+                spending: tx_info("cc"),
+                nonce,
+                flow_id: 13,
+            }),
+            input_x: 114,
+            parameters: DemoParameters {
+                required_amount_sat: 150_000,
+                l_param: 4,
+                b_param: 16,
+            },
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_diff_demo_outputs() {
+        let a = demo_output(3543);
+        assert!(diff_demo_outputs(&a, &demo_output(3543)).is_empty());
+
+        let diff = diff_demo_outputs(&a, &demo_output(3544));
+        assert_eq!(
+            diff,
+            vec![FieldDiff {
+                field: "transactions.nonce".to_string(),
+                left: Some("3543".to_string()),
+                right: Some("3544".to_string()),
+            }]
+        );
+
+        let mut no_txs = demo_output(3543);
+        no_txs.transactions = None;
+        let diff = diff_demo_outputs(&a, &no_txs);
+        assert_eq!(diff.len(), 5);
+        assert!(diff.iter().all(|d| d.left.is_some() && d.right.is_none()));
+    }
+
+    #[test]
+    fn test_demo_output_metadata_round_trip() {
+        let metadata = BTreeMap::from([
+            ("network".to_string(), "regtest".to_string()),
+            ("operator".to_string(), "alice".to_string()),
+        ]);
+        let output = DemoOutput {
+            schema_version: DemoOutput::CURRENT_VERSION,
+            keys: KeyInfo { signers: vec![] },
+            transactions: None,
+            input_x: 114,
+            parameters: DemoParameters {
+                required_amount_sat: 100_000,
+                l_param: 4,
+                b_param: 16,
+            },
+            metadata: metadata.clone(),
+        };
+
+        let json = serde_json::to_string(&output).unwrap();
+        let parsed: DemoOutput = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.metadata, metadata);
+        assert_eq!(parsed.metadata["network"], "regtest");
+
+        // Outputs written before the field existed still parse
+        let mut legacy: serde_json::Value =
+            serde_json::from_str(&json).unwrap();
+        legacy.as_object_mut().unwrap().remove("metadata");
+        let parsed: DemoOutput = serde_json::from_value(legacy).unwrap();
+        assert!(parsed.metadata.is_empty());
+    }
+
+    #[test]
+    fn test_load_demo_output_checks_version() {
+        let dir = std::env::temp_dir()
+            .join(format!("collidervm_demo_output_{}", std::process::id()));
+        write_demo_output_to_file(
+            &demo_output(3543),
+            dir.to_str().unwrap(),
+            "demo.json",
+        )
+        .unwrap();
+        let path = dir.join("demo.json");
+        let loaded = load_demo_output(&path).unwrap();
+        assert_eq!(loaded.schema_version, DemoOutput::CURRENT_VERSION);
+        assert!(diff_demo_outputs(&loaded, &demo_output(3543)).is_empty());
+
+        // A doctored version is rejected by name, before the rest is parsed
+        let mut json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        json["schema_version"] = (DemoOutput::CURRENT_VERSION + 1).into();
+        fs::write(&path, json.to_string()).unwrap();
+        let err = load_demo_output(&path).unwrap_err().to_string();
+        assert!(err.contains("schema version 2"), "{err}");
+
+        // Outputs written before versioning have the version 1 layout
+        json.as_object_mut().unwrap().remove("schema_version");
+        fs::write(&path, json.to_string()).unwrap();
+        assert_eq!(load_demo_output(&path).unwrap().schema_version, 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failing_case_round_trip() {
+        // x = 50 passes the grind but not F1's x > 100
+        let x = 50;
+        let (nonce, flow_id) =
+            find_valid_nonce(x, BBits(16), LBits(4)).unwrap();
+        let locking_script = build_script_step_body(
+            build_script_f1_predicate(F1_THRESHOLD),
+            &flow_id_to_prefix_bytes(flow_id, 16),
+            &MessageSchema::default(),
+        );
+        let witness = Witness::from_slice(&message_to_witness_limbs(x, nonce));
+
+        let mut direct = Builder::new();
+        for item in witness.iter() {
+            direct = direct
+                .push_slice(PushBytesBuf::try_from(item.to_vec()).unwrap());
+        }
+        let mut direct = direct.into_script().into_bytes();
+        direct.extend(locking_script.as_bytes());
+        let expected = execute_script_buf(ScriptBuf::from_bytes(direct));
+        assert!(!expected.success);
+
+        let path = std::env::temp_dir().join(format!(
+            "collidervm_failing_case_{}.json",
+            std::process::id()
+        ));
+        export_failing_case(&witness, &locking_script, &path).unwrap();
+        let reproduced = load_and_run_case(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(!reproduced.success);
+        assert_eq!(reproduced.last_opcode, expected.last_opcode);
+        assert_eq!(
+            format!("{:?}", reproduced.error),
+            format!("{:?}", expected.error)
+        );
+        assert_eq!(reproduced.stats.opcode_count, expected.stats.opcode_count);
+    }
+
+    #[test]
+    fn test_script_vector() {
+        let x = 114;
+        let (nonce, flow_id) =
+            find_valid_nonce(x, BBits(16), LBits(4)).unwrap();
+        let locking = build_script_step_body(
+            build_script_f1_predicate(F1_THRESHOLD),
+            &flow_id_to_prefix_bytes(flow_id, 16),
+            &MessageSchema::default(),
+        );
+        let witness = Witness::from_slice(&message_to_witness_limbs(x, nonce));
+
+        let vector = export_script_vector(&witness, &locking);
+        assert!(vector.expected_success);
+        let script = hex::decode(&vector.script).unwrap();
+        assert!(script.ends_with(locking.as_bytes()));
+
+        let json = serde_json::to_value(&vector).unwrap();
+        assert_eq!(json["expected_success"], true);
+        let parsed: ScriptVector = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, vector);
+    }
+}
@@ -1,11 +1,21 @@
 use crate::core::{
-    build_script_f1_blake3_locked, build_script_f2_blake3_locked,
-    message_to_witness_limbs,
+    ColliderVmConfig, CoreError, F1_THRESHOLD, F2_THRESHOLD, LIMB_LEN, LimbLen,
+    PresignedStep, SCHNORR_SIG_SIZE, ScriptPredicate,
+    build_script_f1_blake3_locked, build_script_f1_fragments,
+    build_script_f2_blake3_locked, build_script_predicate_locked,
+    check_predicates_feasible, combine_scripts, flow_id_to_prefix_bytes,
+    measure_max_stack, message_to_witness_limbs,
+    message_to_witness_limbs_with_limb, witness_pushes,
 };
-use crate::utils::estimate_fee_vbytes;
-use anyhow;
+use crate::musig2::{aggregate_pubkeys, simulate_musig2};
+use crate::utils::{estimate_fee_vbytes, pubkey_inner_from};
+use bitcoin::Psbt;
+use bitcoin::script::{Instruction, PushBytesBuf};
 use bitcoin::sighash::Prevouts;
-use bitcoin::taproot::{LeafVersion, TaprootBuilder, TaprootSpendInfo};
+use bitcoin::taproot::{
+    ControlBlock, LeafVersion, TAPROOT_CONTROL_BASE_SIZE, TaprootBuilder,
+    TaprootSpendInfo,
+};
 use bitcoin::transaction::Version;
 use bitcoin::{
     Address, Amount, Network, OutPoint, ScriptBuf, Sequence, TapLeafHash,
@@ -15,13 +25,493 @@ use bitcoin::{
     secp256k1::{Message, Secp256k1},
     sighash::SighashCache,
 };
+use bitcoin_scriptexec::ExecStats;
+use bitvm::dry_run_taproot_input;
 use musig2::LiftedSignature;
-use secp256k1::{PublicKey, XOnlyPublicKey};
+use secp256k1::{PublicKey, XOnlyPublicKey, schnorr};
+use std::collections::HashMap;
+
+/// Error building, signing or sealing one of the demo transactions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxError {
+    /// A Taproot tree or control block could not be built
+    Taproot(String),
+    /// The output spent by `tx` cannot cover its fee
+    InsufficientValue {
+        tx: &'static str,
+        available_sat: u64,
+        fee_sat: u64,
+    },
+    /// The output of `tx` would be below the dust limit of its script
+    Dust {
+        tx: &'static str,
+        value_sat: u64,
+        dust_limit_sat: u64,
+    },
+    /// The sighash of a script-path spend could not be computed
+    Sighash(String),
+    /// An OP_RETURN payload exceeds [`MAX_OP_RETURN_PAYLOAD`]
+    AnchorTooLarge { len: usize },
+    /// A presigned template no longer matches the message its signers signed
+    TemplateMismatch { expected: Message, found: Message },
+    /// A presigned step carries no signatures
+    MissingSignatures,
+    /// A presigned signature does not verify for its signer
+    InvalidSignature { signer: String, reason: String },
+    /// A locking script is too large to be spent by a standard transaction
+    NonStandardScript(SizeError),
+    /// A PSBT could not be built from a transaction template
+    Psbt(String),
+    /// The MuSig2 key aggregation or signing failed
+    Signing(String),
+    /// The flow parameters or predicates are invalid
+    Config(CoreError),
+    /// An input of the transaction to bump does not signal BIP125 RBF
+    NotReplaceable { input: usize },
+    /// The bumped fee would not exceed the fee already paid by the
+    /// incremental relay fee (BIP125 rule 4)
+    FeeNotIncreased {
+        old_fee_sat: u64,
+        new_fee_sat: u64,
+        min_fee_sat: u64,
+    },
+    /// A multisig witness does not have one signature slot per lock key
+    SignatureCount { expected: usize, found: usize },
+}
+
+impl std::fmt::Display for TxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TxError::Taproot(err) => write!(f, "taproot construction: {err}"),
+            TxError::InsufficientValue {
+                tx,
+                available_sat,
+                fee_sat,
+            } => write!(
+                f,
+                "{available_sat} sat available to {tx}, too small for fee {fee_sat} sat"
+            ),
+            TxError::Dust {
+                tx,
+                value_sat,
+                dust_limit_sat,
+            } => write!(
+                f,
+                "{tx} output of {value_sat} sat is below the dust limit of {dust_limit_sat} sat"
+            ),
+            TxError::Sighash(err) => write!(f, "sighash computation: {err}"),
+            TxError::AnchorTooLarge { len } => write!(
+                f,
+                "OP_RETURN payload is {len} bytes, at most {MAX_OP_RETURN_PAYLOAD} are relayed"
+            ),
+            TxError::TemplateMismatch { expected, found } => write!(
+                f,
+                "tx template no longer matches its sighash: expected {expected}, got {found}"
+            ),
+            TxError::MissingSignatures => {
+                write!(f, "presigned step carries no signatures")
+            }
+            TxError::InvalidSignature { signer, reason } => {
+                write!(f, "invalid signature for signer {signer}: {reason}")
+            }
+            TxError::NonStandardScript(err) => write!(f, "{err}"),
+            TxError::Psbt(err) => write!(f, "psbt: {err}"),
+            TxError::Signing(err) => write!(f, "musig2 signing: {err}"),
+            TxError::Config(err) => write!(f, "{err}"),
+            TxError::NotReplaceable { input } => {
+                write!(f, "input {input} does not signal replaceability")
+            }
+            TxError::FeeNotIncreased {
+                old_fee_sat,
+                new_fee_sat,
+                min_fee_sat,
+            } => write!(
+                f,
+                "bumped fee {new_fee_sat} sat is below {min_fee_sat} sat, the {old_fee_sat} sat already paid plus the incremental relay fee"
+            ),
+            TxError::SignatureCount { expected, found } => write!(
+                f,
+                "expected one signature slot per lock key ({expected}), got {found}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TxError {}
+
+impl From<SizeError> for TxError {
+    fn from(err: SizeError) -> Self {
+        TxError::NonStandardScript(err)
+    }
+}
+
+impl From<CoreError> for TxError {
+    fn from(err: CoreError) -> Self {
+        TxError::Config(err)
+    }
+}
+
+/// Largest witness script relayed in a P2WSH spend (policy)
+pub const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+/// Largest relayed transaction weight (policy)
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+/// Fee-rate, in sat/vB, a replacement must add on top of the fee it
+/// replaces (policy, Bitcoin Core's default `-incrementalrelayfee`)
+pub const INCREMENTAL_RELAY_FEE_RATE: u64 = 1;
+
+/// A witness script larger than the `limit` it must stay within.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeError {
+    pub size: usize,
+    pub limit: usize,
+}
+
+impl std::fmt::Display for SizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "witness script is {} bytes, over the {}-byte standardness limit",
+            self.size, self.limit
+        )
+    }
+}
+
+impl std::error::Error for SizeError {}
+
+/// Checks that `script` is at most `limit` bytes.
+pub fn check_witness_script_size(
+    script: &ScriptBuf,
+    limit: usize,
+) -> Result<(), SizeError> {
+    if script.len() > limit {
+        return Err(SizeError {
+            size: script.len(),
+            limit,
+        });
+    }
+    Ok(())
+}
+
+/// Checks that `script` can be spent as a Tapscript leaf by a standard
+/// transaction.
+///
+/// Tapscript lifts the [`MAX_STANDARD_P2WSH_SCRIPT_SIZE`] limit, which the
+/// F1/F2 scripts (~17 KB) exceed by far. The leaf is still witness data of
+/// its spending transaction, at one weight unit per byte, so it can never
+/// be larger than [`MAX_STANDARD_TX_WEIGHT`].
+pub fn assert_witness_script_standard(
+    script: &ScriptBuf,
+) -> Result<(), SizeError> {
+    check_witness_script_size(script, MAX_STANDARD_TX_WEIGHT)
+}
+
+/// Largest number of stack items, main and alt stack combined (consensus)
+pub const MAX_STACK_SIZE: usize = 1000;
+/// Sigop budget a Tapscript input gets on top of its witness size (BIP342)
+pub const TAPSCRIPT_SIGOP_BUDGET_OFFSET: usize = 50;
+/// Budget consumed by each signature check with a non-empty signature
+pub const TAPSCRIPT_SIGOP_COST: usize = 50;
+
+/// An on-chain limit the F1 script of a config would break.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitViolation {
+    /// The config itself is invalid, so no script can be built for it
+    Config(CoreError),
+    /// The script is too large to be relayed
+    ScriptSize(SizeError),
+    /// Spending the script takes more than [`MAX_STACK_SIZE`] stack items
+    StackDepth { peak: usize, limit: usize },
+    /// The signature checks cost more than the witness sigop budget
+    SigopBudget { cost: usize, budget: usize },
+}
+
+impl std::fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitViolation::Config(err) => write!(f, "invalid config: {err}"),
+            LimitViolation::ScriptSize(err) => write!(f, "{err}"),
+            LimitViolation::StackDepth { peak, limit } => write!(
+                f,
+                "peak stack depth is {peak} items, over the {limit}-item limit"
+            ),
+            LimitViolation::SigopBudget { cost, budget } => write!(
+                f,
+                "signature checks cost {cost}, over the sigop budget of {budget}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitViolation {}
+
+impl ColliderVmConfig {
+    /// Checks that an F1 script for this config, signed by `signer_count`
+    /// separate keys, could be spent on-chain, and returns every limit it
+    /// breaks.
+    ///
+    /// The representative script is F1 for flow 0 with one
+    /// `<pubkey> OP_CHECKSIGVERIFY` per signer; a MuSig2-aggregated key is
+    /// `signer_count = 1`. It is checked against the relay size limit, the
+    /// consensus stack limit (signatures and message limbs on the initial
+    /// stack, then the peak of the script body) and the Tapscript sigop
+    /// budget of its spending witness.
+    pub fn validate_onchain_limits(
+        &self,
+        signer_count: usize,
+    ) -> Result<(), Vec<LimitViolation>> {
+        self.validate()
+            .map_err(|err| vec![LimitViolation::Config(err)])?;
+
+        // Only the length of the keys and signatures matters here
+        let secp = Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1u8; 32])
+            .expect("valid secret key");
+        let pubkey =
+            bitcoin::PublicKey::new(PublicKey::from_secret_key(&secp, &sk));
+        let prefix = flow_id_to_prefix_bytes(0, self.b);
+        let fragments = build_script_f1_fragments(
+            &pubkey,
+            &prefix,
+            self.b,
+            F1_THRESHOLD,
+            LIMB_LEN,
+        );
+        let mut parts = vec![fragments.signature.clone(); signer_count];
+        parts.extend(fragments.to_vec().into_iter().skip(1));
+        let script = combine_scripts(&parts);
+
+        let mut violations = Vec::new();
+        if let Err(err) = assert_witness_script_standard(&script) {
+            violations.push(LimitViolation::ScriptSize(err));
+        }
+
+        let limbs = message_to_witness_limbs(F1_THRESHOLD + 1, 0);
+        let limb_pushes = witness_pushes(&limbs);
+        let body = combine_scripts(&fragments.to_vec()[1..]);
+        let peak = (signer_count + limbs.len())
+            .max(measure_max_stack(&limb_pushes, &body));
+        if peak > MAX_STACK_SIZE {
+            violations.push(LimitViolation::StackDepth {
+                peak,
+                limit: MAX_STACK_SIZE,
+            });
+        }
+
+        let mut witness = Witness::new();
+        for limb in &limbs {
+            witness.push(limb);
+        }
+        for _ in 0..signer_count {
+            witness.push([0u8; 64]);
+        }
+        witness.push(script.as_bytes());
+        witness.push([0u8; 33]); // control block of a single-leaf tree
+        let cost = signer_count * TAPSCRIPT_SIGOP_COST;
+        let budget = TAPSCRIPT_SIGOP_BUDGET_OFFSET + witness.size();
+        if cost > budget {
+            violations.push(LimitViolation::SigopBudget { cost, budget });
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+/// Vsizes of the tx_f1, tx_f2 and spending tx of a flow with a `b_bits`
+/// prefix, each paying to one P2TR output.
+///
+/// They are measured on templates carrying placeholder witnesses of the
+/// final size (see [`lock_spend_placeholder_witness`]), so they follow any
+/// change to the scripts.
+pub fn flow_tx_vbytes(b_bits: usize) -> [usize; 3] {
+    let secp = Secp256k1::verification_only();
+    let key = nums_internal_key();
+    let signer = bitcoin::PublicKey::new(PublicKey::from_x_only_public_key(
+        key,
+        secp256k1::Parity::Even,
+    ));
+    let prefix = flow_id_to_prefix_bytes(0, b_bits);
+    let f1_lock = build_script_f1_blake3_locked(
+        &signer,
+        &prefix,
+        b_bits,
+        F1_THRESHOLD,
+        LIMB_LEN,
+    );
+    let f2_lock = build_script_f2_blake3_locked(
+        &signer,
+        &prefix,
+        b_bits,
+        F2_THRESHOLD,
+        LIMB_LEN,
+    );
+    let single_spend_vbytes = |witness: Witness| {
+        Transaction {
+            version: Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                witness,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptBuf::new_p2tr(&secp, key, None),
+            }],
+        }
+        .vsize()
+    };
+
+    [
+        single_spend_vbytes(Witness::from_slice(&[
+            vec![0u8; SCHNORR_SIG_SIZE],
+            get_funding_script(&key).to_bytes(),
+            vec![0u8; TAPROOT_CONTROL_BASE_SIZE],
+        ])),
+        single_spend_vbytes(lock_spend_placeholder_witness(&f1_lock, LIMB_LEN)),
+        single_spend_vbytes(lock_spend_placeholder_witness(&f2_lock, LIMB_LEN)),
+    ]
+}
+
+/// Fee of `tx` at `fee_rate` sat/vB, from its virtual size.
+///
+/// The vsize counts the witness, so every input of `tx` must already carry a
+/// witness of its final size: fill unsigned inputs with placeholders such as
+/// [`lock_spend_placeholder_witness`] first.
+pub fn estimate_tx_fee(tx: &Transaction, fee_rate: u64) -> Amount {
+    Amount::from_sat(tx.vsize() as u64 * fee_rate)
+}
+
+/// Placeholder of the witness spending a BLAKE3 `lock` (message limbs,
+/// signature, script and control block), as built by [`finalize_lock_tx`],
+/// for a lock built with `limb_len`.
+///
+/// Each limb is pushed at its largest (one byte for [`LIMB_LEN`]), while a
+/// smaller limb may be shorter and a zero limb is pushed empty, so the
+/// estimate never falls short of the final vsize (by at most 6 vbytes for
+/// the 24 limbs of `x || nonce` at [`LIMB_LEN`]). The control block is that
+/// of a single-leaf tree, see [`wrap_taproot_leaf`].
+pub fn lock_spend_placeholder_witness(
+    lock: &ScriptBuf,
+    limb_len: LimbLen,
+) -> Witness {
+    let mut witness = Witness::new();
+    let limb = limb_len.max_limb_push();
+    for _ in message_to_witness_limbs_with_limb(0, 0, limb_len) {
+        witness.push(&limb);
+    }
+    witness.push([0u8; SCHNORR_SIG_SIZE]);
+    witness.push(lock.as_bytes());
+    witness.push([0u8; TAPROOT_CONTROL_BASE_SIZE]);
+    witness
+}
+
+/// Output value left after paying `fee_sat` from `available_sat`, checked
+/// against the dust limit of `script_pubkey`.
+fn output_value_after_fee(
+    tx: &'static str,
+    available_sat: u64,
+    fee_sat: u64,
+    script_pubkey: &ScriptBuf,
+) -> Result<u64, TxError> {
+    let value_sat = available_sat.checked_sub(fee_sat).ok_or(
+        TxError::InsufficientValue {
+            tx,
+            available_sat,
+            fee_sat,
+        },
+    )?;
+    let dust_limit_sat = script_pubkey.minimal_non_dust().to_sat();
+    if value_sat < dust_limit_sat {
+        return Err(TxError::Dust {
+            tx,
+            value_sat,
+            dust_limit_sat,
+        });
+    }
+    Ok(value_sat)
+}
+
+/// `nSequence` of the inputs of the demo transactions: `0xfffffffd` to
+/// signal BIP125 replaceability, else final without RBF.
+fn input_sequence(enable_rbf: bool) -> Sequence {
+    if enable_rbf {
+        Sequence::ENABLE_RBF_NO_LOCKTIME
+    } else {
+        Sequence::ENABLE_LOCKTIME_NO_RBF
+    }
+}
+
+/// Rebuilds `tx` paying `new_fee_rate` sat/vB, with the same inputs and
+/// outputs, for a BIP125 replacement of it.
+///
+/// Per BIP125 rule 4, the new fee must exceed the old one by at least
+/// [`INCREMENTAL_RELAY_FEE_RATE`] times the vsize of the replacement, else
+/// it fails with [`TxError::FeeNotIncreased`].
+///
+/// `prevouts` are the outputs `tx` spends, in input order. The extra fee
+/// comes out of the largest output. `tx` must still carry its witnesses
+/// (or placeholders of their size) for the fee to cover them; the
+/// replacement has them cleared, since the signatures commit to the old
+/// output values and must be made again. Descendants presigned over the
+/// old txid have to be rebuilt too.
+pub fn bump_fee(
+    tx: &Transaction,
+    prevouts: &[TxOut],
+    new_fee_rate: u64,
+) -> Result<Transaction, TxError> {
+    if let Some(input) =
+        tx.input.iter().position(|input| !input.sequence.is_rbf())
+    {
+        return Err(TxError::NotReplaceable { input });
+    }
+    let input_sat = prevouts.iter().map(|out| out.value.to_sat()).sum::<u64>();
+    let output_sat =
+        tx.output.iter().map(|out| out.value.to_sat()).sum::<u64>();
+    let old_fee_sat = input_sat.saturating_sub(output_sat);
+    let new_fee_sat = estimate_tx_fee(tx, new_fee_rate).to_sat();
+    let min_fee_sat =
+        old_fee_sat + estimate_tx_fee(tx, INCREMENTAL_RELAY_FEE_RATE).to_sat();
+    if new_fee_sat < min_fee_sat {
+        return Err(TxError::FeeNotIncreased {
+            old_fee_sat,
+            new_fee_sat,
+            min_fee_sat,
+        });
+    }
+
+    let mut bumped = tx.clone();
+    let Some(largest) = bumped.output.iter_mut().max_by_key(|out| out.value)
+    else {
+        return Err(TxError::InsufficientValue {
+            tx: "bumped",
+            available_sat: 0,
+            fee_sat: new_fee_sat - old_fee_sat,
+        });
+    };
+    largest.value = Amount::from_sat(output_value_after_fee(
+        "bumped",
+        largest.value.to_sat(),
+        new_fee_sat - old_fee_sat,
+        &largest.script_pubkey,
+    )?);
+    for input in &mut bumped.input {
+        input.witness = Witness::new();
+    }
+    Ok(bumped)
+}
+
 // --------------------------------------------------------------------
 // Transaction Creation Functions
 // --------------------------------------------------------------------
 
 /// Creates and signs tx_f1, spending the funding UTXO to the F1 Taproot address.
+///
+/// The F1 lock checks `x > threshold`; the demo passes [`F1_THRESHOLD`].
+/// With `enable_rbf`, the input signals BIP125 replaceability so the tx can
+/// be fee-bumped with [`bump_fee`].
 #[allow(clippy::too_many_arguments)]
 pub fn create_f1_tx(
     b_bits: usize,
@@ -31,82 +521,85 @@ pub fn create_f1_tx(
     funding_outpoint: &OutPoint,
     funding_value_sat: &u64,
     flow_id_prefix: &[u8],
+    threshold: u32,
     fee_rate: &u64,
-) -> anyhow::Result<(
-    Transaction,
-    ScriptBuf,
-    TaprootSpendInfo,
-    ScriptBuf,
-    TaprootSpendInfo,
-    Message,
-)> {
+    internal_key: Option<XOnlyPublicKey>,
+    enable_rbf: bool,
+) -> Result<
+    (
+        Transaction,
+        ScriptBuf,
+        TaprootSpendInfo,
+        ScriptBuf,
+        TaprootSpendInfo,
+        Message,
+    ),
+    TxError,
+> {
     // ── build F1 locking script ─────────────────────────────────────────
     let lock = build_script_f1_blake3_locked(
         &bitcoin::PublicKey::new(*pk_signer),
         flow_id_prefix,
         b_bits,
+        threshold,
+        LIMB_LEN,
     );
+    assert_witness_script_standard(&lock)?;
 
     // ── wrap in a Taproot tree & derive its address ─────────────────────
-    let x_only_pk = secp256k1::XOnlyPublicKey::from(*pk_signer);
-    let spend_info = TaprootBuilder::new()
-        .add_leaf(0, lock.clone())
-        .expect("valid leaf")
-        .finalize(secp, x_only_pk)
-        .unwrap();
+    let xonly_pk = signer_xonly_key(pk_signer);
+    let (tr_addr, spend_info) =
+        wrap_taproot_leaf(secp, &lock, &xonly_pk, *network)?;
 
-    let tr_addr = Address::p2tr_tweaked(spend_info.output_key(), *network);
+    let funding_script = get_funding_script(&xonly_pk);
 
-    let fee_f1 = estimate_fee_vbytes(155, *fee_rate); // ~1 input + 1 output
-    let f1_output_value =
-        funding_value_sat.checked_sub(fee_f1).unwrap_or_else(|| {
-            panic!("function {funding_value_sat} too small for fee {fee_f1}")
-        });
+    let funding_spend_info =
+        funding_taproot_spend_info(secp, &xonly_pk, internal_key);
+
+    // The scriptPubKey for this Taproot output and the address (for funding):
+    let funding_address =
+        Address::p2tr_tweaked(funding_spend_info.output_key(), *network);
 
+    // Size the fee with a placeholder of the funding script-path witness
+    let funding_control_block =
+        leaf_control_block(&funding_spend_info, &funding_script)?;
     let mut tx_f1 = Transaction {
         version: Version::TWO,
         lock_time: absolute::LockTime::ZERO,
         input: vec![TxIn {
             previous_output: *funding_outpoint,
             script_sig: ScriptBuf::new(),
-            sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
-            witness: Witness::new(),
+            sequence: input_sequence(enable_rbf),
+            witness: Witness::from_slice(&[
+                vec![0u8; SCHNORR_SIG_SIZE],
+                funding_script.to_bytes(),
+                funding_control_block.serialize(),
+            ]),
         }],
         output: vec![TxOut {
-            value: Amount::from_sat(f1_output_value),
+            value: Amount::ZERO,
             script_pubkey: tr_addr.script_pubkey(),
         }],
     };
+    let fee_f1 = estimate_tx_fee(&tx_f1, *fee_rate);
+    let f1_output_value = output_value_after_fee(
+        "f1",
+        *funding_value_sat,
+        fee_f1.to_sat(),
+        &tr_addr.script_pubkey(),
+    )?;
+    tx_f1.output[0].value = Amount::from_sat(f1_output_value);
+    tx_f1.input[0].witness = Witness::new();
 
-    let xonly_pk = XOnlyPublicKey::from(*pk_signer);
-    let funding_script = get_funding_script(&xonly_pk);
-
-    let leaf_hash =
-        TapLeafHash::from_script(&funding_script, LeafVersion::TapScript);
-
-    // Build the tree with a single leaf
-    let funding_spend_info = TaprootBuilder::new()
-        .add_leaf(0, funding_script.clone())?
-        .finalize(secp, xonly_pk)
-        .unwrap();
-
-    // The scriptPubKey for this Taproot output and the address (for funding):
-    let funding_address =
-        Address::p2tr_tweaked(funding_spend_info.output_key(), *network);
-
-    let mut cache = SighashCache::new(&mut tx_f1);
-    let sighash = cache.taproot_script_spend_signature_hash(
-        0,
-        &Prevouts::All(&[TxOut {
+    let msg = taproot_leaf_sighash(
+        &tx_f1,
+        &[TxOut {
             value: Amount::from_sat(*funding_value_sat),
             script_pubkey: funding_address.script_pubkey(),
-        }]),
-        leaf_hash,
-        TapSighashType::Default,
+        }],
+        &funding_script,
     )?;
 
-    let msg = Message::from_digest_slice(&sighash[..])?;
-
     Ok((
         tx_f1,
         lock,
@@ -117,30 +610,191 @@ pub fn create_f1_tx(
     ))
 }
 
+/// Wraps `script` as the single leaf of a Taproot tree with `internal_key`,
+/// returning the output address and the spend info needed to build the
+/// control block of a later script-path spend.
+pub fn wrap_taproot_leaf(
+    secp: &Secp256k1<secp256k1::All>,
+    script: &ScriptBuf,
+    internal_key: &XOnlyPublicKey,
+    network: Network,
+) -> Result<(Address, TaprootSpendInfo), TxError> {
+    let spend_info = TaprootBuilder::new()
+        .add_leaf(0, script.clone())
+        .map_err(|e| TxError::Taproot(e.to_string()))?
+        .finalize(secp, *internal_key)
+        .map_err(|_| TxError::Taproot("incomplete taproot tree".to_string()))?;
+    let address = Address::p2tr_tweaked(spend_info.output_key(), network);
+    Ok((address, spend_info))
+}
+
+/// The address of the F1 lock for every flow of `D`, as `(flow_id, address)`
+/// pairs in flow order.
+///
+/// Each address is the Taproot output [`create_f1_tx`] pays to for that
+/// flow's prefix, so an operator funds the one matching the flow it ground.
+/// `config` is expected to pass [`ColliderVmConfig::validate`]; every flow
+/// builds a full F1 script, so this is only practical for small `L`.
+pub fn flow_set_addresses(
+    config: &ColliderVmConfig,
+    signer_pubkey: &PublicKey,
+    network: Network,
+) -> Result<Vec<(u32, Address)>, TxError> {
+    let secp = Secp256k1::new();
+    let internal_key = signer_xonly_key(signer_pubkey);
+    (0..1u32 << config.l)
+        .map(|flow_id| {
+            let lock = build_script_f1_blake3_locked(
+                &bitcoin::PublicKey::new(*signer_pubkey),
+                &flow_id_to_prefix_bytes(flow_id, config.b),
+                config.b,
+                F1_THRESHOLD,
+                LIMB_LEN,
+            );
+            let (address, _) =
+                wrap_taproot_leaf(&secp, &lock, &internal_key, network)?;
+            Ok((flow_id, address))
+        })
+        .collect()
+}
+
+/// Control block for spending `leaf` through `spend_info`.
+fn leaf_control_block(
+    spend_info: &TaprootSpendInfo,
+    leaf: &ScriptBuf,
+) -> Result<ControlBlock, TxError> {
+    spend_info
+        .control_block(&(leaf.clone(), LeafVersion::TapScript))
+        .ok_or_else(|| {
+            TxError::Taproot("script is not a leaf of the tree".to_string())
+        })
+}
+
+/// The x-only key the funding leaf, the step scripts' trees and their
+/// internal keys commit to.
+///
+/// BIP340 keys stand for the even-Y point with their x coordinate, so the
+/// parity of `pk` is dropped here once for every caller: MuSig2 negates the
+/// secret shares of an odd-Y aggregate key, and its signatures verify
+/// against this key whatever the parity.
+pub fn signer_xonly_key(pk: &PublicKey) -> XOnlyPublicKey {
+    pk.x_only_public_key().0
+}
+
+/// `<xonly_pk> OP_CHECKSIG`, the leaf locking the funding output.
 pub fn get_funding_script(xonly_pk: &XOnlyPublicKey) -> ScriptBuf {
     bitcoin::script::Builder::new()
         .push_x_only_key(xonly_pk)
         .push_opcode(bitcoin::opcodes::all::OP_CHECKSIG)
         .into_script()
 }
+
+/// BIP341 "nothing up my sleeve" point `H`, whose discrete log is unknown.
+const NUMS_POINT_BYTES: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60,
+    0x35, 0xe9, 0x7a, 0x5e, 0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5,
+    0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// Returns the BIP341 NUMS point, usable as an internal key to disable the
+/// key-path spend of a Taproot output (forcing script-path only).
+pub fn nums_internal_key() -> XOnlyPublicKey {
+    XOnlyPublicKey::from_slice(&NUMS_POINT_BYTES)
+        .expect("BIP341 NUMS point is a valid x-only key")
+}
+
+/// Builds the single-leaf Taproot tree for the funding output.
+///
+/// The internal key defaults to the signer key; pass `Some(nums_internal_key())`
+/// to make the output script-path only.
+pub fn funding_taproot_spend_info(
+    secp: &Secp256k1<secp256k1::All>,
+    xonly_pk: &XOnlyPublicKey,
+    internal_key: Option<XOnlyPublicKey>,
+) -> TaprootSpendInfo {
+    TaprootBuilder::new()
+        .add_leaf(0, get_funding_script(xonly_pk))
+        .expect("valid leaf")
+        .finalize(secp, internal_key.unwrap_or(*xonly_pk))
+        .expect("single leaf tree is always finalizable")
+}
+
+/// Tapleaf hash of the funding script for `xonly_pk`.
+pub fn funding_tapleaf_hash(xonly_pk: &XOnlyPublicKey) -> TapLeafHash {
+    TapLeafHash::from_script(
+        &get_funding_script(xonly_pk),
+        LeafVersion::TapScript,
+    )
+}
+
+/// Control block for spending the funding script path of the output built
+/// by [`funding_taproot_spend_info`] with the default (signer) internal key.
+pub fn funding_control_block(
+    xonly_pk: &XOnlyPublicKey,
+    secp: &Secp256k1<secp256k1::All>,
+) -> ControlBlock {
+    funding_taproot_spend_info(secp, xonly_pk, None)
+        .control_block(&(get_funding_script(xonly_pk), LeafVersion::TapScript))
+        .expect("funding script is a leaf of the funding tree")
+}
+
+/// Checks that `sig` verifies for the key `leaf` starts with, over the
+/// sighash of `tx` spending `prevout` through `leaf`. A taproot signature
+/// commits to the prevout, so one made over another value fails here
+/// rather than at broadcast.
+fn check_leaf_signature(
+    tx: &Transaction,
+    sig: &LiftedSignature,
+    leaf: &ScriptBuf,
+    prevout: &TxOut,
+) -> Result<(), TxError> {
+    let Some(Ok(Instruction::PushBytes(key))) = leaf.instructions().next()
+    else {
+        return Err(TxError::Taproot(
+            "leaf does not start with a signer key".to_string(),
+        ));
+    };
+    let invalid = |reason: String| TxError::InvalidSignature {
+        signer: hex::encode(key.as_bytes()),
+        reason,
+    };
+    let xonly = XOnlyPublicKey::from_slice(key.as_bytes())
+        .map_err(|e| invalid(e.to_string()))?;
+    let signature = schnorr::Signature::from_slice(&sig.serialize())
+        .map_err(|e| invalid(e.to_string()))?;
+    let sighash =
+        taproot_leaf_sighash(tx, std::slice::from_ref(prevout), leaf)?;
+    Secp256k1::verification_only()
+        .verify_schnorr(&signature, &sighash, &xonly)
+        .map_err(|e| invalid(e.to_string()))
+}
+
+/// Attaches the funding script-path witness to `tx`.
+///
+/// `sig` must verify over the sighash of spending `prevout`, the funding
+/// output, through `funding_script`.
 pub fn finalize_f1_tx(
     tx: &mut Transaction,
     sig: LiftedSignature,
     spend_info: &TaprootSpendInfo,
     funding_script: &ScriptBuf,
-) {
-    let control_block = spend_info
-        .control_block(&(funding_script.clone(), LeafVersion::TapScript))
-        .unwrap();
+    prevout: &TxOut,
+) -> Result<(), TxError> {
+    check_leaf_signature(tx, &sig, funding_script, prevout)?;
+    let control_block = leaf_control_block(spend_info, funding_script)?;
 
     tx.input[0].witness = Witness::from_slice(&[
         sig.serialize().to_vec(),
         funding_script.to_bytes(),
         control_block.serialize(),
     ]);
+    Ok(())
 }
 
 /// Creates and signs tx_f2, spending the F1 output to the F2 Taproot address.
+///
+/// The F2 lock checks `x < threshold`; the demo passes [`F2_THRESHOLD`].
+/// `enable_rbf` is as for [`create_f1_tx`].
 #[allow(clippy::too_many_arguments)]
 pub fn create_f2_tx(
     b_bits: usize,
@@ -151,28 +805,25 @@ pub fn create_f2_tx(
     f1_output_value: &u64,
     f1_lock: &ScriptBuf,
     flow_id_prefix: &[u8],
+    threshold: u32,
     fee_rate: &u64,
-) -> anyhow::Result<(Transaction, ScriptBuf, TaprootSpendInfo, Message)> {
+    enable_rbf: bool,
+) -> Result<(Transaction, ScriptBuf, TaprootSpendInfo, Message), TxError> {
     // ── build F2 locking script & Taproot branch ────────────────────────
     let f2_lock = build_script_f2_blake3_locked(
         &bitcoin::PublicKey::new(*pk_signer),
         flow_id_prefix,
         b_bits,
+        threshold,
+        LIMB_LEN,
     );
-    let x_only_pk = secp256k1::XOnlyPublicKey::from(*pk_signer);
-    let spend_info = TaprootBuilder::new()
-        .add_leaf(0, f2_lock.clone())
-        .expect("valid leaf")
-        .finalize(secp, x_only_pk)
-        .unwrap();
-    let tr_addr = Address::p2tr_tweaked(spend_info.output_key(), *network);
-
-    // Now the tx vsize is about 17093.
-    let fee_f2 = estimate_fee_vbytes(17093, *fee_rate); // 1 input P2TR + 1 output
-    let f2_output_value =
-        f1_output_value.checked_sub(fee_f2).unwrap_or_else(|| {
-            panic!("f1 output {f1_output_value} too small for f2 fee {fee_f2}")
-        });
+    assert_witness_script_standard(&f2_lock)?;
+    let (tr_addr, spend_info) = wrap_taproot_leaf(
+        secp,
+        &f2_lock,
+        &signer_xonly_key(pk_signer),
+        *network,
+    )?;
 
     let mut tx_f2 = Transaction {
         version: Version::TWO,
@@ -183,30 +834,136 @@ pub fn create_f2_tx(
                 vout: 0,
             },
             script_sig: ScriptBuf::new(),
-            sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
-            witness: Witness::new(),
+            sequence: input_sequence(enable_rbf),
+            witness: lock_spend_placeholder_witness(f1_lock, LIMB_LEN),
         }],
         output: vec![TxOut {
-            value: Amount::from_sat(f2_output_value),
+            value: Amount::ZERO,
             script_pubkey: tr_addr.script_pubkey(),
         }],
     };
-
-    // Build the witness stack for the P2TR spend
-    let leaf_hash = TapLeafHash::from_script(f1_lock, LeafVersion::TapScript);
-
-    let mut cache = SighashCache::new(&mut tx_f2);
-    let sighash = cache.taproot_script_spend_signature_hash(
-        0,
-        &Prevouts::All(&[f1_tx.output[0].clone()]),
-        leaf_hash,
-        TapSighashType::Default,
+    let fee_f2 = estimate_tx_fee(&tx_f2, *fee_rate);
+    let f2_output_value = output_value_after_fee(
+        "f2",
+        *f1_output_value,
+        fee_f2.to_sat(),
+        &tr_addr.script_pubkey(),
     )?;
+    tx_f2.output[0].value = Amount::from_sat(f2_output_value);
+    tx_f2.input[0].witness = Witness::new();
 
-    let msg = Message::from_digest_slice(&sighash[..])?;
+    let msg = taproot_leaf_sighash(&tx_f2, &f1_tx.output[..1], f1_lock)?;
     Ok((tx_f2, f2_lock, spend_info, msg))
 }
 
+/// Builds the presigned steps of a flow with one step per predicate,
+/// generalizing F1/F2 to any number of steps.
+///
+/// Step `i` spends an output locked by predicate `i` under a `b_bits`
+/// flow-id prefix (see [`build_script_predicate_locked`]) and pays to the
+/// lock of step `i + 1`; the last step pays to `payout`. Step 0 spends
+/// `flow_outpoint`, which must hold `flow_value_sat` at the first lock's
+/// address. Every step pays the fee of its own vsize, with the witness
+/// sized by [`lock_spend_placeholder_witness`].
+///
+/// The steps carry no signatures yet: each `sighash_message` is what the
+/// signers presign. Predicates that no input satisfies together are
+/// rejected up front with [`CoreError::InfeasiblePredicates`].
+///
+/// `enable_rbf` is as for [`create_f1_tx`].
+#[allow(clippy::too_many_arguments)]
+pub fn build_flow_steps(
+    secp: &Secp256k1<secp256k1::All>,
+    b_bits: usize,
+    predicates: &[ScriptPredicate],
+    signer_pubkey: &PublicKey,
+    flow_id_prefix: &[u8],
+    network: Network,
+    flow_outpoint: OutPoint,
+    flow_value_sat: u64,
+    payout: &ScriptBuf,
+    fee_rate: u64,
+    enable_rbf: bool,
+) -> Result<Vec<PresignedStep>, TxError> {
+    check_predicates_feasible(predicates)?;
+    let internal_key = signer_xonly_key(signer_pubkey);
+    let locks = predicates
+        .iter()
+        .map(|predicate| {
+            let lock = build_script_predicate_locked(
+                &bitcoin::PublicKey::new(*signer_pubkey),
+                flow_id_prefix,
+                b_bits,
+                predicate,
+            );
+            assert_witness_script_standard(&lock)?;
+            let (address, _) =
+                wrap_taproot_leaf(secp, &lock, &internal_key, network)?;
+            Ok((lock, address.script_pubkey()))
+        })
+        .collect::<Result<Vec<_>, TxError>>()?;
+
+    let mut steps = Vec::with_capacity(locks.len());
+    let mut prev_outpoint = flow_outpoint;
+    let mut prevout = match locks.first() {
+        Some((_, script_pubkey)) => TxOut {
+            value: Amount::from_sat(flow_value_sat),
+            script_pubkey: script_pubkey.clone(),
+        },
+        None => return Ok(steps),
+    };
+    for (i, (lock, _)) in locks.iter().enumerate() {
+        let script_pubkey = match locks.get(i + 1) {
+            Some((_, next)) => next.clone(),
+            None => payout.clone(),
+        };
+        let mut tx = Transaction {
+            version: Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: prev_outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: input_sequence(enable_rbf),
+                witness: lock_spend_placeholder_witness(lock, LIMB_LEN),
+            }],
+            output: vec![TxOut {
+                value: Amount::ZERO,
+                script_pubkey,
+            }],
+        };
+        let value_sat = output_value_after_fee(
+            "flow step",
+            prevout.value.to_sat(),
+            estimate_tx_fee(&tx, fee_rate).to_sat(),
+            &tx.output[0].script_pubkey,
+        )?;
+        tx.output[0].value = Amount::from_sat(value_sat);
+        tx.input[0].witness = Witness::new();
+        let sighash_message =
+            taproot_leaf_sighash(&tx, std::slice::from_ref(&prevout), lock)?;
+
+        prev_outpoint = OutPoint {
+            txid: tx.compute_txid(),
+            vout: 0,
+        };
+        let next_prevout = tx.output[0].clone();
+        steps.push(PresignedStep {
+            tx_template: tx,
+            prevouts: vec![prevout],
+            spent_leaf: lock.clone(),
+            sighash_message,
+            signatures: HashMap::new(),
+            limb_len: LIMB_LEN,
+        });
+        prevout = next_prevout;
+    }
+    Ok(steps)
+}
+
+/// Attaches the witness spending `lock` with `x || nonce` to `tx`.
+///
+/// `sig` must verify over the sighash of spending `prevout`, the lock
+/// output, through `lock`.
 pub fn finalize_lock_tx(
     tx: &mut Transaction,
     sig: LiftedSignature,
@@ -214,108 +971,803 @@ pub fn finalize_lock_tx(
     lock: &ScriptBuf,
     x: &u32,
     nonce: &u64,
-) -> anyhow::Result<()> {
-    // Assemble witness
-    let control_block = spend_info
-        .control_block(&(lock.clone(), LeafVersion::TapScript))
-        .unwrap();
+    prevout: &TxOut,
+) -> Result<(), TxError> {
+    check_leaf_signature(tx, &sig, lock, prevout)?;
+    let control_block = leaf_control_block(spend_info, lock)?;
+    tx.input[0].witness =
+        lock_witness(x, nonce, &sig.serialize(), lock, &control_block);
+    Ok(())
+}
+
+/// Witness spending `lock` with `x || nonce`: the message limbs, `sig`, the
+/// script and its control block.
+fn lock_witness(
+    x: &u32,
+    nonce: &u64,
+    sig: &[u8],
+    lock: &ScriptBuf,
+    control_block: &ControlBlock,
+) -> Witness {
+    lock_witness_with_slots(x, nonce, [sig], lock, control_block)
+}
 
+/// [`lock_witness`] with any number of signature slots, pushed in order so
+/// the last one ends up on top of the message limbs.
+fn lock_witness_with_slots<S: AsRef<[u8]>>(
+    x: &u32,
+    nonce: &u64,
+    sig_slots: impl IntoIterator<Item = S>,
+    lock: &ScriptBuf,
+    control_block: &ControlBlock,
+) -> Witness {
     let mut witness = Witness::new();
     for limb in message_to_witness_limbs(*x, *nonce) {
         witness.push(limb);
     }
 
-    witness.push(sig.serialize());
+    for slot in sig_slots {
+        witness.push(slot);
+    }
     witness.push(lock.to_bytes());
     witness.push(control_block.serialize());
+    witness
+}
 
-    tx.input[0].witness = witness;
+/// Attaches the witness spending a `build_script_f1_multisig_locked` lock
+/// with `x || nonce` to `tx`.
+///
+/// `sigs` holds one entry per signer, in the order of the lock's keys;
+/// `None` for the signers not signing. Any other number of entries than
+/// the lock has keys fails with [`TxError::SignatureCount`].
+pub fn finalize_multisig_lock_tx(
+    tx: &mut Transaction,
+    sigs: &[Option<schnorr::Signature>],
+    spend_info: &TaprootSpendInfo,
+    lock: &ScriptBuf,
+    x: &u32,
+    nonce: &u64,
+) -> Result<(), TxError> {
+    let keys = lock
+        .instructions()
+        .filter(|ins| {
+            matches!(
+                ins,
+                Ok(Instruction::Op(op))
+                    if *op == bitcoin::opcodes::all::OP_CHECKSIG
+                        || *op == bitcoin::opcodes::all::OP_CHECKSIGADD
+            )
+        })
+        .count();
+    if sigs.len() != keys {
+        return Err(TxError::SignatureCount {
+            expected: keys,
+            found: sigs.len(),
+        });
+    }
+    let control_block = leaf_control_block(spend_info, lock)?;
+    // The first key's slot ends up on top
+    let slots = sigs.iter().rev().map(|sig| match sig {
+        Some(sig) => sig.serialize().to_vec(),
+        None => Vec::new(),
+    });
+    tx.input[0].witness =
+        lock_witness_with_slots(x, nonce, slots, lock, &control_block);
     Ok(())
 }
 
-/// Creates and signs the spending transaction, spending the F2 output to the receiver.
-#[allow(clippy::too_many_arguments)]
-pub fn create_spending_tx(
-    f2_tx: &Transaction,
-    f2_output_value: &u64,
-    receiver_addr: &Address,
-    f2_lock: &ScriptBuf,
-    fee_rate: &u64,
-) -> anyhow::Result<(Transaction, Message)> {
-    let fee_spending_tx = estimate_fee_vbytes(17082, *fee_rate); // 1 input P2TR + 1 output
-    let spending_output_value = f2_output_value
-        .checked_sub(fee_spending_tx)
-        .unwrap_or_else(|| panic!("f2 output {f2_output_value} too small for spending tx {fee_spending_tx}"));
+/// The funding outpoint and the three transactions of one demo run, in
+/// broadcast order.
+#[derive(Debug, Clone)]
+pub struct DemoChain {
+    pub funding_outpoint: OutPoint,
+    pub funding_value_sat: u64,
+    pub x: u32,
+    pub nonce: u64,
+    pub flow_id: u32,
+    pub f1_tx: Transaction,
+    pub f2_tx: Transaction,
+    pub spending_tx: Transaction,
+}
 
-    let mut spending_tx = Transaction {
-        version: Version::TWO,
-        lock_time: absolute::LockTime::ZERO,
-        input: vec![TxIn {
-            previous_output: OutPoint {
-                txid: f2_tx.compute_txid(),
+/// A broken link in a [`DemoChain`], found before broadcasting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainError {
+    /// `tx` does not have exactly one input and one output (plus an
+    /// optional OP_RETURN anchor for the spending tx)
+    Shape { tx: &'static str },
+    /// `tx` does not spend the output it should
+    WrongOutpoint {
+        tx: &'static str,
+        expected: OutPoint,
+        found: OutPoint,
+    },
+    /// `tx` pays out at least as much as the output it spends
+    NonPositiveFee {
+        tx: &'static str,
+        input_sat: u64,
+        output_sat: u64,
+    },
+}
+
+impl std::fmt::Display for ChainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChainError::Shape { tx } => {
+                write!(f, "{tx} must have exactly one input and one output")
+            }
+            ChainError::WrongOutpoint {
+                tx,
+                expected,
+                found,
+            } => write!(f, "{tx} spends {found}, expected {expected}"),
+            ChainError::NonPositiveFee {
+                tx,
+                input_sat,
+                output_sat,
+            } => write!(
+                f,
+                "{tx} spends {input_sat} sat but pays out {output_sat} sat"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChainError {}
+
+/// Checks that f1 spends the funding outpoint, f2 spends f1's output and the
+/// spending tx spends f2's output, each paying a positive fee.
+pub fn validate_chain_links(chain: &DemoChain) -> Result<(), ChainError> {
+    let links = [
+        (
+            "f1",
+            &chain.f1_tx,
+            chain.funding_outpoint,
+            chain.funding_value_sat,
+        ),
+        (
+            "f2",
+            &chain.f2_tx,
+            OutPoint {
+                txid: chain.f1_tx.compute_txid(),
                 vout: 0,
             },
-            script_sig: ScriptBuf::new(),
-            sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
-            witness: Witness::new(),
-        }],
-        output: vec![TxOut {
-            value: Amount::from_sat(spending_output_value),
-            script_pubkey: receiver_addr.script_pubkey(),
-        }],
-    };
+            chain.f1_tx.output.first().map_or(0, |o| o.value.to_sat()),
+        ),
+        (
+            "spending",
+            &chain.spending_tx,
+            OutPoint {
+                txid: chain.f2_tx.compute_txid(),
+                vout: 0,
+            },
+            chain.f2_tx.output.first().map_or(0, |o| o.value.to_sat()),
+        ),
+    ];
 
-    // Build the witness stack for the P2TR spend
-    let leaf_hash = TapLeafHash::from_script(f2_lock, LeafVersion::TapScript);
+    for (tx_name, tx, expected, input_sat) in links {
+        // The spending tx may carry an OP_RETURN anchor after its payment
+        let anchored = tx_name == "spending"
+            && tx.output.len() == 2
+            && tx.output[1].script_pubkey.is_op_return();
+        if tx.input.len() != 1 || (tx.output.len() != 1 && !anchored) {
+            return Err(ChainError::Shape { tx: tx_name });
+        }
+        let found = tx.input[0].previous_output;
+        if found != expected {
+            return Err(ChainError::WrongOutpoint {
+                tx: tx_name,
+                expected,
+                found,
+            });
+        }
+        let output_sat = tx.output[0].value.to_sat();
+        if output_sat >= input_sat {
+            return Err(ChainError::NonPositiveFee {
+                tx: tx_name,
+                input_sat,
+                output_sat,
+            });
+        }
+    }
+    Ok(())
+}
 
-    let mut cache = SighashCache::new(&mut spending_tx);
+/// Computes the BIP341 script-path sighash of input 0 of `tx`, spending
+/// `leaf` with `TapSighashType::Default`.
+pub fn taproot_leaf_sighash(
+    tx: &Transaction,
+    prevouts: &[TxOut],
+    leaf: &ScriptBuf,
+) -> Result<Message, TxError> {
+    let leaf_hash = TapLeafHash::from_script(leaf, LeafVersion::TapScript);
+    let mut cache = SighashCache::new(tx);
     let sighash = cache
         .taproot_script_spend_signature_hash(
             0,
-            &Prevouts::All(&[f2_tx.output[0].clone()]),
+            &Prevouts::All(prevouts),
             leaf_hash,
             TapSighashType::Default,
         )
-        .unwrap();
+        .map_err(|e| TxError::Sighash(e.to_string()))?;
+    Message::from_digest_slice(&sighash[..])
+        .map_err(|e| TxError::Sighash(e.to_string()))
+}
 
-    let msg = Message::from_digest_slice(&sighash[..])?;
-    Ok((spending_tx, msg))
+/// Verifies every `(message, signature, pubkey)` triple, true only if all of
+/// them are valid.
+///
+/// The secp256k1 version in use exposes no batch verification, so this
+/// checks the signatures one by one and stops at the first invalid one.
+pub fn batch_verify_signatures(
+    items: &[(Message, schnorr::Signature, XOnlyPublicKey)],
+) -> bool {
+    let secp = Secp256k1::verification_only();
+    items.iter().all(|(message, signature, pubkey)| {
+        secp.verify_schnorr(signature, message, pubkey).is_ok()
+    })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::core::find_valid_nonce;
-    use crate::core::flow_id_to_prefix_bytes;
-    use crate::musig2::{generate_keys, simulate_musig2};
-    use crate::utils::inner_from;
-    use Transaction;
-    use bitcoin::Network;
-    use bitcoin::OutPoint;
-    use bitcoin::Txid;
-    use bitcoin::hashes::Hash;
-    use bitcoin::secp256k1::Secp256k1;
-    use bitvm::dry_run_taproot_input;
-    use rstest::*;
-    use std::str::FromStr;
+/// Exports `tx` as an unsigned PSBT spending `leaf` of `spend_info` in
+/// input 0, for external tools to inspect or sign.
+pub fn unsigned_leaf_psbt(
+    tx: &Transaction,
+    prevouts: &[TxOut],
+    leaf: &ScriptBuf,
+    spend_info: &TaprootSpendInfo,
+) -> Result<Psbt, TxError> {
+    let mut psbt = Psbt::from_unsigned_tx(tx.clone())
+        .map_err(|e| TxError::Psbt(e.to_string()))?;
+    let input = psbt
+        .inputs
+        .first_mut()
+        .ok_or_else(|| TxError::Psbt("transaction has no inputs".into()))?;
+    input.witness_utxo = prevouts.first().cloned();
+    input.sighash_type = Some(TapSighashType::Default.into());
+    input.tap_internal_key = Some(spend_info.internal_key());
+    input.tap_merkle_root = spend_info.merkle_root();
+    input.tap_scripts.insert(
+        leaf_control_block(spend_info, leaf)?,
+        (leaf.clone(), LeafVersion::TapScript),
+    );
+    Ok(psbt)
+}
 
-    #[allow(dead_code)]
-    #[derive(Debug, Clone)]
-    struct TxContext {
-        secp: Secp256k1<secp256k1::All>,
-        sk_signers:
-            [(musig2::secp256k1::SecretKey, musig2::secp256k1::PublicKey); 2],
-        network: Network,
-        funding_outpoint: OutPoint,
-        funding_value_sat: u64,
-        fee_rate: u64,
-        l: usize,
-        b: usize,
-        x: u32,
-        nonce: u64,
-        flow_id_prefix: Vec<u8>,
-        receiver_addr: Address,
-    }
+/// The signature, leaf and control block of input 0 of a signed PSBT from
+/// [`unsigned_leaf_psbt`].
+fn signed_leaf_of_psbt(
+    psbt: &Psbt,
+) -> Result<(Vec<u8>, ScriptBuf, ControlBlock), TxError> {
+    let input = psbt
+        .inputs
+        .first()
+        .ok_or_else(|| TxError::Psbt("PSBT has no inputs".into()))?;
+    let leaves = input.tap_scripts.iter().collect::<Vec<_>>();
+    let [(control_block, (leaf, version))] = leaves[..] else {
+        return Err(TxError::Psbt(
+            "input 0 must carry exactly one tapscript leaf".into(),
+        ));
+    };
+    let leaf_hash = TapLeafHash::from_script(leaf, *version);
+    let sig = input
+        .tap_script_sigs
+        .iter()
+        .find(|((_, hash), _)| *hash == leaf_hash)
+        .map(|(_, sig)| sig.to_vec())
+        .ok_or(TxError::MissingSignatures)?;
+    Ok((sig, leaf.clone(), control_block.clone()))
+}
+
+/// Extracts tx_f1 from its PSBT once the funding leaf signature is in, with
+/// the same witness as [`finalize_f1_tx`].
+pub fn finalize_f1_psbt(psbt: &Psbt) -> Result<Transaction, TxError> {
+    let (sig, funding_script, control_block) = signed_leaf_of_psbt(psbt)?;
+    let mut tx = psbt.unsigned_tx.clone();
+    tx.input[0].witness = Witness::from_slice(&[
+        sig,
+        funding_script.to_bytes(),
+        control_block.serialize(),
+    ]);
+    Ok(tx)
+}
+
+/// Extracts a transaction spending a lock from its PSBT once the leaf
+/// signature is in, with the same witness as [`finalize_lock_tx`].
+///
+/// The message limbs are no PSBT field, so `x` and `nonce` are passed here.
+pub fn finalize_lock_psbt(
+    psbt: &Psbt,
+    x: &u32,
+    nonce: &u64,
+) -> Result<Transaction, TxError> {
+    let (sig, lock, control_block) = signed_leaf_of_psbt(psbt)?;
+    let mut tx = psbt.unsigned_tx.clone();
+    tx.input[0].witness = lock_witness(x, nonce, &sig, &lock, &control_block);
+    Ok(tx)
+}
+
+/// Signs every tapscript leaf of `psbt` that checks the MuSig2 aggregate key
+/// of `keys` first (the funding script and every step lock), filling in the
+/// aggregated signature.
+///
+/// Leaves that start with any other key are left alone. Every input needs
+/// its `witness_utxo` for the sighash.
+pub fn sign_psbt_with_musig2(
+    psbt: &mut Psbt,
+    keys: &[(musig2::secp256k1::SecretKey, musig2::secp256k1::PublicKey)],
+) -> Result<(), TxError> {
+    let pubkeys = keys.iter().map(|key| key.1).collect::<Vec<_>>();
+    let (_, agg_pubkey) = aggregate_pubkeys(&pubkeys)
+        .map_err(|e| TxError::Signing(e.to_string()))?;
+    let xonly = XOnlyPublicKey::from(pubkey_inner_from(agg_pubkey));
+
+    let prevouts = psbt
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            input.witness_utxo.clone().ok_or_else(|| {
+                TxError::Psbt(format!("input {i} is missing its witness_utxo"))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    let mut signatures = Vec::new();
+    for (i, input) in psbt.inputs.iter().enumerate() {
+        for (leaf, version) in input.tap_scripts.values() {
+            let first = leaf.instructions().next();
+            let checks_signer = matches!(
+                first,
+                Some(Ok(Instruction::PushBytes(key)))
+                    if key.as_bytes() == xonly.serialize()
+            );
+            if !checks_signer {
+                continue;
+            }
+            let leaf_hash = TapLeafHash::from_script(leaf, *version);
+            let sighash = cache
+                .taproot_script_spend_signature_hash(
+                    i,
+                    &Prevouts::All(&prevouts),
+                    leaf_hash,
+                    TapSighashType::Default,
+                )
+                .map_err(|e| TxError::Sighash(e.to_string()))?;
+            let message = Message::from_digest_slice(&sighash[..])
+                .map_err(|e| TxError::Sighash(e.to_string()))?;
+            let signature = simulate_musig2(keys, &message)
+                .map_err(|e| TxError::Signing(e.to_string()))?;
+            let signature =
+                schnorr::Signature::from_slice(&signature.serialize())
+                    .map_err(|e| TxError::Signing(e.to_string()))?;
+            signatures.push((i, leaf_hash, signature));
+        }
+    }
+
+    for (i, leaf_hash, signature) in signatures {
+        psbt.inputs[i].tap_script_sigs.insert(
+            (xonly, leaf_hash),
+            bitcoin::taproot::Signature {
+                signature,
+                sighash_type: TapSighashType::Default,
+            },
+        );
+    }
+    Ok(())
+}
+
+/// A [`PresignedStep`] whose template is known to match its signatures.
+///
+/// The wrapped step can only be read, never mutated, so a sealed step keeps
+/// the presigning contract: the template is exactly what was signed.
+#[derive(Debug, Clone)]
+pub struct SealedStep(PresignedStep);
+
+impl SealedStep {
+    /// Recomputes the sighash of `step.tx_template` and checks it against
+    /// the stored message and every stored signature.
+    pub fn new(
+        secp: &Secp256k1<secp256k1::All>,
+        step: PresignedStep,
+    ) -> Result<Self, TxError> {
+        let sighash = taproot_leaf_sighash(
+            &step.tx_template,
+            &step.prevouts,
+            &step.spent_leaf,
+        )?;
+        if sighash != step.sighash_message {
+            return Err(TxError::TemplateMismatch {
+                expected: step.sighash_message,
+                found: sighash,
+            });
+        }
+        if step.signatures.is_empty() {
+            return Err(TxError::MissingSignatures);
+        }
+        for (pubkey, signature) in &step.signatures {
+            let invalid = |reason: String| TxError::InvalidSignature {
+                signer: hex::encode(pubkey),
+                reason,
+            };
+            let xonly = XOnlyPublicKey::from_slice(pubkey)
+                .map_err(|e| invalid(e.to_string()))?;
+            secp.verify_schnorr(signature, &sighash, &xonly)
+                .map_err(|e| invalid(e.to_string()))?;
+        }
+        Ok(Self(step))
+    }
+
+    pub fn tx_template(&self) -> &Transaction {
+        &self.0.tx_template
+    }
+
+    pub fn prevouts(&self) -> &[TxOut] {
+        &self.0.prevouts
+    }
+
+    pub fn spent_leaf(&self) -> &ScriptBuf {
+        &self.0.spent_leaf
+    }
+
+    pub fn sighash_message(&self) -> &Message {
+        &self.0.sighash_message
+    }
+
+    pub fn signatures(&self) -> &HashMap<Vec<u8>, schnorr::Signature> {
+        &self.0.signatures
+    }
+
+    pub fn into_inner(self) -> PresignedStep {
+        self.0
+    }
+}
+
+/// A spend of a [`DemoChain`] the node would reject, found by
+/// [`validate_flow_locally`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// `tx` has no input or its input carries no witness
+    MissingWitness { tx: &'static str },
+    /// The witness of `tx` does not satisfy the lock it spends
+    ScriptFailed { tx: &'static str, reason: String },
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::MissingWitness { tx } => {
+                write!(f, "{tx} has no witness to validate")
+            }
+            ValidationError::ScriptFailed { tx, reason } => {
+                write!(f, "{tx} does not satisfy the lock it spends: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// The F1 and F2 spends of `chain`: input 0 of f2 and of the spending tx,
+/// with the transaction whose outputs they spend.
+fn flow_spends(
+    chain: &DemoChain,
+) -> [(&'static str, &Transaction, &Transaction); 2] {
+    [
+        ("f2", &chain.f2_tx, &chain.f1_tx),
+        ("spending", &chain.spending_tx, &chain.f2_tx),
+    ]
+}
+
+/// Runs input 0 of `tx` against the outputs of `prev_tx`, signature checks
+/// included. `tx_name` labels the errors.
+pub fn run_spend(
+    tx_name: &'static str,
+    tx: &Transaction,
+    prev_tx: &Transaction,
+) -> Result<(), ValidationError> {
+    if tx
+        .input
+        .first()
+        .is_none_or(|input| input.witness.is_empty())
+    {
+        return Err(ValidationError::MissingWitness { tx: tx_name });
+    }
+    let exec = dry_run_taproot_input(tx, 0, &prev_tx.output);
+    if !exec.success {
+        return Err(ValidationError::ScriptFailed {
+            tx: tx_name,
+            reason: format!(
+                "{:?} (last opcode {:?})",
+                exec.error, exec.last_opcode
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Runs the F1 and F2 spends of `chain` one after the other and returns the
+/// result of each, in flow order.
+pub fn run_flow_spends(chain: &DemoChain) -> [Result<(), ValidationError>; 2] {
+    flow_spends(chain)
+        .map(|(tx_name, tx, prev_tx)| run_spend(tx_name, tx, prev_tx))
+}
+
+/// Same as [`run_flow_spends`], with each spend on its own thread.
+///
+/// The two executions are independent, so this takes about as long as the
+/// slower of the two.
+pub fn run_flow_spends_concurrently(
+    chain: &DemoChain,
+) -> [Result<(), ValidationError>; 2] {
+    std::thread::scope(|scope| {
+        flow_spends(chain)
+            .map(|(tx_name, tx, prev_tx)| {
+                scope.spawn(move || run_spend(tx_name, tx, prev_tx))
+            })
+            .map(|handle| handle.join().expect("spend execution panicked"))
+    })
+}
+
+/// Runs the F1 and F2 spends of `chain` (input 0 of f2 and of the spending
+/// tx) locally, signature checks included, as the node will once they are
+/// broadcast. The first failing spend, in flow order, is returned.
+pub fn validate_flow_locally(chain: &DemoChain) -> Result<(), ValidationError> {
+    for result in run_flow_spends_concurrently(chain) {
+        result?;
+    }
+    Ok(())
+}
+
+/// Peak stack depth reached by the F1 locking script, as spent by input 0 of
+/// `tx_f2`.
+pub fn measure_f1_max_stack(tx_f2: &Transaction, tx_f1: &Transaction) -> usize {
+    dry_run_taproot_input(tx_f2, 0, &tx_f1.output)
+        .stats
+        .max_nb_stack_items
+}
+
+/// Peak stack depth reached by the F2 locking script, as spent by input 0 of
+/// `spending_tx`.
+pub fn measure_f2_max_stack(
+    spending_tx: &Transaction,
+    tx_f2: &Transaction,
+) -> usize {
+    dry_run_taproot_input(spending_tx, 0, &tx_f2.output)
+        .stats
+        .max_nb_stack_items
+}
+
+/// Execution stats of the F1 and F2 locking scripts of `chain`, side by
+/// side.
+///
+/// F1 and F2 only differ in their predicate, so their costs should be within
+/// a few opcodes of each other.
+pub fn compare_exec_cost(chain: &DemoChain) -> (ExecStats, ExecStats) {
+    let f1 = dry_run_taproot_input(&chain.f2_tx, 0, &chain.f1_tx.output).stats;
+    let f2 =
+        dry_run_taproot_input(&chain.spending_tx, 0, &chain.f2_tx.output).stats;
+    (f1, f2)
+}
+
+/// Back-of-the-envelope economics of one operator run, see
+/// [`operator_economics`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperatorEconomics {
+    /// Expected hashes to find a valid nonce, `2^(B-L)`
+    pub expected_hashes: u64,
+    /// Expected grinding cost in sat
+    pub grind_cost_sat: f64,
+    /// Fees of f1, f2 and the spending tx at the given fee rate
+    pub fees_sat: u64,
+    /// Reward left after fees, negative if the fees exceed it
+    pub net_reward_sat: i64,
+    /// `net_reward_sat - grind_cost_sat`
+    pub profit_sat: f64,
+    /// Whether `profit_sat` is positive
+    pub profitable: bool,
+}
+
+/// Estimates whether running a flow pays off for the operator: the expected
+/// grinding cost (`2^(B-L)` hashes at `cost_per_hash_sat` each) against
+/// `reward_sat` minus the fees of the three transactions at `fee_rate`.
+pub fn operator_economics(
+    config: &ColliderVmConfig,
+    cost_per_hash_sat: f64,
+    reward_sat: u64,
+    fee_rate: u64,
+) -> OperatorEconomics {
+    let expected_hashes = 1u64
+        .checked_shl(config.b.saturating_sub(config.l) as u32)
+        .unwrap_or(u64::MAX);
+    let grind_cost_sat = expected_hashes as f64 * cost_per_hash_sat;
+    let fees_sat = flow_tx_vbytes(config.b)
+        .into_iter()
+        .map(|vbytes| estimate_fee_vbytes(vbytes, fee_rate))
+        .sum::<u64>();
+    let net_reward_sat = reward_sat as i64 - fees_sat as i64;
+    let profit_sat = net_reward_sat as f64 - grind_cost_sat;
+
+    OperatorEconomics {
+        expected_hashes,
+        grind_cost_sat,
+        fees_sat,
+        net_reward_sat,
+        profit_sat,
+        profitable: profit_sat > 0.0,
+    }
+}
+
+/// Largest OP_RETURN payload relayed by default (`-datacarriersize`)
+pub const MAX_OP_RETURN_PAYLOAD: usize = 80;
+
+/// OP_RETURN payload anchoring a run on-chain: `flow_id || x`, both
+/// little-endian.
+pub fn anchor_payload(flow_id: u32, x: u32) -> Vec<u8> {
+    [flow_id.to_le_bytes(), x.to_le_bytes()].concat()
+}
+
+/// Zero-value `OP_RETURN <payload>` output.
+fn build_anchor_output(payload: &[u8]) -> Result<TxOut, TxError> {
+    let too_large = TxError::AnchorTooLarge { len: payload.len() };
+    if payload.len() > MAX_OP_RETURN_PAYLOAD {
+        return Err(too_large);
+    }
+    let payload =
+        PushBytesBuf::try_from(payload.to_vec()).map_err(|_| too_large)?;
+    Ok(TxOut {
+        value: Amount::ZERO,
+        script_pubkey: ScriptBuf::new_op_return(payload),
+    })
+}
+
+/// Creates and signs the spending transaction, spending the F2 output to the receiver.
+///
+/// With `anchor`, a zero-value OP_RETURN output carrying it is appended after
+/// the payment output.
+///
+/// With `funder_change` set to `(funder_addr, reward_sat)`, the receiver
+/// only gets `reward_sat` and the funder gets back the rest minus the fee,
+/// in an output placed right after the payment.
+///
+/// `enable_rbf` is as for [`create_f1_tx`].
+#[allow(clippy::too_many_arguments)]
+pub fn create_spending_tx(
+    f2_tx: &Transaction,
+    f2_output_value: &u64,
+    receiver_addr: &Address,
+    f2_lock: &ScriptBuf,
+    fee_rate: &u64,
+    anchor: Option<&[u8]>,
+    funder_change: Option<(&Address, u64)>,
+    enable_rbf: bool,
+) -> Result<(Transaction, Message), TxError> {
+    let anchor_output = anchor.map(build_anchor_output).transpose()?;
+    let receiver_script = receiver_addr.script_pubkey();
+
+    // Size the fee with a placeholder of the F2 lock witness and the final
+    // outputs, whose values do not change the size
+    let mut spending_tx = Transaction {
+        version: Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: f2_tx.compute_txid(),
+                vout: 0,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: input_sequence(enable_rbf),
+            witness: lock_spend_placeholder_witness(f2_lock, LIMB_LEN),
+        }],
+        output: std::iter::once(&receiver_script)
+            .chain(funder_change.map(|(addr, _)| addr.script_pubkey()).as_ref())
+            .map(|script_pubkey| TxOut {
+                value: Amount::ZERO,
+                script_pubkey: script_pubkey.clone(),
+            })
+            .chain(anchor_output.clone())
+            .collect(),
+    };
+    let fee_spending_tx = estimate_tx_fee(&spending_tx, *fee_rate).to_sat();
+
+    let mut outputs = Vec::new();
+    match funder_change {
+        Some((funder_addr, reward_sat)) => {
+            let dust_limit_sat = receiver_script.minimal_non_dust().to_sat();
+            if reward_sat < dust_limit_sat {
+                return Err(TxError::Dust {
+                    tx: "spending",
+                    value_sat: reward_sat,
+                    dust_limit_sat,
+                });
+            }
+            // The reward comes out of the F2 output before the change, like
+            // an extra fee
+            let change_sat = output_value_after_fee(
+                "spending change",
+                *f2_output_value,
+                fee_spending_tx.saturating_add(reward_sat),
+                &funder_addr.script_pubkey(),
+            )?;
+            outputs.push(TxOut {
+                value: Amount::from_sat(reward_sat),
+                script_pubkey: receiver_script,
+            });
+            outputs.push(TxOut {
+                value: Amount::from_sat(change_sat),
+                script_pubkey: funder_addr.script_pubkey(),
+            });
+        }
+        None => {
+            let spending_output_value = output_value_after_fee(
+                "spending",
+                *f2_output_value,
+                fee_spending_tx,
+                &receiver_script,
+            )?;
+            outputs.push(TxOut {
+                value: Amount::from_sat(spending_output_value),
+                script_pubkey: receiver_script,
+            });
+        }
+    }
+    outputs.extend(anchor_output);
+    spending_tx.output = outputs;
+    spending_tx.input[0].witness = Witness::new();
+
+    let msg = taproot_leaf_sighash(&spending_tx, &f2_tx.output[..1], f2_lock)?;
+    Ok((spending_tx, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        BBits, LBits, PresignedFlow, build_script_f1_multisig_locked,
+        calculate_flow_id, find_valid_nonce, truncate_flow_id,
+    };
+    use crate::musig2::{
+        generate_keys, generate_keys_n_from_seed, simulate_musig2,
+        simulate_musig2_seeded,
+    };
+    use crate::output::{load_presigned_flows, save_presigned_flows};
+    use crate::utils::{pubkey_inner_from, write_psbt_to_file};
+    use Transaction;
+    use bitcoin::Network;
+    use bitcoin::OutPoint;
+    use bitcoin::Txid;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::TapTweak;
+    use bitcoin::secp256k1::{Keypair, Secp256k1};
+    use rstest::*;
+    use std::str::FromStr;
+
+    #[allow(dead_code)]
+    #[derive(Debug, Clone)]
+    struct TxContext {
+        secp: Secp256k1<secp256k1::All>,
+        sk_signers:
+            [(musig2::secp256k1::SecretKey, musig2::secp256k1::PublicKey); 2],
+        network: Network,
+        funding_outpoint: OutPoint,
+        funding_value_sat: u64,
+        fee_rate: u64,
+        l: usize,
+        b: usize,
+        x: u32,
+        nonce: u64,
+        flow_id_prefix: Vec<u8>,
+        receiver_addr: Address,
+    }
+
+    impl TxContext {
+        /// MuSig2 aggregate of the `sk_signers` keys, which the test locks
+        /// are built for
+        fn pk_signer(&self) -> PublicKey {
+            let pubkeys = self.sk_signers.map(|key| key.1);
+            pubkey_inner_from(aggregate_pubkeys(&pubkeys).unwrap().1)
+        }
+    }
 
     #[fixture]
     #[once]
@@ -337,7 +1789,7 @@ mod tests {
         const B: usize = 16;
         let x = 123;
 
-        let (nonce, flow_id) = find_valid_nonce(x, B, L).unwrap();
+        let (nonce, flow_id) = find_valid_nonce(x, BBits(B), LBits(L)).unwrap();
         let flow_id_prefix = flow_id_to_prefix_bytes(flow_id, B);
         let receiver_addr =
             Address::from_str("bcrt1qz3fps2lxvrp5rqj8ucsqrzjx2c3md9gawqr3l6")
@@ -382,10 +1834,7 @@ mod tests {
             ..
         } = tx_context;
 
-        let pk_signers = sk_signers.iter().map(|key| key.1).collect::<Vec<_>>();
-        let agg_ctx = musig2::KeyAggContext::new(pk_signers).unwrap();
-        let pk_signer: musig2::secp256k1::PublicKey =
-            agg_ctx.aggregated_pubkey();
+        let pk_signer = tx_context.pk_signer();
 
         let (
             mut tx,
@@ -397,22 +1846,33 @@ mod tests {
         ) = create_f1_tx(
             *b,
             secp,
-            &inner_from(pk_signer),
+            &pk_signer,
             network,
             funding_outpoint,
             funding_value_sat,
             flow_id_prefix,
+            F1_THRESHOLD,
             fee_rate,
+            None,
+            false,
         )
         .unwrap();
 
         let final_sig = simulate_musig2(sk_signers, &message).unwrap();
+        let funding_prevout = TxOut {
+            value: Amount::from_sat(*funding_value_sat),
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(
+                funding_spend_info.output_key(),
+            ),
+        };
         finalize_f1_tx(
             &mut tx,
             final_sig,
             &funding_spend_info,
             &funding_script,
-        );
+            &funding_prevout,
+        )
+        .unwrap();
 
         TxFixture {
             tx,
@@ -438,10 +1898,7 @@ mod tests {
             ..
         } = tx_context;
 
-        let pk_signers = sk_signers.iter().map(|key| key.1).collect::<Vec<_>>();
-        let agg_ctx = musig2::KeyAggContext::new(pk_signers).unwrap();
-        let pk_signer: musig2::secp256k1::PublicKey =
-            agg_ctx.aggregated_pubkey();
+        let pk_signer = tx_context.pk_signer();
 
         let TxFixture {
             tx: tx_f1,
@@ -453,18 +1910,28 @@ mod tests {
         let (mut tx, prev_lock, prev_spend_info, message) = create_f2_tx(
             *b,
             secp,
-            &inner_from(pk_signer),
+            &pk_signer,
             network,
             tx_f1,
             &tx_f1.output[0].value.to_sat(),
             f1_lock,
             flow_id_prefix,
+            F2_THRESHOLD,
             fee_rate,
+            false,
         )
         .unwrap();
         let final_sig = simulate_musig2(sk_signers, &message).unwrap();
-        finalize_lock_tx(&mut tx, final_sig, f1_spend_info, f1_lock, x, nonce)
-            .unwrap();
+        finalize_lock_tx(
+            &mut tx,
+            final_sig,
+            f1_spend_info,
+            f1_lock,
+            x,
+            nonce,
+            &tx_f1.output[0],
+        )
+        .unwrap();
 
         TxFixture {
             tx,
@@ -500,14 +1967,158 @@ mod tests {
             receiver_addr,
             f2_lock,
             fee_rate,
+            None,
+            None,
+            false,
         )
         .unwrap();
         let final_sig = simulate_musig2(sk_signers, &message).unwrap();
-        finalize_lock_tx(&mut tx, final_sig, f2_spend_info, f2_lock, x, nonce)
-            .unwrap();
+        finalize_lock_tx(
+            &mut tx,
+            final_sig,
+            f2_spend_info,
+            f2_lock,
+            x,
+            nonce,
+            &tx_f2.output[0],
+        )
+        .unwrap();
         tx
     }
 
+    /// The [`DemoChain`] of `tx_context`'s own input and nonce.
+    fn demo_chain(tx_context: &TxContext) -> DemoChain {
+        let (f1_tx, f2_tx, spending_tx) =
+            build_chain_for_input(tx_context, tx_context.x);
+        DemoChain {
+            funding_outpoint: tx_context.funding_outpoint,
+            funding_value_sat: tx_context.funding_value_sat,
+            x: tx_context.x,
+            nonce: tx_context.nonce,
+            flow_id: truncate_flow_id(
+                calculate_flow_id(tx_context.x, tx_context.nonce),
+                tx_context.b,
+            ),
+            f1_tx,
+            f2_tx,
+            spending_tx,
+        }
+    }
+
+    /// Builds the whole f1 -> f2 -> spending chain for an arbitrary `x`,
+    /// reusing the keys and parameters of `tx_context`.
+    fn build_chain_for_input(
+        tx_context: &TxContext,
+        x: u32,
+    ) -> (Transaction, Transaction, Transaction) {
+        let TxContext {
+            secp,
+            sk_signers,
+            network,
+            funding_outpoint,
+            funding_value_sat,
+            fee_rate,
+            l,
+            b,
+            receiver_addr,
+            ..
+        } = tx_context;
+
+        let pk_signer = tx_context.pk_signer();
+
+        let (nonce, flow_id) =
+            find_valid_nonce(x, BBits(*b), LBits(*l)).unwrap();
+        let flow_id_prefix = flow_id_to_prefix_bytes(flow_id, *b);
+
+        let (
+            mut tx_f1,
+            f1_lock,
+            f1_spend_info,
+            funding_script,
+            funding_spend_info,
+            message,
+        ) = create_f1_tx(
+            *b,
+            secp,
+            &pk_signer,
+            network,
+            funding_outpoint,
+            funding_value_sat,
+            &flow_id_prefix,
+            F1_THRESHOLD,
+            fee_rate,
+            None,
+            false,
+        )
+        .unwrap();
+        let sig = simulate_musig2(sk_signers, &message).unwrap();
+        let funding_prevout = TxOut {
+            value: Amount::from_sat(*funding_value_sat),
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(
+                funding_spend_info.output_key(),
+            ),
+        };
+        finalize_f1_tx(
+            &mut tx_f1,
+            sig,
+            &funding_spend_info,
+            &funding_script,
+            &funding_prevout,
+        )
+        .unwrap();
+
+        let (mut tx_f2, f2_lock, f2_spend_info, message) = create_f2_tx(
+            *b,
+            secp,
+            &pk_signer,
+            network,
+            &tx_f1,
+            &tx_f1.output[0].value.to_sat(),
+            &f1_lock,
+            &flow_id_prefix,
+            F2_THRESHOLD,
+            fee_rate,
+            false,
+        )
+        .unwrap();
+        let sig = simulate_musig2(sk_signers, &message).unwrap();
+        finalize_lock_tx(
+            &mut tx_f2,
+            sig,
+            &f1_spend_info,
+            &f1_lock,
+            &x,
+            &nonce,
+            &tx_f1.output[0],
+        )
+        .unwrap();
+
+        let (mut spending_tx, message) = create_spending_tx(
+            &tx_f2,
+            &tx_f2.output[0].value.to_sat(),
+            receiver_addr,
+            &f2_lock,
+            fee_rate,
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        let sig = simulate_musig2(sk_signers, &message).unwrap();
+        finalize_lock_tx(
+            &mut spending_tx,
+            sig,
+            &f2_spend_info,
+            &f2_lock,
+            &x,
+            &nonce,
+            &tx_f2.output[0],
+        )
+        .unwrap();
+
+        (tx_f1, tx_f2, spending_tx)
+    }
+
     #[rstest]
     fn test_e2e_valid_input(
         f1_tx_fixture: TxFixture,
@@ -566,6 +2177,9 @@ mod tests {
             receiver_addr,
             &f2_lock,
             fee_rate,
+            None,
+            None,
+            false,
         )?;
         let final_sig = simulate_musig2(sk_signers, &message).unwrap();
         // invalid input value: x+1
@@ -576,6 +2190,7 @@ mod tests {
             &f2_lock,
             &(x + 1),
             nonce,
+            &tx_f2.output[0],
         )
         .unwrap();
 
@@ -597,4 +2212,1489 @@ mod tests {
         );
         Ok(())
     }
+
+    #[rstest]
+    fn test_f1_f2_stack_headroom(
+        f1_tx_fixture: TxFixture,
+        f2_tx_fixture: TxFixture,
+        spending_tx: Transaction,
+    ) {
+        let TxFixture { tx: tx_f1, .. } = f1_tx_fixture;
+        let TxFixture { tx: tx_f2, .. } = f2_tx_fixture;
+
+        let f1_peak = measure_f1_max_stack(&tx_f2, &tx_f1);
+        let f2_peak = measure_f2_max_stack(&spending_tx, &tx_f2);
+        println!("F1 peak stack depth: {f1_peak} / 1000");
+        println!("F2 peak stack depth: {f2_peak} / 1000");
+        assert!(f1_peak < 1000);
+        assert!(f2_peak < 1000);
+    }
+
+    #[rstest]
+    fn test_flow_set_addresses(
+        tx_context: &TxContext,
+        f1_tx_fixture: TxFixture,
+    ) {
+        let config = ColliderVmConfig {
+            n: 2,
+            m: 1,
+            l: tx_context.l,
+            b: tx_context.b,
+            k: 2,
+        };
+        let pk_signer = tx_context.pk_signer();
+
+        let addresses =
+            flow_set_addresses(&config, &pk_signer, tx_context.network)
+                .unwrap();
+        assert_eq!(addresses.len(), 1 << tx_context.l);
+        let distinct = addresses
+            .iter()
+            .map(|(_, address)| address.to_string())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(distinct.len(), addresses.len());
+
+        // The ground flow's address is the one its F1 tx pays to
+        let (_, address) = addresses
+            .iter()
+            .find(|(flow_id, _)| {
+                flow_id_to_prefix_bytes(*flow_id, tx_context.b)
+                    == tx_context.flow_id_prefix
+            })
+            .unwrap();
+        assert_eq!(
+            address.script_pubkey(),
+            f1_tx_fixture.tx.output[0].script_pubkey
+        );
+    }
+
+    #[rstest]
+    fn test_build_flow_steps(tx_context: &TxContext) {
+        let TxContext {
+            secp,
+            sk_signers,
+            network,
+            funding_outpoint,
+            funding_value_sat,
+            fee_rate,
+            b,
+            x,
+            nonce,
+            flow_id_prefix,
+            receiver_addr,
+            ..
+        } = tx_context;
+        let pk_signer = tx_context.pk_signer();
+        // x = 123 passes all three
+        let predicates = [
+            ScriptPredicate::F1,
+            ScriptPredicate::F2,
+            ScriptPredicate::InRange(120, 130),
+        ];
+
+        let steps = build_flow_steps(
+            secp,
+            *b,
+            &predicates,
+            &pk_signer,
+            flow_id_prefix,
+            *network,
+            *funding_outpoint,
+            *funding_value_sat * 2,
+            &receiver_addr.script_pubkey(),
+            *fee_rate,
+            true,
+        )
+        .unwrap();
+        assert_eq!(steps.len(), predicates.len());
+        assert!(
+            steps
+                .iter()
+                .all(|step| step.tx_template.is_explicitly_rbf())
+        );
+
+        for (i, (step, predicate)) in steps.iter().zip(&predicates).enumerate()
+        {
+            assert_eq!(
+                step.spent_leaf,
+                build_script_predicate_locked(
+                    &bitcoin::PublicKey::new(pk_signer),
+                    flow_id_prefix,
+                    *b,
+                    predicate,
+                )
+            );
+            match i.checked_sub(1).map(|prev| &steps[prev]) {
+                Some(prev) => {
+                    assert_eq!(
+                        step.tx_template.input[0].previous_output.txid,
+                        prev.tx_template.compute_txid()
+                    );
+                    assert_eq!(step.prevouts, prev.tx_template.output);
+                }
+                None => assert_eq!(
+                    step.tx_template.input[0].previous_output,
+                    *funding_outpoint
+                ),
+            }
+
+            // Each step's script runs on its own once signed
+            let (_, spend_info) = wrap_taproot_leaf(
+                secp,
+                &step.spent_leaf,
+                &XOnlyPublicKey::from(pk_signer),
+                *network,
+            )
+            .unwrap();
+            let sig =
+                simulate_musig2(sk_signers, &step.sighash_message).unwrap();
+            let mut tx = step.tx_template.clone();
+            finalize_lock_tx(
+                &mut tx,
+                sig,
+                &spend_info,
+                &step.spent_leaf,
+                x,
+                nonce,
+                &step.prevouts[0],
+            )
+            .unwrap();
+            let exec = dry_run_taproot_input(&tx, 0, &step.prevouts);
+            assert!(exec.success, "step {i} failed: {:?}", exec.last_opcode);
+        }
+        assert_eq!(
+            steps[2].tx_template.output[0].script_pubkey,
+            receiver_addr.script_pubkey()
+        );
+
+        // No x passes both x > 200 and x < 100
+        let contradictory = [
+            ScriptPredicate::GreaterThan(200),
+            ScriptPredicate::LessThan(100),
+        ];
+        let err = build_flow_steps(
+            secp,
+            *b,
+            &contradictory,
+            &pk_signer,
+            flow_id_prefix,
+            *network,
+            *funding_outpoint,
+            *funding_value_sat,
+            &receiver_addr.script_pubkey(),
+            *fee_rate,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            TxError::Config(CoreError::InfeasiblePredicates(
+                contradictory.to_vec()
+            ))
+        );
+    }
+
+    #[rstest]
+    fn test_multisig_lock(tx_context: &TxContext) {
+        let TxContext {
+            secp,
+            funding_outpoint,
+            x,
+            nonce,
+            flow_id_prefix,
+            receiver_addr,
+            ..
+        } = tx_context;
+        let keypairs = (0..3)
+            .map(|_| Keypair::new(secp, &mut rand::thread_rng()))
+            .collect::<Vec<_>>();
+        let pubkeys = keypairs
+            .iter()
+            .map(|keypair| bitcoin::PublicKey::new(keypair.public_key()))
+            .collect::<Vec<_>>();
+        let lock = build_script_f1_multisig_locked(&pubkeys, 2, flow_id_prefix)
+            .unwrap();
+        let (address, spend_info) = wrap_taproot_leaf(
+            secp,
+            &lock,
+            &nums_internal_key(),
+            Network::Regtest,
+        )
+        .unwrap();
+        let prevouts = [TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: address.script_pubkey(),
+        }];
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: *funding_outpoint,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: receiver_addr.script_pubkey(),
+            }],
+        };
+        let message = taproot_leaf_sighash(&tx, &prevouts, &lock).unwrap();
+        let sign = |keypair: &Keypair| {
+            Some(secp.sign_schnorr_no_aux_rand(&message, keypair))
+        };
+        let spend = |sigs: &[Option<schnorr::Signature>]| {
+            let mut tx = tx.clone();
+            finalize_multisig_lock_tx(
+                &mut tx,
+                sigs,
+                &spend_info,
+                &lock,
+                x,
+                nonce,
+            )
+            .unwrap();
+            dry_run_taproot_input(&tx, 0, &prevouts).success
+        };
+
+        // 2-of-3, whichever two sign
+        assert!(spend(&[sign(&keypairs[0]), None, sign(&keypairs[2])]));
+        assert!(spend(&[sign(&keypairs[0]), sign(&keypairs[1]), None]));
+        assert!(spend(&[
+            sign(&keypairs[0]),
+            sign(&keypairs[1]),
+            sign(&keypairs[2])
+        ]));
+        // One signature is below the threshold
+        assert!(!spend(&[None, sign(&keypairs[1]), None]));
+        // A signature in the wrong slot is invalid, not just uncounted
+        assert!(!spend(&[sign(&keypairs[1]), None, sign(&keypairs[2])]));
+
+        // One slot per key, no more and no less
+        let mut short = tx.clone();
+        assert_eq!(
+            finalize_multisig_lock_tx(
+                &mut short,
+                &[sign(&keypairs[0]), sign(&keypairs[1])],
+                &spend_info,
+                &lock,
+                x,
+                nonce,
+            ),
+            Err(TxError::SignatureCount {
+                expected: 3,
+                found: 2
+            })
+        );
+        assert_eq!(
+            build_script_f1_multisig_locked(&pubkeys, 4, flow_id_prefix),
+            Err(CoreError::ThresholdOutOfRange {
+                threshold: 4,
+                signers: 3
+            })
+        );
+    }
+
+    #[rstest]
+    fn test_presigned_flows_round_trip(tx_context: &TxContext) {
+        let TxContext {
+            secp,
+            sk_signers,
+            network,
+            funding_outpoint,
+            funding_value_sat,
+            fee_rate,
+            b,
+            flow_id_prefix,
+            receiver_addr,
+            ..
+        } = tx_context;
+        let pk_signer = tx_context.pk_signer();
+
+        let mut steps = build_flow_steps(
+            secp,
+            *b,
+            &[ScriptPredicate::F1, ScriptPredicate::F2],
+            &pk_signer,
+            flow_id_prefix,
+            *network,
+            *funding_outpoint,
+            *funding_value_sat,
+            &receiver_addr.script_pubkey(),
+            *fee_rate,
+            false,
+        )
+        .unwrap();
+        for step in &mut steps {
+            let sig =
+                simulate_musig2(sk_signers, &step.sighash_message).unwrap();
+            step.signatures.insert(
+                XOnlyPublicKey::from(pk_signer).serialize().to_vec(),
+                schnorr::Signature::from_slice(&sig.serialize()).unwrap(),
+            );
+        }
+        let flows = vec![PresignedFlow { flow_id: 3, steps }];
+
+        let path = std::env::temp_dir()
+            .join(format!("collidervm_presigned_{}.json", std::process::id()));
+        save_presigned_flows(&flows, &path).unwrap();
+        assert_eq!(load_presigned_flows(&path).unwrap(), flows);
+
+        // Signatures are hex in the file, keyed by their signer
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap())
+                .unwrap();
+        let signatures = json[0]["steps"][0]["signatures"].as_object().unwrap();
+        assert_eq!(signatures.len(), 1);
+        assert!(signatures.contains_key(&hex::encode(
+            XOnlyPublicKey::from(pk_signer).serialize()
+        )));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[rstest]
+    fn test_validate_chain_links(tx_context: &TxContext) {
+        let chain = demo_chain(tx_context);
+        assert_eq!(validate_chain_links(&chain), Ok(()));
+
+        // spending tx pointing at f1 instead of f2
+        let mut broken = chain.clone();
+        broken.spending_tx.input[0].previous_output.txid =
+            chain.f1_tx.compute_txid();
+        assert!(matches!(
+            validate_chain_links(&broken),
+            Err(ChainError::WrongOutpoint { tx: "spending", .. })
+        ));
+
+        // f2 paying out more than f1 provides
+        let mut broken = chain.clone();
+        broken.f2_tx.output[0].value = chain.f1_tx.output[0].value;
+        assert!(matches!(
+            validate_chain_links(&broken),
+            Err(ChainError::NonPositiveFee { tx: "f2", .. })
+        ));
+    }
+
+    /// `chain` with f2 witnessing a nonce that selects another flow than the
+    /// one F1 is locked to. The spending tx is left valid.
+    fn with_wrong_f2_nonce(chain: &DemoChain, b_bits: usize) -> DemoChain {
+        let flow_of =
+            |nonce| truncate_flow_id(calculate_flow_id(chain.x, nonce), b_bits);
+        let wrong_nonce = (chain.nonce + 1..)
+            .find(|&nonce| flow_of(nonce) != flow_of(chain.nonce))
+            .unwrap();
+        let mut broken = chain.clone();
+        let witness = broken.f2_tx.input[0].witness.to_vec();
+        let limbs = message_to_witness_limbs(chain.x, wrong_nonce);
+        let rest = witness[limbs.len()..].to_vec();
+        broken.f2_tx.input[0].witness =
+            Witness::from_slice(&[limbs, rest].concat());
+        broken
+    }
+
+    #[rstest]
+    fn test_validate_flow_locally(tx_context: &TxContext) {
+        let chain = demo_chain(tx_context);
+        assert_eq!(validate_flow_locally(&chain), Ok(()));
+
+        let broken = with_wrong_f2_nonce(&chain, tx_context.b);
+        assert!(matches!(
+            validate_flow_locally(&broken),
+            Err(ValidationError::ScriptFailed { tx: "f2", .. })
+        ));
+
+        let mut unsigned = chain.clone();
+        unsigned.spending_tx.input[0].witness = Witness::new();
+        assert_eq!(
+            validate_flow_locally(&unsigned),
+            Err(ValidationError::MissingWitness { tx: "spending" })
+        );
+    }
+
+    #[rstest]
+    fn test_concurrent_flow_spends_match_serial(tx_context: &TxContext) {
+        let chain = demo_chain(tx_context);
+        let mixed = with_wrong_f2_nonce(&chain, tx_context.b);
+
+        for chain in [&chain, &mixed] {
+            assert_eq!(
+                run_flow_spends_concurrently(chain),
+                run_flow_spends(chain)
+            );
+        }
+        let [f2, spending] = run_flow_spends_concurrently(&mixed);
+        assert!(matches!(f2, Err(ValidationError::ScriptFailed { .. })));
+        assert_eq!(spending, Ok(()));
+    }
+
+    #[rstest]
+    fn test_compare_exec_cost(tx_context: &TxContext) {
+        let chain = demo_chain(tx_context);
+
+        let (f1, f2) = compare_exec_cost(&chain);
+        println!("F1 => exec_stats={f1:?}");
+        println!("F2 => exec_stats={f2:?}");
+        assert!(f1.opcode_count > 0);
+        // Only the comparison opcode and its threshold differ
+        assert!(
+            f1.opcode_count.abs_diff(f2.opcode_count) <= 2,
+            "F1 ran {} opcodes, F2 ran {}",
+            f1.opcode_count,
+            f2.opcode_count
+        );
+    }
+
+    #[rstest]
+    fn test_spending_tx_anchor(
+        tx_context: &TxContext,
+        f2_tx_fixture: TxFixture,
+    ) {
+        let TxFixture {
+            tx: tx_f2,
+            prev_lock: f2_lock,
+            ..
+        } = f2_tx_fixture;
+        let flow_id = 0x0d;
+        let payload = anchor_payload(flow_id, tx_context.x);
+
+        let (spending_tx, _) = create_spending_tx(
+            &tx_f2,
+            &tx_f2.output[0].value.to_sat(),
+            &tx_context.receiver_addr,
+            &f2_lock,
+            &tx_context.fee_rate,
+            Some(&payload),
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(spending_tx.output.len(), 2);
+        let anchor = &spending_tx.output[1];
+        assert!(anchor.script_pubkey.is_op_return());
+        assert_eq!(anchor.value, Amount::ZERO);
+        let pushed =
+            anchor.script_pubkey.instructions().nth(1).unwrap().unwrap();
+        let pushed = pushed.push_bytes().unwrap().as_bytes();
+        assert_eq!(&pushed[..4], &flow_id.to_le_bytes());
+        assert_eq!(&pushed[4..], &tx_context.x.to_le_bytes());
+
+        // Oversized payloads are not relayed
+        assert!(
+            create_spending_tx(
+                &tx_f2,
+                &tx_f2.output[0].value.to_sat(),
+                &tx_context.receiver_addr,
+                &f2_lock,
+                &tx_context.fee_rate,
+                Some(&[0u8; MAX_OP_RETURN_PAYLOAD + 1]),
+                None,
+                false,
+            )
+            .is_err()
+        );
+    }
+
+    #[rstest]
+    fn test_spending_tx_funder_change(
+        tx_context: &TxContext,
+        f2_tx_fixture: TxFixture,
+    ) {
+        let TxFixture {
+            tx: tx_f2,
+            prev_lock: f2_lock,
+            prev_spend_info: f2_spend_info,
+        } = f2_tx_fixture;
+        let (_, pk) = tx_context.secp.generate_keypair(&mut rand::thread_rng());
+        let funder_addr = Address::p2tr(
+            &tx_context.secp,
+            pk.x_only_public_key().0,
+            None,
+            tx_context.network,
+        );
+        let input = tx_f2.output[0].value.to_sat();
+        let reward = 10_000;
+
+        let (mut spending_tx, message) = create_spending_tx(
+            &tx_f2,
+            &input,
+            &tx_context.receiver_addr,
+            &f2_lock,
+            &tx_context.fee_rate,
+            None,
+            Some((&funder_addr, reward)),
+            false,
+        )
+        .unwrap();
+        let mut sized = spending_tx.clone();
+        sized.input[0].witness =
+            lock_spend_placeholder_witness(&f2_lock, LIMB_LEN);
+        let fee = estimate_tx_fee(&sized, tx_context.fee_rate).to_sat();
+        assert_eq!(spending_tx.output.len(), 2);
+        assert_eq!(spending_tx.output[0].value.to_sat(), reward);
+        assert_eq!(
+            spending_tx.output[0].script_pubkey,
+            tx_context.receiver_addr.script_pubkey()
+        );
+        assert_eq!(spending_tx.output[1].value.to_sat(), input - reward - fee);
+        assert_eq!(
+            spending_tx.output[1].script_pubkey,
+            funder_addr.script_pubkey()
+        );
+
+        // The F2 lock still accepts the split spend
+        let sig = simulate_musig2(&tx_context.sk_signers, &message).unwrap();
+        finalize_lock_tx(
+            &mut spending_tx,
+            sig,
+            &f2_spend_info,
+            &f2_lock,
+            &tx_context.x,
+            &tx_context.nonce,
+            &tx_f2.output[0],
+        )
+        .unwrap();
+        assert!(dry_run_taproot_input(&spending_tx, 0, &tx_f2.output).success);
+
+        // A reward the F2 output cannot cover along with the fee
+        let err = create_spending_tx(
+            &tx_f2,
+            &input,
+            &tx_context.receiver_addr,
+            &f2_lock,
+            &tx_context.fee_rate,
+            None,
+            Some((&funder_addr, input)),
+            false,
+        )
+        .unwrap_err();
+        assert!(matches!(err, TxError::InsufficientValue { .. }));
+    }
+
+    #[rstest]
+    fn test_zero_input(tx_context: &TxContext) {
+        let (tx_f1, tx_f2, spending_tx) = build_chain_for_input(tx_context, 0);
+
+        // F1: 0 > 100 must fail on the predicate's OP_VERIFY, not earlier
+        // while reconstructing x from the all-zero limbs
+        let exec_info_f1 = dry_run_taproot_input(&tx_f2, 0, &tx_f1.output);
+        assert!(!exec_info_f1.success, "F1 accepted x = 0");
+        assert_eq!(
+            exec_info_f1.last_opcode,
+            Some(bitcoin::opcodes::all::OP_VERIFY),
+            "F1 failed outside the predicate check: {:?}",
+            exec_info_f1.error
+        );
+
+        // F2: 0 < 200 must pass
+        let exec_info_f2 =
+            dry_run_taproot_input(&spending_tx, 0, &tx_f2.output);
+        assert!(
+            exec_info_f2.success,
+            "F2 rejected x = 0: {:?}",
+            exec_info_f2.error
+        );
+    }
+
+    #[test]
+    fn test_batch_verify_signatures() {
+        let secp = Secp256k1::new();
+        let items = (0..4u8)
+            .map(|i| {
+                let (sk, _) = secp.generate_keypair(&mut rand::thread_rng());
+                let keypair = secp256k1::Keypair::from_secret_key(&secp, &sk);
+                let message = Message::from_digest([i; 32]);
+                let signature =
+                    secp.sign_schnorr_no_aux_rand(&message, &keypair);
+                (message, signature, keypair.x_only_public_key().0)
+            })
+            .collect::<Vec<_>>();
+        let individually =
+            |items: &[(Message, schnorr::Signature, XOnlyPublicKey)]| {
+                items
+                    .iter()
+                    .all(|(m, s, pk)| secp.verify_schnorr(s, m, pk).is_ok())
+            };
+
+        assert!(batch_verify_signatures(&items));
+        assert!(batch_verify_signatures(&[]));
+
+        // Swap in a signature over another message
+        let mut mixed = items.clone();
+        mixed[2].1 = items[1].1;
+        assert!(!individually(&mixed));
+        assert_eq!(batch_verify_signatures(&mixed), individually(&mixed));
+
+        // Or a signature checked against the wrong key
+        let mut mixed = items.clone();
+        mixed[3].2 = items[0].2;
+        assert_eq!(batch_verify_signatures(&mixed), individually(&mixed));
+        assert!(!batch_verify_signatures(&mixed));
+    }
+
+    #[rstest]
+    fn test_sign_psbt_with_musig2(tx_context: &TxContext) {
+        let TxContext {
+            secp,
+            sk_signers,
+            network,
+            funding_outpoint,
+            funding_value_sat,
+            fee_rate,
+            b,
+            flow_id_prefix,
+            ..
+        } = tx_context;
+
+        let pk_signer = tx_context.pk_signer();
+
+        let (tx, _, _, funding_script, funding_spend_info, message) =
+            create_f1_tx(
+                *b,
+                secp,
+                &pk_signer,
+                network,
+                funding_outpoint,
+                funding_value_sat,
+                flow_id_prefix,
+                F1_THRESHOLD,
+                fee_rate,
+                None,
+                false,
+            )
+            .unwrap();
+        let prevouts = [TxOut {
+            value: Amount::from_sat(*funding_value_sat),
+            script_pubkey: Address::p2tr_tweaked(
+                funding_spend_info.output_key(),
+                *network,
+            )
+            .script_pubkey(),
+        }];
+
+        // Export, hand over as bytes, import again
+        let exported = unsigned_leaf_psbt(
+            &tx,
+            &prevouts,
+            &funding_script,
+            &funding_spend_info,
+        )
+        .unwrap();
+        let mut psbt = Psbt::deserialize(&exported.serialize()).unwrap();
+        assert_eq!(psbt, exported);
+
+        // Other signers do not recognize the funding leaf
+        let mut foreign = psbt.clone();
+        sign_psbt_with_musig2(&mut foreign, &generate_keys::<2>()).unwrap();
+        assert!(foreign.inputs[0].tap_script_sigs.is_empty());
+
+        sign_psbt_with_musig2(&mut psbt, sk_signers).unwrap();
+        let sigs = &psbt.inputs[0].tap_script_sigs;
+        assert_eq!(sigs.len(), 1);
+        let ((xonly, leaf_hash), sig) = sigs.iter().next().unwrap();
+        assert_eq!(*xonly, XOnlyPublicKey::from(pk_signer));
+        assert_eq!(*leaf_hash, funding_tapleaf_hash(xonly));
+        secp.verify_schnorr(&sig.signature, &message, xonly)
+            .unwrap();
+    }
+
+    #[rstest]
+    fn test_psbt_round_trip(tx_context: &TxContext, f1_tx_fixture: TxFixture) {
+        let TxContext {
+            secp,
+            sk_signers,
+            network,
+            funding_outpoint,
+            funding_value_sat,
+            fee_rate,
+            b,
+            x,
+            nonce,
+            flow_id_prefix,
+            ..
+        } = tx_context;
+
+        let pk_signer = tx_context.pk_signer();
+        let xonly = XOnlyPublicKey::from(pk_signer);
+        let output_dir = std::env::temp_dir()
+            .join(format!("collidervm_psbt_{}", std::process::id()));
+
+        // Written to disk and read back, as an external signer would get it,
+        // then signed with the same signature as the direct path
+        let sign_externally = |psbt: &Psbt, name: &str, sig: &[u8; 64]| {
+            let path =
+                write_psbt_to_file(psbt, output_dir.to_str().unwrap(), name)
+                    .unwrap();
+            let mut psbt =
+                Psbt::deserialize(&std::fs::read(path).unwrap()).unwrap();
+            let (_, (leaf, version)) =
+                psbt.inputs[0].tap_scripts.iter().next().unwrap();
+            let leaf_hash = TapLeafHash::from_script(leaf, *version);
+            psbt.inputs[0].tap_script_sigs.insert(
+                (xonly, leaf_hash),
+                bitcoin::taproot::Signature {
+                    signature: schnorr::Signature::from_slice(sig).unwrap(),
+                    sighash_type: TapSighashType::Default,
+                },
+            );
+            psbt
+        };
+
+        // tx_f1, spending the funding script
+        let (tx_f1, _, _, funding_script, funding_spend_info, message) =
+            create_f1_tx(
+                *b,
+                secp,
+                &pk_signer,
+                network,
+                funding_outpoint,
+                funding_value_sat,
+                flow_id_prefix,
+                F1_THRESHOLD,
+                fee_rate,
+                None,
+                false,
+            )
+            .unwrap();
+        let funding_prevout = TxOut {
+            value: Amount::from_sat(*funding_value_sat),
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(
+                funding_spend_info.output_key(),
+            ),
+        };
+        let sig = simulate_musig2(sk_signers, &message).unwrap();
+        let sig_bytes = sig.serialize();
+        let mut direct = tx_f1.clone();
+        finalize_f1_tx(
+            &mut direct,
+            sig,
+            &funding_spend_info,
+            &funding_script,
+            &funding_prevout,
+        )
+        .unwrap();
+
+        let psbt = unsigned_leaf_psbt(
+            &tx_f1,
+            &[funding_prevout],
+            &funding_script,
+            &funding_spend_info,
+        )
+        .unwrap();
+        assert_eq!(finalize_f1_psbt(&psbt), Err(TxError::MissingSignatures));
+        let psbt = sign_externally(&psbt, "f1", &sig_bytes);
+        assert_eq!(finalize_f1_psbt(&psbt).unwrap(), direct);
+
+        // tx_f2, spending the F1 lock with x || nonce
+        let TxFixture {
+            tx: tx_f1,
+            prev_lock: f1_lock,
+            prev_spend_info: f1_spend_info,
+        } = &f1_tx_fixture;
+        let (tx_f2, _, _, message) = create_f2_tx(
+            *b,
+            secp,
+            &pk_signer,
+            network,
+            tx_f1,
+            &tx_f1.output[0].value.to_sat(),
+            f1_lock,
+            flow_id_prefix,
+            F2_THRESHOLD,
+            fee_rate,
+            false,
+        )
+        .unwrap();
+        let sig = simulate_musig2(sk_signers, &message).unwrap();
+        let sig_bytes = sig.serialize();
+        let mut direct = tx_f2.clone();
+        finalize_lock_tx(
+            &mut direct,
+            sig,
+            f1_spend_info,
+            f1_lock,
+            x,
+            nonce,
+            &tx_f1.output[0],
+        )
+        .unwrap();
+
+        let psbt = unsigned_leaf_psbt(
+            &tx_f2,
+            &tx_f1.output[..1],
+            f1_lock,
+            f1_spend_info,
+        )
+        .unwrap();
+        let psbt = sign_externally(&psbt, "f2", &sig_bytes);
+        assert_eq!(finalize_lock_psbt(&psbt, x, nonce).unwrap(), direct);
+
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[rstest]
+    fn test_sealed_step(tx_context: &TxContext) {
+        let TxContext {
+            secp,
+            sk_signers,
+            network,
+            funding_outpoint,
+            funding_value_sat,
+            fee_rate,
+            b,
+            flow_id_prefix,
+            ..
+        } = tx_context;
+
+        let pk_signer = tx_context.pk_signer();
+
+        let (tx, _, _, funding_script, funding_spend_info, message) =
+            create_f1_tx(
+                *b,
+                secp,
+                &pk_signer,
+                network,
+                funding_outpoint,
+                funding_value_sat,
+                flow_id_prefix,
+                F1_THRESHOLD,
+                fee_rate,
+                None,
+                false,
+            )
+            .unwrap();
+        let sig = simulate_musig2(sk_signers, &message).unwrap();
+
+        let step = PresignedStep {
+            tx_template: tx,
+            prevouts: vec![TxOut {
+                value: Amount::from_sat(*funding_value_sat),
+                script_pubkey: Address::p2tr_tweaked(
+                    funding_spend_info.output_key(),
+                    *network,
+                )
+                .script_pubkey(),
+            }],
+            spent_leaf: funding_script,
+            sighash_message: message,
+            signatures: HashMap::from([(
+                XOnlyPublicKey::from(pk_signer).serialize().to_vec(),
+                schnorr::Signature::from_slice(&sig.serialize()).unwrap(),
+            )]),
+            limb_len: LIMB_LEN,
+        };
+
+        let sealed = SealedStep::new(secp, step.clone()).unwrap();
+        assert_eq!(sealed.sighash_message(), &message);
+
+        // Tampering with the template after signing must be rejected
+        let mut tampered = step;
+        tampered.tx_template.output[0].value = Amount::from_sat(1);
+        assert!(SealedStep::new(secp, tampered).is_err());
+    }
+
+    #[rstest]
+    fn test_tx_errors(tx_context: &TxContext, f2_tx_fixture: TxFixture) {
+        let TxContext {
+            secp,
+            sk_signers,
+            network,
+            funding_outpoint,
+            fee_rate,
+            b,
+            flow_id_prefix,
+            receiver_addr,
+            ..
+        } = tx_context;
+        let pk_signer = tx_context.pk_signer();
+
+        // Funding too small to pay for f1
+        let err = create_f1_tx(
+            *b,
+            secp,
+            &pk_signer,
+            network,
+            funding_outpoint,
+            &100,
+            flow_id_prefix,
+            F1_THRESHOLD,
+            fee_rate,
+            None,
+            false,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            TxError::InsufficientValue {
+                tx: "f1",
+                available_sat: 100,
+                ..
+            }
+        ));
+
+        // Enough for the fee, but what is left is dust
+        let TxFixture {
+            tx: tx_f2,
+            prev_lock: f2_lock,
+            prev_spend_info: f2_spend_info,
+            ..
+        } = f2_tx_fixture;
+        let (full, _) = create_spending_tx(
+            &tx_f2,
+            &tx_f2.output[0].value.to_sat(),
+            receiver_addr,
+            &f2_lock,
+            fee_rate,
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        let fee = tx_f2.output[0].value - full.output[0].value;
+        let err = create_spending_tx(
+            &tx_f2,
+            &(fee.to_sat() + 1),
+            receiver_addr,
+            &f2_lock,
+            fee_rate,
+            None,
+            None,
+            false,
+        )
+        .unwrap_err();
+        assert!(matches!(err, TxError::Dust { tx: "spending", .. }));
+
+        // Finalizing with a script that is not a leaf of the tree
+        let (mut spending_tx, message) = create_spending_tx(
+            &tx_f2,
+            &tx_f2.output[0].value.to_sat(),
+            receiver_addr,
+            &f2_lock,
+            fee_rate,
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        let sig = simulate_musig2(sk_signers, &message).unwrap();
+        let err = finalize_lock_tx(
+            &mut spending_tx,
+            sig,
+            &f2_spend_info,
+            &ScriptBuf::from_bytes(vec![bitcoin::opcodes::OP_TRUE.to_u8()]),
+            &tx_context.x,
+            &tx_context.nonce,
+            &tx_f2.output[0],
+        )
+        .unwrap_err();
+        assert!(matches!(err, TxError::Taproot(_)));
+
+        // Finalizing against a prevout other than the one signed over
+        let sig = simulate_musig2(sk_signers, &message).unwrap();
+        let mut prevout = tx_f2.output[0].clone();
+        prevout.value += Amount::from_sat(1);
+        let err = finalize_lock_tx(
+            &mut spending_tx,
+            sig,
+            &f2_spend_info,
+            &f2_lock,
+            &tx_context.x,
+            &tx_context.nonce,
+            &prevout,
+        )
+        .unwrap_err();
+        assert!(matches!(err, TxError::InvalidSignature { .. }), "{err}");
+        // Or a signature over another message
+        let sig = simulate_musig2(sk_signers, &Message::from_digest([0; 32]))
+            .unwrap();
+        let err = finalize_lock_tx(
+            &mut spending_tx,
+            sig,
+            &f2_spend_info,
+            &f2_lock,
+            &tx_context.x,
+            &tx_context.nonce,
+            &tx_f2.output[0],
+        )
+        .unwrap_err();
+        assert!(matches!(err, TxError::InvalidSignature { .. }), "{err}");
+        assert!(spending_tx.input[0].witness.is_empty());
+    }
+
+    #[rstest]
+    fn test_estimate_tx_fee(
+        tx_context: &TxContext,
+        f1_tx_fixture: TxFixture,
+        f2_tx_fixture: TxFixture,
+    ) {
+        let fee_rate = tx_context.fee_rate;
+        let tx_f1 = &f1_tx_fixture.tx;
+        let tx_f2 = &f2_tx_fixture.tx;
+        assert_eq!(
+            estimate_tx_fee(tx_f2, 3),
+            Amount::from_sat(3 * tx_f2.vsize() as u64)
+        );
+
+        // The funding witness is sized exactly
+        let f1_fee =
+            tx_context.funding_value_sat - tx_f1.output[0].value.to_sat();
+        assert_eq!(f1_fee, fee_rate * tx_f1.vsize() as u64);
+
+        // Placeholder limbs overshoot by at most ceil(limbs / 4) vbytes
+        let f2_fee = tx_f1.output[0].value - tx_f2.output[0].value;
+        let predicted = fee_rate * tx_f2.vsize() as u64;
+        let tolerance =
+            fee_rate * message_to_witness_limbs(0, 0).len().div_ceil(4) as u64;
+        assert!(f2_fee.to_sat() >= predicted);
+        assert!(f2_fee.to_sat() - predicted <= tolerance);
+    }
+
+    #[rstest]
+    fn test_witness_script_size(f1_tx_fixture: TxFixture) {
+        let f1_lock = f1_tx_fixture.prev_lock;
+        assert_eq!(assert_witness_script_standard(&f1_lock), Ok(()));
+        // Only the Tapscript relaxation makes the F1 script relayable
+        assert_eq!(
+            check_witness_script_size(&f1_lock, MAX_STANDARD_P2WSH_SCRIPT_SIZE),
+            Err(SizeError {
+                size: f1_lock.len(),
+                limit: MAX_STANDARD_P2WSH_SCRIPT_SIZE
+            })
+        );
+
+        let oversized = ScriptBuf::from_bytes(vec![
+            bitcoin::opcodes::all::OP_NOP.to_u8();
+            MAX_STANDARD_TX_WEIGHT + 1
+        ]);
+        let err = assert_witness_script_standard(&oversized).unwrap_err();
+        assert_eq!(err.size, MAX_STANDARD_TX_WEIGHT + 1);
+        assert!(
+            TxError::from(err)
+                .to_string()
+                .contains(&(MAX_STANDARD_TX_WEIGHT + 1).to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_onchain_limits() {
+        let mut config = ColliderVmConfig::new(3, 2, 4, 16, 2).unwrap();
+        assert_eq!(config.validate_onchain_limits(1), Ok(()));
+        assert_eq!(config.validate_onchain_limits(3), Ok(()));
+
+        // No valid B can hit an on-chain limit: B only trades digest-nibble
+        // drops for prefix comparisons, a few bytes and no stack either way
+        for b in (4..=32).step_by(4) {
+            config.b = b;
+            assert_eq!(config.validate_onchain_limits(1), Ok(()), "B={b}");
+        }
+
+        // A B past the 32-bit flow ids is rejected by the config check
+        // itself, before any limit is measured
+        config.b = 40;
+        assert_eq!(
+            config.validate_onchain_limits(1),
+            Err(vec![LimitViolation::Config(
+                CoreError::HashPrefixOutOfRange {
+                    prefix: 40,
+                    max: 32
+                }
+            )])
+        );
+
+        // Unaggregated signers break several limits at once
+        config.b = 16;
+        let violations = config.validate_onchain_limits(12_000).unwrap_err();
+        assert!(
+            violations
+                .iter()
+                .any(|v| matches!(v, LimitViolation::ScriptSize(_)))
+        );
+        assert!(violations.iter().any(|v| matches!(
+            v,
+            LimitViolation::StackDepth { peak, .. } if *peak > MAX_STACK_SIZE
+        )));
+    }
+
+    #[test]
+    fn test_operator_economics() {
+        let config = ColliderVmConfig {
+            n: 3,
+            m: 2,
+            l: 4,
+            b: 16,
+            k: 2,
+        };
+        let fee_rate = 1;
+        let fees = flow_tx_vbytes(16).iter().sum::<usize>() as u64;
+        // 2^12 hashes at 1 sat each
+        let grind_cost = 4096;
+
+        let rich =
+            operator_economics(&config, 1.0, fees + grind_cost + 1, fee_rate);
+        assert_eq!(rich.expected_hashes, 4096);
+        assert_eq!(rich.fees_sat, fees);
+        assert!(rich.profitable);
+
+        // Once the reward no longer covers fees plus grinding, it flips
+        let poor =
+            operator_economics(&config, 1.0, fees + grind_cost - 1, fee_rate);
+        assert!(!poor.profitable);
+        assert!(poor.net_reward_sat > 0);
+
+        // Fees alone can eat the reward
+        let underwater = operator_economics(&config, 0.0, fees / 2, fee_rate);
+        assert!(underwater.net_reward_sat < 0);
+        assert!(!underwater.profitable);
+    }
+
+    #[test]
+    fn test_wrap_taproot_leaf() {
+        let secp = Secp256k1::new();
+        let (_, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        let internal_key = XOnlyPublicKey::from(pk);
+        let script = get_funding_script(&internal_key);
+
+        let (address, spend_info) =
+            wrap_taproot_leaf(&secp, &script, &internal_key, Network::Regtest)
+                .unwrap();
+
+        // The witness program is the internal key tweaked by the leaf's root
+        let leaf_hash =
+            TapLeafHash::from_script(&script, LeafVersion::TapScript);
+        assert_eq!(
+            spend_info.merkle_root().map(|root| root.to_byte_array()),
+            Some(leaf_hash.to_byte_array())
+        );
+        let (output_key, _) =
+            internal_key.tap_tweak(&secp, spend_info.merkle_root());
+        let program = address.witness_program().unwrap();
+        assert_eq!(
+            program.program().as_bytes(),
+            &output_key.to_inner().serialize()
+        );
+
+        // ... and the control block for the leaf verifies against it
+        let control_block = spend_info
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .unwrap();
+        assert!(control_block.verify_taproot_commitment(
+            &secp,
+            output_key.to_inner(),
+            &script
+        ));
+    }
+
+    #[test]
+    fn test_funding_spend_verifies_for_either_key_parity() {
+        let secp = Secp256k1::new();
+        let funding_outpoint = OutPoint {
+            txid: Txid::all_zeros(),
+            vout: 0,
+        };
+        let funding_value_sat = 100_000;
+        let prefix = flow_id_to_prefix_bytes(0, 8);
+
+        // The first seeds giving an even-Y and an odd-Y aggregate key
+        let mut by_parity = Vec::new();
+        for seed in 0.. {
+            let keys = generate_keys_n_from_seed(2, seed);
+            let pubkeys = keys.iter().map(|key| key.1).collect::<Vec<_>>();
+            let (_, agg_pubkey) = aggregate_pubkeys(&pubkeys).unwrap();
+            let pk_signer = pubkey_inner_from(agg_pubkey);
+            let parity = pk_signer.x_only_public_key().1;
+            if by_parity.iter().all(|(seen, ..)| *seen != parity) {
+                by_parity.push((parity, keys, pk_signer));
+            }
+            if by_parity.len() == 2 {
+                break;
+            }
+        }
+
+        for (parity, keys, pk_signer) in by_parity {
+            let (mut tx, _, _, funding_script, funding_spend_info, message) =
+                create_f1_tx(
+                    8,
+                    &secp,
+                    &pk_signer,
+                    &Network::Regtest,
+                    &funding_outpoint,
+                    &funding_value_sat,
+                    &prefix,
+                    F1_THRESHOLD,
+                    &1,
+                    None,
+                    false,
+                )
+                .unwrap();
+            assert_eq!(
+                funding_script,
+                get_funding_script(&signer_xonly_key(&pk_signer))
+            );
+
+            let prevout = TxOut {
+                value: Amount::from_sat(funding_value_sat),
+                script_pubkey: ScriptBuf::new_p2tr_tweaked(
+                    funding_spend_info.output_key(),
+                ),
+            };
+            let sig = simulate_musig2_seeded(&keys, &message, 0).unwrap();
+            finalize_f1_tx(
+                &mut tx,
+                sig,
+                &funding_spend_info,
+                &funding_script,
+                &prevout,
+            )
+            .unwrap();
+
+            let exec = dry_run_taproot_input(&tx, 0, &[prevout]);
+            assert!(
+                exec.success,
+                "funding spend fails for a {parity:?} aggregate key: {:?}",
+                exec.error
+            );
+        }
+    }
+
+    #[test]
+    fn test_funding_control_block() {
+        let secp = Secp256k1::new();
+        let (_, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        let xonly_pk = XOnlyPublicKey::from(pk);
+
+        let spend_info = funding_taproot_spend_info(&secp, &xonly_pk, None);
+        let control_block = funding_control_block(&xonly_pk, &secp);
+
+        assert!(control_block.verify_taproot_commitment(
+            &secp,
+            spend_info.output_key().to_inner(),
+            &get_funding_script(&xonly_pk),
+        ));
+        // A single-leaf tree's merkle root is the leaf hash itself
+        assert_eq!(
+            spend_info.merkle_root().map(|root| root.to_byte_array()),
+            Some(funding_tapleaf_hash(&xonly_pk).to_byte_array())
+        );
+
+        // The control block must not validate against another output key
+        let (_, other) = secp.generate_keypair(&mut rand::thread_rng());
+        let other_info = funding_taproot_spend_info(
+            &secp,
+            &XOnlyPublicKey::from(other),
+            None,
+        );
+        assert!(!control_block.verify_taproot_commitment(
+            &secp,
+            other_info.output_key().to_inner(),
+            &get_funding_script(&xonly_pk),
+        ));
+    }
+
+    #[rstest]
+    fn test_create_tx_custom_thresholds(tx_context: &TxContext) {
+        let TxContext {
+            secp,
+            network,
+            funding_outpoint,
+            funding_value_sat,
+            fee_rate,
+            b,
+            flow_id_prefix,
+            ..
+        } = tx_context;
+        let pk_signer = tx_context.pk_signer();
+        let signer = bitcoin::PublicKey::new(pk_signer);
+
+        let (tx_f1, f1_lock, ..) = create_f1_tx(
+            *b,
+            secp,
+            &pk_signer,
+            network,
+            funding_outpoint,
+            funding_value_sat,
+            flow_id_prefix,
+            0,
+            fee_rate,
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            f1_lock,
+            build_script_f1_blake3_locked(
+                &signer,
+                flow_id_prefix,
+                *b,
+                0,
+                LIMB_LEN
+            )
+        );
+
+        let threshold = i32::MAX as u32;
+        let (_, f2_lock, ..) = create_f2_tx(
+            *b,
+            secp,
+            &pk_signer,
+            network,
+            &tx_f1,
+            &tx_f1.output[0].value.to_sat(),
+            &f1_lock,
+            flow_id_prefix,
+            threshold,
+            fee_rate,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            f2_lock,
+            build_script_f2_blake3_locked(
+                &signer,
+                flow_id_prefix,
+                *b,
+                threshold,
+                LIMB_LEN
+            )
+        );
+        assert_ne!(
+            f2_lock,
+            build_script_f2_blake3_locked(
+                &signer,
+                flow_id_prefix,
+                *b,
+                F2_THRESHOLD,
+                LIMB_LEN
+            )
+        );
+    }
+
+    #[rstest]
+    fn test_rbf_and_bump_fee(tx_context: &TxContext, f1_tx_fixture: TxFixture) {
+        let TxContext {
+            secp,
+            sk_signers,
+            network,
+            funding_outpoint,
+            funding_value_sat,
+            fee_rate,
+            b,
+            flow_id_prefix,
+            ..
+        } = tx_context;
+        let pk_signer = tx_context.pk_signer();
+
+        let (mut tx, _, _, funding_script, funding_spend_info, message) =
+            create_f1_tx(
+                *b,
+                secp,
+                &pk_signer,
+                network,
+                funding_outpoint,
+                funding_value_sat,
+                flow_id_prefix,
+                F1_THRESHOLD,
+                fee_rate,
+                None,
+                true,
+            )
+            .unwrap();
+        assert_eq!(tx.input[0].sequence, Sequence(0xfffffffd));
+        assert!(tx.is_explicitly_rbf());
+
+        let prevout = TxOut {
+            value: Amount::from_sat(*funding_value_sat),
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(
+                funding_spend_info.output_key(),
+            ),
+        };
+        let sig = simulate_musig2(sk_signers, &message).unwrap();
+        finalize_f1_tx(
+            &mut tx,
+            sig,
+            &funding_spend_info,
+            &funding_script,
+            &prevout,
+        )
+        .unwrap();
+        let prevouts = [prevout];
+        let fee =
+            |tx: &Transaction| *funding_value_sat - tx.output[0].value.to_sat();
+
+        let bumped = bump_fee(&tx, &prevouts, fee_rate * 5).unwrap();
+        assert!(fee(&bumped) > fee(&tx));
+        assert_eq!(fee(&bumped), estimate_tx_fee(&tx, fee_rate * 5).to_sat());
+        assert_eq!(bumped.input[0].previous_output, *funding_outpoint);
+        assert_eq!(bumped.input[0].sequence, tx.input[0].sequence);
+        assert!(bumped.input[0].witness.is_empty());
+        assert_eq!(bumped.output[0].script_pubkey, tx.output[0].script_pubkey);
+
+        // The replacement must pay more than the original
+        assert!(matches!(
+            bump_fee(&tx, &prevouts, *fee_rate),
+            Err(TxError::FeeNotIncreased { .. })
+        ));
+        // ... by at least the incremental relay fee over its own vsize
+        let vsize = tx.vsize() as u64;
+        let mut cheap = tx.clone();
+        cheap.output[0].value =
+            Amount::from_sat(*funding_value_sat - vsize * 3 / 2);
+        assert_eq!(
+            bump_fee(&cheap, &prevouts, 2),
+            Err(TxError::FeeNotIncreased {
+                old_fee_sat: vsize * 3 / 2,
+                new_fee_sat: vsize * 2,
+                min_fee_sat: vsize * 3 / 2 + vsize * INCREMENTAL_RELAY_FEE_RATE,
+            })
+        );
+        assert!(bump_fee(&cheap, &prevouts, 3).is_ok());
+        // ... and the original must have opted in
+        assert_eq!(
+            bump_fee(&f1_tx_fixture.tx, &prevouts, fee_rate * 5),
+            Err(TxError::NotReplaceable { input: 0 })
+        );
+    }
+
+    #[test]
+    fn test_nums_internal_key_disables_key_path() {
+        let secp = Secp256k1::new();
+        let (sk, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        let xonly_pk = XOnlyPublicKey::from(pk);
+
+        let default_info = funding_taproot_spend_info(&secp, &xonly_pk, None);
+        let nums_info = funding_taproot_spend_info(
+            &secp,
+            &xonly_pk,
+            Some(nums_internal_key()),
+        );
+        assert_eq!(nums_info.internal_key(), nums_internal_key());
+
+        let default_addr =
+            Address::p2tr_tweaked(default_info.output_key(), Network::Regtest);
+        let nums_addr =
+            Address::p2tr_tweaked(nums_info.output_key(), Network::Regtest);
+        assert_ne!(default_addr, nums_addr);
+
+        // Both trees commit to the same leaf, so the script path is unchanged
+        assert_eq!(default_info.merkle_root(), nums_info.merkle_root());
+
+        // The signer can no longer produce a valid key-path signature
+        let msg = Message::from_digest([7u8; 32]);
+        let keypair = bitcoin::secp256k1::Keypair::from_secret_key(&secp, &sk);
+        let tweaked = keypair.tap_tweak(&secp, nums_info.merkle_root());
+        let sig = secp.sign_schnorr_no_aux_rand(&msg, &tweaked.to_inner());
+        assert!(
+            secp.verify_schnorr(&sig, &msg, &nums_info.output_key().to_inner())
+                .is_err()
+        );
+
+        // ...while it still can for the default internal key
+        let keypair = bitcoin::secp256k1::Keypair::from_secret_key(&secp, &sk);
+        let tweaked = keypair.tap_tweak(&secp, default_info.merkle_root());
+        let sig = secp.sign_schnorr_no_aux_rand(&msg, &tweaked.to_inner());
+        assert!(
+            secp.verify_schnorr(
+                &sig,
+                &msg,
+                &default_info.output_key().to_inner()
+            )
+            .is_ok()
+        );
+    }
 }
@@ -0,0 +1,549 @@
+use crate::core::{
+    BBits, ColliderVmConfig, F1_THRESHOLD, F2_THRESHOLD, LBits, MessageSchema,
+    build_script_f1_predicate, build_script_f2_predicate,
+    build_script_step_body, find_valid_nonce, flow_id_to_prefix_bytes,
+    message_to_witness_limbs, witness_pushes,
+};
+use crate::musig2::{
+    aggregate_pubkeys, generate_keys_n_from_seed, simulate_musig2_seeded,
+};
+use crate::transactions::{
+    TxError, create_f1_tx, create_f2_tx, create_spending_tx, finalize_lock_tx,
+};
+use crate::utils::pubkey_inner_from;
+use bitcoin::hashes::{Hash, sha256};
+use bitcoin::secp256k1::{Message, Secp256k1};
+use bitcoin::{
+    Address, Network, OutPoint, ScriptBuf, Transaction, Txid, Witness,
+};
+use bitvm::{dry_run_taproot_input, execute_script_buf};
+use serde::Serialize;
+use std::fmt;
+
+/// One step of a simulated protocol run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TranscriptEvent {
+    /// The operator ground a nonce selecting a flow in `D`
+    Grind {
+        input: u32,
+        attempts: u64,
+        nonce: u64,
+        flow_id: u32,
+    },
+    /// A step's locking script was built for the flow the operator spends
+    ScriptBuilt {
+        step: String,
+        flow_id: u32,
+        script_len: usize,
+        script_sha256: String,
+    },
+    /// The witness limbs for `x || nonce` were assembled
+    WitnessAssembled {
+        message_hex: String,
+        limbs: usize,
+        witness_len: usize,
+    },
+    /// A step's spend was executed locally, signature check included
+    Executed {
+        step: String,
+        success: bool,
+        /// The first check that failed, when `success` is false
+        rejected_by: Option<ScriptCheck>,
+        opcode_count: usize,
+        max_stack_items: usize,
+    },
+}
+
+impl fmt::Display for TranscriptEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscriptEvent::Grind {
+                input,
+                attempts,
+                nonce,
+                flow_id,
+            } => write!(
+                f,
+                "Found nonce r = {nonce} for x = {input} after {attempts} attempts, selecting flow d = {flow_id}"
+            ),
+            TranscriptEvent::ScriptBuilt {
+                step,
+                flow_id,
+                script_len,
+                script_sha256,
+            } => write!(
+                f,
+                "Built the {step} locking script of flow {flow_id}: {script_len} bytes, sha256 {script_sha256}"
+            ),
+            TranscriptEvent::WitnessAssembled {
+                message_hex,
+                limbs,
+                witness_len,
+            } => write!(
+                f,
+                "Assembled the witness for message {message_hex}: {limbs} limbs, {witness_len} bytes"
+            ),
+            TranscriptEvent::Executed {
+                step,
+                success,
+                rejected_by,
+                opcode_count,
+                max_stack_items,
+            } => {
+                write!(f, "Executed {step}: ")?;
+                match (success, rejected_by) {
+                    (true, _) => write!(f, "success")?,
+                    (false, Some(check)) => write!(f, "rejected, {check}")?,
+                    (false, None) => write!(f, "failure")?,
+                }
+                write!(
+                    f,
+                    " ({opcode_count} opcodes, peak stack {max_stack_items})"
+                )
+            }
+        }
+    }
+}
+
+/// A transcript entry: the event and its human-readable summary.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TranscriptEntry {
+    pub summary: String,
+    #[serde(flatten)]
+    pub event: TranscriptEvent,
+}
+
+/// Ordered record of a simulated protocol run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Transcript {
+    pub config: ColliderVmConfig,
+    pub scenario: SimulationScenario,
+    pub entries: Vec<TranscriptEntry>,
+}
+
+impl Transcript {
+    fn push(&mut self, event: TranscriptEvent) {
+        self.entries.push(TranscriptEntry {
+            summary: event.to_string(),
+            event,
+        });
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = &TranscriptEvent> {
+        self.entries.iter().map(|entry| &entry.event)
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// The first step that rejected the operator's spend, and the failing
+    /// check.
+    pub fn rejection(&self) -> Option<(&str, ScriptCheck)> {
+        self.events().find_map(|event| match event {
+            TranscriptEvent::Executed {
+                step,
+                rejected_by: Some(check),
+                ..
+            } => Some((step.as_str(), *check)),
+            _ => None,
+        })
+    }
+}
+
+/// Seed of the signer keys and MuSig2 nonces, which keeps the script hashes
+/// and signatures reproducible across runs
+const SIGNER_SEED: u64 = 0;
+/// Value of the simulated funding output
+const FUNDING_VALUE_SAT: u64 = 100_000;
+/// Fee-rate of the simulated transactions, in sat/vB
+const FEE_RATE: u64 = 1;
+
+/// Runs the whole protocol for `input` locally, with the operator behaving
+/// as `scenario`, and records every step.
+///
+/// The grind, the F1/F2 script construction, the witness assembly and the
+/// execution of both steps end up as events, in order. Each step is run as
+/// the real spend of its lock, signed by the `n` signers of `config`, so
+/// the signature check executes as on-chain. Both steps are executed even
+/// after a rejection, to show which checks each of them fails.
+pub fn run_simulation_transcript(
+    config: &ColliderVmConfig,
+    input: u32,
+    scenario: SimulationScenario,
+) -> Result<Transcript, TxError> {
+    config.validate()?;
+    let mut transcript = Transcript {
+        config: config.clone(),
+        scenario,
+        entries: Vec::new(),
+    };
+
+    let input = match scenario {
+        SimulationScenario::WrongInput => F1_THRESHOLD,
+        _ => input,
+    };
+    let b_bits = BBits::new(config.b)?;
+    let l_bits = LBits::new(config.l, b_bits)?;
+    let (nonce, flow_id) = find_valid_nonce(input, b_bits, l_bits)?;
+    transcript.push(TranscriptEvent::Grind {
+        input,
+        attempts: nonce + 1,
+        nonce,
+        flow_id,
+    });
+    let spent_flow_id = match scenario {
+        SimulationScenario::ForgedFlowId => (flow_id + 1) % (1 << config.l),
+        _ => flow_id,
+    };
+
+    let chain = build_signed_flow(config, input, nonce, spent_flow_id)?;
+    for (step, script) in [("F1", &chain.f1_lock), ("F2", &chain.f2_lock)] {
+        transcript.push(TranscriptEvent::ScriptBuilt {
+            step: step.to_string(),
+            flow_id: spent_flow_id,
+            script_len: script.len(),
+            script_sha256: sha256::Hash::hash(script.as_bytes()).to_string(),
+        });
+    }
+
+    let schema = MessageSchema::default();
+    let message = schema.encode(&[&input.to_le_bytes(), &nonce.to_le_bytes()]);
+    let limbs = message_to_witness_limbs(input, nonce);
+    let witness = witness_pushes(&limbs);
+    transcript.push(TranscriptEvent::WitnessAssembled {
+        message_hex: hex::encode(message),
+        limbs: limbs.len(),
+        witness_len: witness.len(),
+    });
+
+    let f2_prevouts = chain.tx_f2.output.clone();
+    let mut spends = [
+        (chain.tx_f2, chain.tx_f1.output),
+        (chain.spending_tx, f2_prevouts),
+    ];
+    if scenario == SimulationScenario::MissingSignature {
+        for (tx, _) in &mut spends {
+            clear_signature(tx);
+        }
+    }
+    let prefix = flow_id_to_prefix_bytes(spent_flow_id, config.b);
+    for ((step, predicate), (tx, prevouts)) in
+        step_predicates().into_iter().zip(&spends)
+    {
+        let res = dry_run_taproot_input(tx, 0, prevouts);
+        let rejected_by = (!res.success)
+            .then(|| failing_check(&witness, &predicate, &prefix, &schema));
+        transcript.push(TranscriptEvent::Executed {
+            step: step.to_string(),
+            success: res.success,
+            rejected_by,
+            opcode_count: res.stats.opcode_count,
+            max_stack_items: res.stats.max_nb_stack_items,
+        });
+    }
+
+    Ok(transcript)
+}
+
+/// The F1 and F2 locks of a flow and the signed transactions spending them.
+struct SignedFlow {
+    tx_f1: Transaction,
+    f1_lock: ScriptBuf,
+    tx_f2: Transaction,
+    f2_lock: ScriptBuf,
+    spending_tx: Transaction,
+}
+
+/// Builds the f1 -> f2 -> spending chain of flow `flow_id`, with the
+/// witnesses spending both locks with `input || nonce` and the signatures
+/// of `config.n` MuSig2 signers.
+fn build_signed_flow(
+    config: &ColliderVmConfig,
+    input: u32,
+    nonce: u64,
+    flow_id: u32,
+) -> Result<SignedFlow, TxError> {
+    let signing = |err: anyhow::Error| TxError::Signing(err.to_string());
+    let keys = generate_keys_n_from_seed(config.n, SIGNER_SEED);
+    let pubkeys = keys.iter().map(|key| key.1).collect::<Vec<_>>();
+    let (_, agg_pubkey) = aggregate_pubkeys(&pubkeys).map_err(signing)?;
+    let pk_signer = pubkey_inner_from(agg_pubkey);
+    let sign = |message: &Message| {
+        simulate_musig2_seeded(&keys, message, SIGNER_SEED).map_err(signing)
+    };
+
+    let secp = Secp256k1::new();
+    let network = Network::Regtest;
+    let prefix = flow_id_to_prefix_bytes(flow_id, config.b);
+    let funding_outpoint = OutPoint {
+        txid: Txid::all_zeros(),
+        vout: 0,
+    };
+    let (tx_f1, f1_lock, f1_spend_info, ..) = create_f1_tx(
+        config.b,
+        &secp,
+        &pk_signer,
+        &network,
+        &funding_outpoint,
+        &FUNDING_VALUE_SAT,
+        &prefix,
+        F1_THRESHOLD,
+        &FEE_RATE,
+        None,
+        false,
+    )?;
+
+    let (mut tx_f2, f2_lock, f2_spend_info, message) = create_f2_tx(
+        config.b,
+        &secp,
+        &pk_signer,
+        &network,
+        &tx_f1,
+        &tx_f1.output[0].value.to_sat(),
+        &f1_lock,
+        &prefix,
+        F2_THRESHOLD,
+        &FEE_RATE,
+        false,
+    )?;
+    finalize_lock_tx(
+        &mut tx_f2,
+        sign(&message)?,
+        &f1_spend_info,
+        &f1_lock,
+        &input,
+        &nonce,
+        &tx_f1.output[0],
+    )?;
+
+    let receiver =
+        Address::p2tr(&secp, pk_signer.x_only_public_key().0, None, network);
+    let (mut spending_tx, message) = create_spending_tx(
+        &tx_f2,
+        &tx_f2.output[0].value.to_sat(),
+        &receiver,
+        &f2_lock,
+        &FEE_RATE,
+        None,
+        None,
+        false,
+    )?;
+    finalize_lock_tx(
+        &mut spending_tx,
+        sign(&message)?,
+        &f2_spend_info,
+        &f2_lock,
+        &input,
+        &nonce,
+        &tx_f2.output[0],
+    )?;
+
+    Ok(SignedFlow {
+        tx_f1,
+        f1_lock,
+        tx_f2,
+        f2_lock,
+        spending_tx,
+    })
+}
+
+/// Empties the signature slot of a lock's script-path witness (limbs,
+/// signature, script, control block), as an operator spending without the
+/// signers would.
+fn clear_signature(tx: &mut Transaction) {
+    let mut items = tx.input[0].witness.to_vec();
+    let signature = items.len() - 3;
+    items[signature].clear();
+    tx.input[0].witness = Witness::from_slice(&items);
+}
+
+/// The check of a step that rejected `witness`: its predicate or prefix
+/// comparison when they fail on their own, the signature otherwise.
+fn failing_check(
+    witness: &ScriptBuf,
+    predicate: &ScriptBuf,
+    prefix: &[u8],
+    schema: &MessageSchema,
+) -> ScriptCheck {
+    let passes = |locking: ScriptBuf| {
+        execute_script_buf(concat_scripts(witness, &locking)).success
+    };
+    // Hashes and drops the whole digest, comparing no prefix
+    if !passes(build_script_step_body(predicate.clone(), &[], schema)) {
+        ScriptCheck::Predicate
+    } else if !passes(build_script_step_body(predicate.clone(), prefix, schema))
+    {
+        ScriptCheck::FlowIdPrefix
+    } else {
+        ScriptCheck::Signature
+    }
+}
+
+/// The demo steps, in spending order, with their predicates.
+fn step_predicates() -> [(&'static str, ScriptBuf); 2] {
+    [
+        ("F1", build_script_f1_predicate(F1_THRESHOLD)),
+        ("F2", build_script_f2_predicate(F2_THRESHOLD)),
+    ]
+}
+
+fn concat_scripts(witness: &ScriptBuf, locking: &ScriptBuf) -> ScriptBuf {
+    let mut script = witness.to_bytes();
+    script.extend(locking.to_bytes());
+    ScriptBuf::from_bytes(script)
+}
+
+/// How the operator behaves when spending its flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SimulationScenario {
+    /// Spends the flow its nonce selects, with the presigned signatures
+    HonestOperator,
+    /// Grinds a valid flow for an x failing F1 (`x = F1_THRESHOLD`)
+    WrongInput,
+    /// Spends another flow than the one its nonce selects
+    ForgedFlowId,
+    /// Spends without the signers' signature
+    MissingSignature,
+}
+
+impl SimulationScenario {
+    pub const ALL: [SimulationScenario; 4] = [
+        SimulationScenario::HonestOperator,
+        SimulationScenario::WrongInput,
+        SimulationScenario::ForgedFlowId,
+        SimulationScenario::MissingSignature,
+    ];
+}
+
+/// The check of a step's locking script that rejected a spend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptCheck {
+    /// `<signer_pubkey> OP_CHECKSIGVERIFY`
+    Signature,
+    /// The step's comparison on x
+    Predicate,
+    /// The BLAKE3 prefix against the flow id the script is locked to
+    FlowIdPrefix,
+}
+
+impl fmt::Display for ScriptCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptCheck::Signature => {
+                write!(f, "no valid signature from the signers")
+            }
+            ScriptCheck::Predicate => write!(f, "x fails the step predicate"),
+            ScriptCheck::FlowIdPrefix => write!(
+                f,
+                "the message hash does not select the flow the script is locked to"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ColliderVmConfig {
+        ColliderVmConfig {
+            n: 3,
+            m: 2,
+            l: 4,
+            b: 16,
+            k: 2,
+        }
+    }
+
+    fn executions(transcript: &Transcript) -> Vec<(&str, bool)> {
+        transcript
+            .events()
+            .filter_map(|e| match e {
+                TranscriptEvent::Executed { step, success, .. } => {
+                    Some((step.as_str(), *success))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_run_simulation_transcript() {
+        let transcript = run_simulation_transcript(
+            &config(),
+            114,
+            SimulationScenario::HonestOperator,
+        )
+        .unwrap();
+
+        let grinds = transcript
+            .events()
+            .filter(|e| matches!(e, TranscriptEvent::Grind { .. }))
+            .count();
+        assert_eq!(grinds, 1);
+        assert_eq!(executions(&transcript), vec![("F1", true), ("F2", true)]);
+        assert_eq!(transcript.rejection(), None);
+
+        // x = 50 fails F1's x > 100 but still satisfies F2
+        let transcript = run_simulation_transcript(
+            &config(),
+            50,
+            SimulationScenario::HonestOperator,
+        )
+        .unwrap();
+        assert_eq!(executions(&transcript), vec![("F1", false), ("F2", true)]);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&transcript.to_json().unwrap()).unwrap();
+        assert_eq!(json["scenario"], "honest_operator");
+        assert_eq!(json["entries"][0]["kind"], "grind");
+        assert!(json["entries"][0]["summary"].is_string());
+    }
+
+    #[test]
+    fn test_run_simulation_transcript_scenarios() {
+        for scenario in SimulationScenario::ALL {
+            let transcript =
+                run_simulation_transcript(&config(), 114, scenario).unwrap();
+            let expected = match scenario {
+                SimulationScenario::HonestOperator => None,
+                SimulationScenario::WrongInput => {
+                    Some(("F1", ScriptCheck::Predicate))
+                }
+                SimulationScenario::ForgedFlowId => {
+                    Some(("F1", ScriptCheck::FlowIdPrefix))
+                }
+                SimulationScenario::MissingSignature => {
+                    Some(("F1", ScriptCheck::Signature))
+                }
+            };
+            assert_eq!(transcript.rejection(), expected, "{scenario:?}");
+        }
+
+        // Without the signature, the otherwise valid spends of both steps
+        // fail, and only at the signature check
+        let transcript = run_simulation_transcript(
+            &config(),
+            114,
+            SimulationScenario::MissingSignature,
+        )
+        .unwrap();
+        let rejections = transcript
+            .events()
+            .filter_map(|e| match e {
+                TranscriptEvent::Executed { rejected_by, .. } => {
+                    Some(*rejected_by)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rejections,
+            vec![Some(ScriptCheck::Signature), Some(ScriptCheck::Signature)]
+        );
+    }
+}
@@ -1,12 +1,13 @@
+use crate::core::{NonceProgress, expected_attempts};
+use anyhow::Context;
 use indicatif::{ProgressBar, ProgressStyle};
+use std::cell::RefCell;
 use std::cmp::max;
 use std::time::Duration;
 use std::time::Instant;
 
 use bitcoin::{Network, Txid, secp256k1::SecretKey};
 use bitcoincore_rpc::{Client, RpcApi};
-use serde::Serialize;
-use serde::de::DeserializeOwned;
 
 /// Encode an i64 as a minimally‑encoded script number (little‑endian)
 pub fn encode_scriptnum(n: i64) -> Vec<u8> {
@@ -98,14 +99,37 @@ pub fn wait_for_confirmation(
     }
 }
 
+/// Writes `tx` as raw hex to `<output_dir>/<file_name>.tx`, creating
+/// `output_dir` (and its parents) if needed. IO errors name the path that
+/// was being created or written.
 pub fn write_transaction_to_file(
     tx: &bitcoin::Transaction,
     output_dir: &str,
     file_name: &str,
 ) -> anyhow::Result<String> {
-    std::fs::create_dir_all(output_dir)?;
+    std::fs::create_dir_all(output_dir).with_context(|| {
+        format!("failed to create output directory {output_dir}")
+    })?;
     let path = format!("{output_dir}/{file_name}.tx");
-    std::fs::write(&path, bitcoin::consensus::encode::serialize_hex(tx))?;
+    std::fs::write(&path, bitcoin::consensus::encode::serialize_hex(tx))
+        .with_context(|| format!("failed to write transaction to {path}"))?;
+    Ok(path)
+}
+
+/// Writes `psbt` in its BIP174 binary form to
+/// `<output_dir>/<file_name>.psbt`, creating `output_dir` (and its parents)
+/// if needed.
+pub fn write_psbt_to_file(
+    psbt: &bitcoin::Psbt,
+    output_dir: &str,
+    file_name: &str,
+) -> anyhow::Result<String> {
+    std::fs::create_dir_all(output_dir).with_context(|| {
+        format!("failed to create output directory {output_dir}")
+    })?;
+    let path = format!("{output_dir}/{file_name}.psbt");
+    std::fs::write(&path, psbt.serialize())
+        .with_context(|| format!("failed to write PSBT to {path}"))?;
     Ok(path)
 }
 
@@ -119,84 +143,200 @@ pub fn wrap_network(network: &str) -> Network {
     }
 }
 
+/// Wall-clock time we aim for between two progress reports
+const REPORT_TARGET: Duration = Duration::from_millis(200);
+const MIN_REPORT_INTERVAL: u64 = 1_000;
+const MAX_REPORT_INTERVAL: u64 = 100_000_000;
+
+/// Look of the nonce search progress bar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgressStyleConfig {
+    /// Interval between two spinner ticks, in milliseconds
+    pub tick_ms: u64,
+    /// indicatif progress chars: filled, (in-progress,) empty
+    pub progress_chars: String,
+    /// indicatif template string
+    pub template: String,
+}
+
+impl Default for ProgressStyleConfig {
+    fn default() -> Self {
+        Self {
+            tick_ms: 100,
+            progress_chars: "■□·".to_string(),
+            template: "{spinner:.green} [{elapsed_precise}] [{bar:50.cyan/blue}] {pos}/{len} ({percent}%) [{per_sec}] {msg}".to_string(),
+        }
+    }
+}
+
+impl ProgressStyleConfig {
+    fn to_progress_style(&self) -> Result<ProgressStyle, String> {
+        if self.progress_chars.chars().count() < 2 {
+            return Err(format!(
+                "progress_chars needs at least 2 chars, got {:?}",
+                self.progress_chars
+            ));
+        }
+        Ok(ProgressStyle::default_bar()
+            .template(&self.template)
+            .map_err(|e| format!("invalid progress template: {e}"))?
+            .progress_chars(&self.progress_chars))
+    }
+}
+
 pub struct NonceSearchProgress {
     progress_bar: Option<ProgressBar>,
+    style: ProgressStyleConfig,
     expected_attempts: u64,
     last_update: u64,
+    /// Nonce the search started at, non-zero when resumed
+    start_nonce: u64,
     hash_rates: Vec<f64>,
     start_time: Instant,
+    last_report_time: Instant,
     report_interval: u64,
 }
 
 impl NonceSearchProgress {
     pub fn new(expected_attempts: u64) -> Self {
+        Self::with_style(expected_attempts, ProgressStyleConfig::default())
+            .expect("default progress style is valid")
+    }
+
+    /// Same as [`NonceSearchProgress::new`], drawing the bar with `style`.
+    pub fn with_style(
+        expected_attempts: u64,
+        style: ProgressStyleConfig,
+    ) -> Result<Self, String> {
+        let progress_style = style.to_progress_style()?;
         let progress_bar = if expected_attempts > 100 {
             let pb = ProgressBar::new(expected_attempts);
-            pb.set_style(
-                ProgressStyle::default_bar()
-                    .template("{spinner:.green} [{elapsed_precise}] [{bar:50.cyan/blue}] {pos}/{len} ({percent}%) [{per_sec}] {msg}")
-                    .unwrap()
-                    .progress_chars("■□·")
-            );
+            pb.set_style(progress_style);
             pb.set_message("Finding nonce for valid flow...");
-            pb.enable_steady_tick(std::time::Duration::from_millis(100));
+            pb.enable_steady_tick(Duration::from_millis(style.tick_ms));
             Some(pb)
         } else {
             None
         };
-        Self {
+        Ok(Self {
             progress_bar,
+            style,
             expected_attempts,
             last_update: 0,
+            start_nonce: 0,
             hash_rates: Vec::with_capacity(10),
             start_time: Instant::now(),
+            last_report_time: Instant::now(),
             report_interval: 50_000,
+        })
+    }
+
+    /// Style the progress bar is drawn with
+    pub fn style(&self) -> &ProgressStyleConfig {
+        &self.style
+    }
+
+    /// Number of hashes between two progress reports
+    pub fn report_interval(&self) -> u64 {
+        self.report_interval
+    }
+
+    /// Rescale the report interval so that the next one takes roughly
+    /// `REPORT_TARGET`, given that the last one took `elapsed`.
+    pub fn adapt_report_interval(&mut self, elapsed: Duration) {
+        let elapsed = elapsed.as_secs_f64().max(1e-6);
+        let scaled =
+            self.report_interval as f64 * REPORT_TARGET.as_secs_f64() / elapsed;
+        self.report_interval = (scaled.round() as u64)
+            .clamp(MIN_REPORT_INTERVAL, MAX_REPORT_INTERVAL);
+    }
+
+    /// Seeds the hash rate window with a calibrated rate (see
+    /// `core::benchmark_hash_rate`), so the ETA is meaningful from the
+    /// start of the search rather than after the first report.
+    pub fn seed_hash_rate(&mut self, hash_rate: f64) {
+        if hash_rate <= 0.0 {
+            return;
+        }
+        self.hash_rates.push(hash_rate);
+        if let Some(pb) = &self.progress_bar {
+            pb.set_message(self.eta_message(0, hash_rate));
+        }
+    }
+
+    /// Mean of the recent hash rate samples, if any
+    pub fn average_hash_rate(&self) -> Option<f64> {
+        (!self.hash_rates.is_empty()).then(|| {
+            self.hash_rates.iter().sum::<f64>() / self.hash_rates.len() as f64
+        })
+    }
+
+    /// Seconds left to reach the expected attempts at `hash_rate`
+    pub fn eta_secs(&self, nonce: u64, hash_rate: f64) -> f64 {
+        if nonce >= self.expected_attempts {
+            0.0
+        } else {
+            (self.expected_attempts - nonce) as f64 / hash_rate
+        }
+    }
+
+    fn eta_message(&self, nonce: u64, hash_rate: f64) -> String {
+        let eta_secs = self.eta_secs(nonce, hash_rate);
+        let eta_str = if eta_secs < 60.0 {
+            format!("{eta_secs:.1}s")
+        } else if eta_secs < 3600.0 {
+            format!("{:.1}m {:.0}s", eta_secs / 60.0, eta_secs % 60.0)
+        } else {
+            format!(
+                "{:.1}h {:.0}m",
+                eta_secs / 3600.0,
+                (eta_secs % 3600.0) / 60.0
+            )
+        };
+        format!(
+            "ETA: {eta_str} @ {:.2} KH/s, {:.1}% done",
+            hash_rate / 1000.0,
+            (nonce as f64 / self.expected_attempts as f64) * 100.0
+        )
+    }
+
+    /// Starts the bar at `start_nonce`, for a search resumed there. Hash
+    /// rates only count the nonces tried since.
+    pub fn resume_from(&mut self, start_nonce: u64) {
+        self.start_nonce = start_nonce;
+        self.last_update = start_nonce;
+        if let Some(pb) = &self.progress_bar {
+            pb.set_position(start_nonce);
         }
     }
 
     pub fn update(&mut self, nonce: u64) {
         if nonce > self.last_update + self.report_interval {
             let elapsed = self.start_time.elapsed();
+            let tried = (nonce - self.start_nonce) as f64;
             let hash_rate = if elapsed.as_secs() > 0 {
-                nonce as f64 / elapsed.as_secs_f64()
+                tried / elapsed.as_secs_f64()
             } else {
-                nonce as f64
+                tried
             };
             self.hash_rates.push(hash_rate);
             if self.hash_rates.len() > 10 {
                 self.hash_rates.remove(0);
             }
-            let avg_hash_rate: f64 = self.hash_rates.iter().sum::<f64>()
-                / self.hash_rates.len() as f64;
+            let avg_hash_rate = self.average_hash_rate().unwrap_or(hash_rate);
             if let Some(pb) = &self.progress_bar {
                 pb.set_position(nonce);
-                let eta_secs = if nonce >= self.expected_attempts {
-                    0.0
-                } else {
-                    (self.expected_attempts - nonce) as f64 / avg_hash_rate
-                };
-                let eta_str = if eta_secs < 60.0 {
-                    format!("{eta_secs:.1}s")
-                } else if eta_secs < 3600.0 {
-                    format!("{:.1}m {:.0}s", eta_secs / 60.0, eta_secs % 60.0)
-                } else {
-                    format!(
-                        "{:.1}h {:.0}m",
-                        eta_secs / 3600.0,
-                        (eta_secs % 3600.0) / 60.0
-                    )
-                };
-                pb.set_message(format!(
-                    "ETA: {eta_str} @ {:.2} KH/s, {:.1}% done",
-                    avg_hash_rate / 1000.0,
-                    (nonce as f64 / self.expected_attempts as f64) * 100.0
-                ));
+                pb.set_message(self.eta_message(nonce, avg_hash_rate));
             } else {
                 println!(
                     "  Tried {nonce} hashes... ({avg_hash_rate:.2} hash/s)"
                 );
             }
             self.last_update = nonce;
+
+            let now = Instant::now();
+            self.adapt_report_interval(now - self.last_report_time);
+            self.last_report_time = now;
         }
         if let Some(pb) = &self.progress_bar {
             let update_frequency = if self.expected_attempts > 1_000_000 {
@@ -214,10 +354,11 @@ impl NonceSearchProgress {
 
     pub fn success(&self, flow_id: u32, nonce: u64) {
         let elapsed = self.start_time.elapsed();
+        let tried = nonce.saturating_sub(self.start_nonce) as f64;
         let hash_rate = if elapsed.as_secs() > 0 {
-            nonce as f64 / elapsed.as_secs_f64()
+            tried / elapsed.as_secs_f64()
         } else {
-            nonce as f64
+            tried
         };
         if let Some(pb) = &self.progress_bar {
             pb.finish_with_message(format!(
@@ -231,6 +372,26 @@ impl NonceSearchProgress {
         println!("  Average hash rate: {hash_rate:.2} hashes/sec");
     }
 
+    /// Final report of a batch search over `inputs` inputs.
+    pub fn batch_success(&self, inputs: usize, total_attempts: u64) {
+        let elapsed = self.start_time.elapsed();
+        let hash_rate = if elapsed.as_secs() > 0 {
+            total_attempts as f64 / elapsed.as_secs_f64()
+        } else {
+            total_attempts as f64
+        };
+        if let Some(pb) = &self.progress_bar {
+            pb.finish_with_message(format!(
+                "Found nonces for {inputs} inputs after {total_attempts} hashes!"
+            ));
+        } else {
+            println!(
+                "  Found valid nonces for {inputs} inputs after {total_attempts} hashes."
+            );
+        }
+        println!("  Average hash rate: {hash_rate:.2} hashes/sec");
+    }
+
     pub fn failure(&self) {
         if let Some(pb) = &self.progress_bar {
             pb.finish_with_message("Exceeded maximum attempts");
@@ -238,8 +399,201 @@ impl NonceSearchProgress {
     }
 }
 
-/// This is special for conversion of SecretKey and PublicKey between ::musig2::secp256k and ::secp256k1
-pub fn inner_from<F: Serialize, T: DeserializeOwned>(from: F) -> T {
-    let value = serde_json::to_value(&from).unwrap();
-    serde_json::from_value(value).unwrap()
+/// The terminal output of a nonce search: a header line, then the
+/// [`NonceSearchProgress`] bar fed with the search's [`NonceProgress`]
+/// events, then the result.
+pub struct ConsoleProgress {
+    progress: RefCell<NonceSearchProgress>,
+}
+
+impl ConsoleProgress {
+    /// Prints the header of a search for `B`/`L` starting at `start_nonce`
+    /// and sets up its bar.
+    pub fn new(
+        b_bits: usize,
+        l_bits: usize,
+        style: ProgressStyleConfig,
+        start_nonce: u64,
+    ) -> Result<Self, String> {
+        let expected_attempts = expected_attempts(b_bits, l_bits);
+        println!(
+            "Finding valid nonce (L={}, B={})... (Expected work: ~2^{} = {} hashes)",
+            l_bits,
+            b_bits,
+            b_bits.saturating_sub(l_bits),
+            expected_attempts
+        );
+        let mut progress =
+            NonceSearchProgress::with_style(expected_attempts, style)?;
+        if start_nonce > 0 {
+            println!("  Resuming from nonce {start_nonce}");
+            progress.resume_from(start_nonce);
+        }
+        Ok(Self {
+            progress: RefCell::new(progress),
+        })
+    }
+
+    /// See [`NonceSearchProgress::seed_hash_rate`].
+    pub fn seed_hash_rate(&self, hash_rate: f64) {
+        self.progress.borrow_mut().seed_hash_rate(hash_rate);
+    }
+
+    /// Moves the bar to the last nonce `event` counts.
+    pub fn report(&self, event: NonceProgress) {
+        let mut progress = self.progress.borrow_mut();
+        let nonce = progress.start_nonce + event.attempts.saturating_sub(1);
+        progress.update(nonce);
+    }
+
+    pub fn success(&self, flow_id: u32, nonce: u64) {
+        self.progress.borrow().success(flow_id, nonce);
+    }
+
+    pub fn failure(&self) {
+        self.progress.borrow().failure();
+    }
+}
+
+/// Convert a `musig2` public key into the `secp256k1` (and `bitcoin`) type.
+///
+/// `musig2` re-exports a different `secp256k1` release than `bitcoin`, so the
+/// two `PublicKey` types are distinct; the key goes over as its compressed
+/// encoding.
+pub fn pubkey_inner_from(
+    pk: musig2::secp256k1::PublicKey,
+) -> secp256k1::PublicKey {
+    secp256k1::PublicKey::from_slice(&pk.serialize())
+        .expect("a valid public key stays valid across secp256k1 versions")
+}
+
+/// Convert a `musig2` secret key into the `secp256k1` (and `bitcoin`) type.
+pub fn seckey_inner_from(sk: musig2::secp256k1::SecretKey) -> SecretKey {
+    SecretKey::from_slice(&sk.secret_bytes())
+        .expect("a valid secret key stays valid across secp256k1 versions")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_inner_from_round_trip() {
+        let secp = musig2::secp256k1::Secp256k1::new();
+        let (sk, pk) = secp.generate_keypair(&mut rand::thread_rng());
+
+        let inner_pk = pubkey_inner_from(pk);
+        assert_eq!(inner_pk.serialize(), pk.serialize());
+        let back_pk =
+            musig2::secp256k1::PublicKey::from_slice(&inner_pk.serialize())
+                .unwrap();
+        assert_eq!(back_pk, pk);
+
+        let inner_sk = seckey_inner_from(sk);
+        assert_eq!(inner_sk.secret_bytes(), sk.secret_bytes());
+        let back_sk =
+            musig2::secp256k1::SecretKey::from_slice(&inner_sk.secret_bytes())
+                .unwrap();
+        assert_eq!(back_sk, sk);
+
+        // The converted keys still form a pair
+        let inner_secp = secp256k1::Secp256k1::new();
+        assert_eq!(
+            secp256k1::PublicKey::from_secret_key(&inner_secp, &inner_sk),
+            inner_pk
+        );
+    }
+
+    #[test]
+    fn test_custom_progress_style() {
+        let style = ProgressStyleConfig {
+            tick_ms: 250,
+            progress_chars: "#>-".to_string(),
+            template: "{bar:20} {pos}/{len}".to_string(),
+        };
+        let progress =
+            NonceSearchProgress::with_style(1_000, style.clone()).unwrap();
+        assert_eq!(progress.style(), &style);
+        assert_eq!(progress.style().template, "{bar:20} {pos}/{len}");
+        assert!(progress.progress_bar.is_some());
+
+        assert_eq!(
+            NonceSearchProgress::new(1_000).style(),
+            &ProgressStyleConfig::default()
+        );
+
+        let bad_chars = ProgressStyleConfig {
+            progress_chars: "#".to_string(),
+            ..style
+        };
+        assert!(NonceSearchProgress::with_style(1_000, bad_chars).is_err());
+    }
+
+    #[test]
+    fn test_report_interval_adapts() {
+        // Fast hasher: 50k hashes in 20ms => report less often
+        let mut fast = NonceSearchProgress::new(10);
+        let initial = fast.report_interval();
+        fast.adapt_report_interval(Duration::from_millis(20));
+        assert!(fast.report_interval() > initial);
+
+        // Slow hasher: 50k hashes in 2s => report more often
+        let mut slow = NonceSearchProgress::new(10);
+        slow.adapt_report_interval(Duration::from_secs(2));
+        assert!(slow.report_interval() < initial);
+
+        // On target: the interval is kept
+        let mut steady = NonceSearchProgress::new(10);
+        steady.adapt_report_interval(REPORT_TARGET);
+        assert_eq!(steady.report_interval(), initial);
+
+        // Extremes stay within bounds
+        slow.adapt_report_interval(Duration::from_secs(3600));
+        assert_eq!(slow.report_interval(), MIN_REPORT_INTERVAL);
+        fast.adapt_report_interval(Duration::ZERO);
+        assert_eq!(fast.report_interval(), MAX_REPORT_INTERVAL);
+    }
+
+    #[test]
+    fn test_seeded_hash_rate() {
+        let mut progress = NonceSearchProgress::new(1_000_000);
+        assert_eq!(progress.average_hash_rate(), None);
+        progress.seed_hash_rate(0.0);
+        assert_eq!(progress.average_hash_rate(), None);
+
+        progress.seed_hash_rate(100_000.0);
+        assert_eq!(progress.average_hash_rate(), Some(100_000.0));
+        assert_eq!(progress.eta_secs(0, 100_000.0), 10.0);
+        assert_eq!(progress.eta_secs(2_000_000, 100_000.0), 0.0);
+    }
+
+    #[test]
+    fn test_write_transaction_creates_nested_dir() {
+        let root = std::env::temp_dir()
+            .join(format!("collidervm_write_tx_{}", std::process::id()));
+        let output_dir = root.join("nested/deeper");
+        assert!(!output_dir.exists());
+
+        let tx = bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        };
+        let path =
+            write_transaction_to_file(&tx, output_dir.to_str().unwrap(), "f1")
+                .unwrap();
+        assert!(output_dir.is_dir());
+        assert!(std::path::Path::new(&path).is_file());
+
+        // A file in place of the directory is reported with its path
+        let blocker = root.join("blocker");
+        std::fs::write(&blocker, "").unwrap();
+        let err =
+            write_transaction_to_file(&tx, blocker.to_str().unwrap(), "f1")
+                .unwrap_err();
+        assert!(err.to_string().contains(blocker.to_str().unwrap()));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -0,0 +1,100 @@
+//! End-to-end run of the demo binary against a live regtest node.
+//!
+//! Start the node from `scripts/demo` (`docker compose up -d`), then run
+//! ```bash
+//! cargo test --features regtest --test demo_e2e
+//! ```
+//! The node's miner (one block every ~2s) confirms the transactions.
+#![cfg(feature = "regtest")]
+
+use bitcoin::consensus::encode::deserialize_hex;
+use bitcoin::{Amount, Transaction};
+use bitcoincore_rpc::{Auth, Client, RpcApi};
+use std::process::Command;
+
+const RPC_URL: &str = "http://127.0.0.1:18443";
+const RPC_USER: &str = "user";
+const RPC_PASSWORD: &str = "PaSsWoRd";
+const WALLET_NAME: &str = "alice";
+const WALLET_PASSPHRASE: &str = "alicePsWd";
+
+fn env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+fn read_tx(output_dir: &std::path::Path, name: &str) -> Transaction {
+    let hex = std::fs::read_to_string(output_dir.join(format!("{name}.tx")))
+        .unwrap_or_else(|e| panic!("missing {name}.tx: {e}"));
+    deserialize_hex(&hex).unwrap()
+}
+
+#[test]
+fn test_demo_e2e_regtest() {
+    let rpc_url = env_or("COLLIDERVM_RPC_URL", RPC_URL);
+    let rpc_user = env_or("COLLIDERVM_RPC_USER", RPC_USER);
+    let rpc_password = env_or("COLLIDERVM_RPC_PASSWORD", RPC_PASSWORD);
+    let wallet_name = env_or("COLLIDERVM_WALLET", WALLET_NAME);
+
+    let rpc = Client::new(
+        &format!("{rpc_url}/wallet/{wallet_name}"),
+        Auth::UserPass(rpc_user.clone(), rpc_password.clone()),
+    )
+    .expect("regtest node must be running, see scripts/demo/README.md");
+    rpc.call::<serde_json::Value>(
+        "walletpassphrase",
+        &[
+            env_or("COLLIDERVM_WALLET_PASSPHRASE", WALLET_PASSPHRASE).into(),
+            600.into(),
+        ],
+    )
+    .expect("failed to unlock the wallet");
+
+    let receiver = rpc.get_new_address(None, None).unwrap().assume_checked();
+    let output_dir = std::env::temp_dir()
+        .join(format!("collidervm_demo_e2e_{}", std::process::id()));
+
+    let status = Command::new(env!("CARGO_BIN_EXE_demo"))
+        .args([
+            "--network",
+            "regtest",
+            "--rpc-url",
+            &rpc_url,
+            "--rpc-user",
+            &rpc_user,
+            "--rpc-password",
+            &rpc_password,
+            "--wallet-name",
+            &wallet_name,
+            "--receiver",
+            &receiver.to_string(),
+            "--output-dir",
+            output_dir.to_str().unwrap(),
+        ])
+        .status()
+        .expect("failed to run the demo binary");
+    assert!(status.success(), "demo exited with {status}");
+
+    let f1_tx = read_tx(&output_dir, "f1");
+    let f2_tx = read_tx(&output_dir, "f2");
+    let spending_tx = read_tx(&output_dir, "spending");
+    let funding_txid = f1_tx.input[0].previous_output.txid;
+
+    for txid in [
+        funding_txid,
+        f1_tx.compute_txid(),
+        f2_tx.compute_txid(),
+        spending_tx.compute_txid(),
+    ] {
+        let info = rpc.get_raw_transaction_info(&txid, None).unwrap();
+        assert!(
+            info.confirmations.unwrap_or(0) >= 1,
+            "{txid} is not confirmed"
+        );
+    }
+
+    let received = rpc.get_received_by_address(&receiver, Some(1)).unwrap();
+    assert_eq!(received, spending_tx.output[0].value);
+    assert!(received > Amount::ZERO);
+
+    std::fs::remove_dir_all(&output_dir).unwrap();
+}
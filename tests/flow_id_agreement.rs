@@ -0,0 +1,108 @@
+//! The flow id computed off-chain (`calculate_flow_id`) must be exactly the
+//! prefix the F1 script checks on-chain (`build_prefix_equalverify` fed by
+//! `flow_id_to_prefix_bytes`).
+//!
+//! Every case builds and signs the real tx_f1/tx_f2 pair and runs the F1
+//! spend, signature check included.
+
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{Network, OutPoint, Txid};
+use bitvm::dry_run_taproot_input;
+use collidervm_toy::core::{
+    BBits, F1_THRESHOLD, F2_THRESHOLD, LBits, calculate_flow_id,
+    find_valid_nonce, flow_id_to_prefix_bytes, truncate_flow_id,
+};
+use collidervm_toy::musig2::{
+    aggregate_pubkeys, generate_keys_n_from_seed, simulate_musig2_seeded,
+};
+use collidervm_toy::transactions::{
+    create_f1_tx, create_f2_tx, finalize_lock_tx,
+};
+use collidervm_toy::utils::pubkey_inner_from;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Inputs drawn per `B`
+const INPUTS_PER_B: usize = 3;
+/// Nonces tried per input, starting at the ground one
+const NONCES_PER_INPUT: u64 = 3;
+
+#[test]
+fn test_flow_id_onchain_offchain_agreement() {
+    let secp = Secp256k1::new();
+    let network = Network::Regtest;
+    let keys = generate_keys_n_from_seed(2, 7);
+    let pubkeys = keys.iter().map(|key| key.1).collect::<Vec<_>>();
+    let (_, agg_pubkey) = aggregate_pubkeys(&pubkeys).unwrap();
+    let pk_signer = pubkey_inner_from(agg_pubkey);
+    let funding_outpoint = OutPoint {
+        txid: Txid::all_zeros(),
+        vout: 0,
+    };
+    let mut rng = StdRng::seed_from_u64(268);
+
+    for b_bits in [8, 16, 24, 32] {
+        // At most 2^6 expected hashes per grind
+        let l_bits = b_bits - 6;
+        for _ in 0..INPUTS_PER_B {
+            let x = rng.gen_range(F1_THRESHOLD + 1..=i32::MAX as u32);
+            let (nonce, flow_id) =
+                find_valid_nonce(x, BBits(b_bits), LBits(l_bits)).unwrap();
+            let prefix = flow_id_to_prefix_bytes(flow_id, b_bits);
+
+            let (tx_f1, f1_lock, f1_spend_info, ..) = create_f1_tx(
+                b_bits,
+                &secp,
+                &pk_signer,
+                &network,
+                &funding_outpoint,
+                &100_000,
+                &prefix,
+                F1_THRESHOLD,
+                &1,
+                None,
+                false,
+            )
+            .unwrap();
+            let (tx_f2, _, _, message) = create_f2_tx(
+                b_bits,
+                &secp,
+                &pk_signer,
+                &network,
+                &tx_f1,
+                &tx_f1.output[0].value.to_sat(),
+                &f1_lock,
+                &prefix,
+                F2_THRESHOLD,
+                &1,
+                false,
+            )
+            .unwrap();
+            let sig = simulate_musig2_seeded(&keys, &message, 0).unwrap();
+
+            for nonce in nonce..nonce + NONCES_PER_INPUT {
+                let offchain =
+                    truncate_flow_id(calculate_flow_id(x, nonce), b_bits);
+                let mut spend = tx_f2.clone();
+                finalize_lock_tx(
+                    &mut spend,
+                    sig,
+                    &f1_spend_info,
+                    &f1_lock,
+                    &x,
+                    &nonce,
+                    &tx_f1.output[0],
+                )
+                .unwrap();
+                let onchain =
+                    dry_run_taproot_input(&spend, 0, &tx_f1.output).success;
+                assert_eq!(
+                    onchain,
+                    offchain == flow_id,
+                    "B={b_bits} x={x} nonce={nonce}: off-chain flow {offchain}, script locked to {flow_id}"
+                );
+            }
+        }
+    }
+}
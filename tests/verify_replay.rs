@@ -0,0 +1,77 @@
+//! A dry run of the demo must replay cleanly through `verify --replay`.
+//!
+//! The dry run still funds the flow from the node's wallet, so start the
+//! node from `scripts/demo` (`docker compose up -d`), then run
+//! ```bash
+//! cargo test --features regtest --test verify_replay
+//! ```
+#![cfg(feature = "regtest")]
+
+use bitcoincore_rpc::{Auth, Client, RpcApi};
+use std::process::Command;
+
+const RPC_URL: &str = "http://127.0.0.1:18443";
+const RPC_USER: &str = "user";
+const RPC_PASSWORD: &str = "PaSsWoRd";
+const WALLET_NAME: &str = "alice";
+const WALLET_PASSPHRASE: &str = "alicePsWd";
+
+fn env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+#[test]
+fn test_dry_run_replays_green() {
+    let rpc_url = env_or("COLLIDERVM_RPC_URL", RPC_URL);
+    let rpc_user = env_or("COLLIDERVM_RPC_USER", RPC_USER);
+    let rpc_password = env_or("COLLIDERVM_RPC_PASSWORD", RPC_PASSWORD);
+    let wallet_name = env_or("COLLIDERVM_WALLET", WALLET_NAME);
+
+    let rpc = Client::new(
+        &format!("{rpc_url}/wallet/{wallet_name}"),
+        Auth::UserPass(rpc_user.clone(), rpc_password.clone()),
+    )
+    .expect("regtest node must be running, see scripts/demo/README.md");
+    rpc.call::<serde_json::Value>(
+        "walletpassphrase",
+        &[
+            env_or("COLLIDERVM_WALLET_PASSPHRASE", WALLET_PASSPHRASE).into(),
+            600.into(),
+        ],
+    )
+    .expect("failed to unlock the wallet");
+
+    let output_dir = std::env::temp_dir()
+        .join(format!("collidervm_verify_replay_{}", std::process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_demo"))
+        .args([
+            "--dry-run",
+            "--network",
+            "regtest",
+            "--rpc-url",
+            &rpc_url,
+            "--rpc-user",
+            &rpc_user,
+            "--rpc-password",
+            &rpc_password,
+            "--wallet-name",
+            &wallet_name,
+            "--output-dir",
+            output_dir.to_str().unwrap(),
+        ])
+        .status()
+        .expect("failed to run the demo binary");
+    assert!(status.success(), "demo exited with {status}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_verify"))
+        .arg("--replay")
+        .arg(output_dir.join("demo.json"))
+        .output()
+        .expect("failed to run the verify binary");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "verify failed:\n{stdout}");
+    assert!(!stdout.contains('❌'), "{stdout}");
+    assert_eq!(stdout.matches('✅').count(), 5, "{stdout}");
+
+    std::fs::remove_dir_all(&output_dir).unwrap();
+}